@@ -0,0 +1,108 @@
+use std::collections::HashSet;
+
+use anyhow::{
+    bail,
+    Result
+};
+use futures::stream;
+use mongodb::{
+    bson::Document,
+    Database
+};
+
+use crate::{
+    client::Execution,
+    constants::DOG_INFO_COLLECTION,
+    features::FeatureContext,
+    logit,
+    market,
+    models::{
+        BaselineResults,
+        PredictorBackend,
+        RaceCard,
+        TestParams,
+        TestResults,
+        TokenUsage
+    },
+    progress::Progress,
+    speed,
+    utils::settle,
+    MongoDogInfoRepo
+};
+
+/// Predicts the races locally with a non-LLM backend. Races the backend
+/// cannot rate are left out, like races a model does not answer.
+pub async fn execute(backend: PredictorBackend, races: &[Document], database: &Database) -> Result<Execution> {
+    let predictions: Vec<_> = match backend {
+        PredictorBackend::Llm => bail!("The LLM backend is not run locally"),
+        PredictorBackend::SpeedRating => {
+            let context = FeatureContext::load(database, races).await;
+            races.iter().filter_map(|race| speed::predict(race, &context)).collect()
+        }
+        PredictorBackend::Market => {
+            let repo = MongoDogInfoRepo::new(database.collection(DOG_INFO_COLLECTION));
+            let mut predictions = Vec::with_capacity(races.len());
+            for race in races {
+                predictions.extend(market::predict(race, &repo).await?);
+            }
+            predictions
+        }
+        PredictorBackend::Logit => {
+            let model = logit::latest(database).await?;
+            logit::check(&model)?;
+            let context = FeatureContext::load(database, races).await;
+            races.iter().filter_map(|race| logit::predict(&model, race, &context)).collect()
+        }
+    };
+    log::info!(
+        "{}: {} of {} races predicted",
+        backend.as_str(),
+        predictions.len(),
+        races.len()
+    );
+
+    Ok(Execution {
+        predictions,
+        usage: TokenUsage::default(),
+        failed: Vec::new()
+    })
+}
+
+/// Adds how the market's own ranking did on the races the backtest
+/// answered, with the same staking. `cards` may hold more races, they
+/// are left out. A failure only loses the baseline.
+pub async fn with_market(
+    results: TestResults,
+    cards: Vec<RaceCard>,
+    database: &Database,
+    params: &TestParams
+) -> TestResults {
+    match market_for(cards, &results, database, params).await {
+        Ok(market) => results.with_baseline(market),
+        Err(err) => {
+            log::warn!("Market baseline failed: {err}");
+            results
+        }
+    }
+}
+
+async fn market_for(
+    cards: Vec<RaceCard>,
+    results: &TestResults,
+    database: &Database,
+    params: &TestParams
+) -> Result<BaselineResults> {
+    let answered: HashSet<u64> = results.race_ids().collect();
+    let repo = MongoDogInfoRepo::new(database.collection(DOG_INFO_COLLECTION));
+
+    let mut predictions = Vec::with_capacity(answered.len());
+    for card in cards.into_iter().filter(|c| answered.contains(&c.race_id)) {
+        predictions.extend(market::predict_card(card, &repo).await?);
+    }
+
+    // Market prices carry no confidence, so the threshold would skip every race.
+    let params = TestParams { min_confidence: None, ..*params };
+    let (meta, _) = settle(stream::iter(predictions), answered.len(), database, &params, &Progress::none()).await?;
+
+    Ok(BaselineResults { backend: PredictorBackend::Market, meta })
+}
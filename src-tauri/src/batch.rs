@@ -0,0 +1,707 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf
+};
+
+use anyhow::{
+    anyhow,
+    bail,
+    Context,
+    Result
+};
+use async_openai::types::{
+    BatchCompletionWindow,
+    BatchEndpoint,
+    BatchRequest,
+    BatchRequestInput,
+    BatchRequestInputMethod,
+    BatchRequestOutput,
+    BatchStatus,
+    CreateChatCompletionResponse,
+    CreateFileRequestArgs,
+    FilePurpose
+};
+use futures::TryStreamExt;
+use mongodb::{
+    bson::{
+        doc,
+        to_document,
+        DateTime
+    },
+    Collection,
+    Database
+};
+
+use crate::{
+    baseline,
+    client::{
+        share_usage,
+        Completion,
+        Execution,
+        OpenAIClient
+    },
+    constants::{
+        BATCH_FILES_DIR,
+        BATCH_JOBS_COLLECTION,
+        BATCH_PRICE_FACTOR,
+        BATCH_REQUESTS_COLLECTION
+    },
+    models::{
+        BatchJob,
+        BatchRequestDoc,
+        RequestsInfo,
+        TestParams,
+        TestResults,
+        TokenUsage
+    },
+    progress::Progress,
+    utils::{
+        backtest,
+        save_test_run
+    },
+    validation::{
+        feedback_request,
+        request_cards,
+        Rejection
+    }
+};
+
+/// Runs backtest requests through the asynchronous Batch API:
+/// requests are written to a JSONL file, uploaded and polled until the
+/// output file can be downloaded.
+pub struct BatchClient {
+    client: OpenAIClient,
+    jobs: Collection<BatchJob>,
+    requests: Collection<BatchRequestDoc>,
+    files_dir: PathBuf,
+}
+
+impl BatchClient {
+    pub fn new(client: OpenAIClient, database: &Database) -> Self {
+        Self {
+            client,
+            jobs: database.collection(BATCH_JOBS_COLLECTION),
+            requests: database.collection(BATCH_REQUESTS_COLLECTION),
+            files_dir: PathBuf::from(BATCH_FILES_DIR)
+        }
+    }
+
+    /// Directory the input files are written to, relative to the working
+    /// directory unless set.
+    pub fn with_files_dir(mut self, dir: PathBuf) -> Self {
+        self.files_dir = dir;
+        self
+    }
+
+    pub async fn submit(
+        &self,
+        requests_info: RequestsInfo,
+        params: TestParams
+    ) -> Result<BatchJob> {
+        let job = self.create(&requests_info, params).await?;
+
+        let requests: Vec<BatchRequestDoc> = requests_info
+            .requests
+            .into_iter()
+            .enumerate()
+            .map(|(index, request)| BatchRequestDoc {
+                batch_id: job.batch_id.clone(),
+                index,
+                request
+            })
+            .collect();
+        self.requests.insert_many(&requests).await?;
+        self.jobs.insert_one(&job).await?;
+
+        Ok(job)
+    }
+
+    /// Uploads the input file and creates the batch.
+    async fn create(&self, requests_info: &RequestsInfo, params: TestParams) -> Result<BatchJob> {
+        if requests_info.requests.is_empty() {
+            bail!("No data to send");
+        }
+
+        let path = self.write_batch_file(&requests_info.requests).await?;
+
+        let file_request = CreateFileRequestArgs::default()
+            .file(path.as_path())
+            .purpose(FilePurpose::Batch)
+            .build()
+            .context("Failed to build CreateFileRequest")?;
+        let file = self.client
+            .inner()
+            .files()
+            .create(file_request)
+            .await
+            .map_err(|err| anyhow!("{err}"))?;
+
+        let batch = self.client
+            .inner()
+            .batches()
+            .create(BatchRequest {
+                input_file_id: file.id.clone(),
+                endpoint: BatchEndpoint::V1ChatCompletions,
+                completion_window: BatchCompletionWindow::W24H,
+                metadata: None,
+            })
+            .await
+            .map_err(|err| anyhow!("{err}"))?;
+
+        log::info!("Submitted batch {} ({})", batch.id, path.display());
+
+        Ok(BatchJob {
+            batch_id: batch.id,
+            input_file_id: file.id,
+            output_file_id: batch.output_file_id,
+            status: batch.status,
+            settings: self.client.config().clone(),
+            model: self.client.model().clone(),
+            params,
+            request_count: requests_info.requests.len(),
+            total_races: requests_info.total_races,
+            created_at: DateTime::now(),
+            finished: false,
+        })
+    }
+
+    /// Updates the job status from the API and, once the batch has
+    /// completed, settles the backtest. Results are returned only by the
+    /// poll that finishes the job.
+    pub async fn poll_test(
+        &self,
+        job: &mut BatchJob,
+        database: &Database
+    ) -> Result<Option<TestResults>> {
+        if job.finished {
+            return Ok(None);
+        }
+
+        self.refresh(job).await?;
+
+        let results = match job.status {
+            BatchStatus::Completed => {
+                let results = self.finish_test(job, database).await?;
+                job.finished = true;
+                Some(results)
+            }
+            BatchStatus::Failed | BatchStatus::Expired | BatchStatus::Cancelled => {
+                job.finished = true;
+                None
+            }
+            _ => None
+        };
+
+        self.save_job(job).await?;
+        if job.finished {
+            self.requests.delete_many(doc! { "batchId": &job.batch_id }).await?;
+        }
+
+        Ok(results)
+    }
+
+    /// Takes the status and output file of the batch from the API.
+    async fn refresh(&self, job: &mut BatchJob) -> Result<()> {
+        let batch = self.client
+            .inner()
+            .batches()
+            .retrieve(&job.batch_id)
+            .await
+            .map_err(|err| anyhow!("{err}"))?;
+
+        job.status = batch.status;
+        job.output_file_id = batch.output_file_id;
+
+        Ok(())
+    }
+
+    pub async fn cancel(&self, job: &mut BatchJob) -> Result<()> {
+        self.request_cancel(job).await?;
+        self.save_job(job).await
+    }
+
+    async fn request_cancel(&self, job: &mut BatchJob) -> Result<()> {
+        let batch = self.client
+            .inner()
+            .batches()
+            .cancel(&job.batch_id)
+            .await
+            .map_err(|err| anyhow!("{err}"))?;
+
+        job.status = batch.status;
+
+        Ok(())
+    }
+
+    async fn finish_test(&self, job: &BatchJob, database: &Database) -> Result<TestResults> {
+        let requests = self.load_requests(job).await?;
+        let execution = self.collect(job, &requests).await?;
+        let cards = requests.iter().flat_map(request_cards).collect();
+        let requests_info = RequestsInfo {
+            requests,
+            total_races: job.total_races
+        };
+
+        let results = backtest(execution, requests_info, database, &job.params, &Progress::none()).await?;
+        let results = baseline::with_market(results, cards, database, &job.params).await;
+        save_test_run(database, &job.model.id, &results).await?;
+
+        Ok(results)
+    }
+
+    /// Downloads the output file and validates every response the same
+    /// way synchronous requests are. Requests that failed in the batch are
+    /// re-sent synchronously so the backtest covers every race.
+    async fn collect(
+        &self,
+        job: &BatchJob,
+        requests: &[HashMap<String, serde_json::Value>]
+    ) -> Result<Execution> {
+        let output_file_id = job
+            .output_file_id
+            .as_deref()
+            .ok_or_else(|| anyhow!("Batch {} has no output file", job.batch_id))?;
+
+        let content = self.client
+            .inner()
+            .files()
+            .content(output_file_id)
+            .await
+            .map_err(|err| anyhow!("{err}"))?;
+        let content = String::from_utf8(content.to_vec())
+            .context("Batch output is not valid UTF-8")?;
+
+        let mut outputs: HashMap<String, CreateChatCompletionResponse> = HashMap::new();
+        for line in content.lines().filter(|l| !l.trim().is_empty()) {
+            let output: BatchRequestOutput = match serde_json::from_str(line) {
+                Ok(o) => o,
+                Err(err) => {
+                    log::error!("Failed to parse batch output line: {err}");
+                    continue;
+                }
+            };
+
+            match output.response {
+                Some(resp) if resp.status_code == 200 => {
+                    match serde_json::from_value(resp.body) {
+                        Ok(r) => {
+                            outputs.insert(output.custom_id, r);
+                        }
+                        Err(err) => log::error!("{}: {err}", output.custom_id),
+                    }
+                }
+                Some(resp) => log::error!("{}: status {}", output.custom_id, resp.status_code),
+                None => log::error!("{}: {:?}", output.custom_id, output.error),
+            }
+        }
+
+        let mut predictions = Vec::with_capacity(requests.len());
+        let mut total_usage = TokenUsage::default();
+        let mut failed = Vec::new();
+
+        for (idx, request) in requests.iter().enumerate() {
+            let Some(response) = outputs.remove(&custom_id(idx)) else {
+                failed.push((idx, request.clone()));
+                continue;
+            };
+
+            let cache_key = self.client.cache_key(&self.client.build_request(request)?)?;
+            let completion = Completion { response, cache_key, cached: false };
+
+            let usage = self.client
+                .completion_usage(&completion)
+                .map(|u| u.scaled(BATCH_PRICE_FACTOR));
+            if let Some(usage) = &usage {
+                total_usage.add(usage);
+            }
+
+            match self.client.accept(&completion, request).await {
+                Ok(mut p) => {
+                    share_usage(&mut p, usage);
+                    predictions.extend(p);
+                }
+                Err(Rejection::Invalid { content, errors }) => {
+                    failed.push((idx, feedback_request(request, &content, &errors)));
+                }
+                Err(Rejection::Malformed) => failed.push((idx, request.clone())),
+            }
+        }
+
+        let mut failed_requests = Vec::new();
+        if !failed.is_empty() {
+            log::warn!("Re-sending {} failed batch requests", failed.len());
+            let (indices, retry_requests): (Vec<usize>, Vec<_>) = failed.into_iter().unzip();
+            let retried = self.client.execute_requests(retry_requests).await;
+
+            predictions.extend(retried.predictions);
+            total_usage.add(&retried.usage);
+            failed_requests = retried
+                .failed
+                .into_iter()
+                .map(|mut f| {
+                    f.index = indices[f.index];
+                    f
+                })
+                .collect();
+        }
+
+        predictions.sort_by_key(|p| (p.meta.date, p.meta.time));
+
+        Ok(Execution {
+            predictions,
+            usage: total_usage,
+            failed: failed_requests
+        })
+    }
+
+    /// Requests of the job in input file order.
+    async fn load_requests(&self, job: &BatchJob) -> Result<Vec<HashMap<String, serde_json::Value>>> {
+        let docs: Vec<BatchRequestDoc> = self.requests
+            .find(doc! { "batchId": &job.batch_id })
+            .sort(doc! { "index": 1 })
+            .await?
+            .try_collect()
+            .await?;
+        if docs.is_empty() || docs.len() != job.request_count {
+            bail!(
+                "Batch {} has {} of {} requests stored",
+                job.batch_id,
+                docs.len(),
+                job.request_count
+            );
+        }
+
+        Ok(docs.into_iter().map(|d| d.request).collect())
+    }
+
+    async fn write_batch_file(&self, requests: &[HashMap<String, serde_json::Value>]) -> Result<PathBuf> {
+        let mut lines = Vec::with_capacity(requests.len());
+        for (idx, request) in requests.iter().enumerate() {
+            let body = serde_json::to_value(self.client.build_request(request)?)?;
+            let input = BatchRequestInput {
+                custom_id: custom_id(idx),
+                method: BatchRequestInputMethod::POST,
+                url: BatchEndpoint::V1ChatCompletions,
+                body: Some(body),
+            };
+            lines.push(serde_json::to_string(&input)?);
+        }
+
+        tokio::fs::create_dir_all(&self.files_dir).await?;
+        let path = self.files_dir
+            .join(format!("batch-{}.jsonl", chrono::Utc::now().format("%Y%m%d-%H%M%S%.3f")));
+        tokio::fs::write(&path, lines.join("\n"))
+            .await
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+
+        Ok(path)
+    }
+
+    async fn save_job(&self, job: &BatchJob) -> Result<()> {
+        self.jobs
+            .update_one(
+                doc! { "batchId": &job.batch_id },
+                doc! { "$set": to_document(job)? }
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+fn custom_id(idx: usize) -> String {
+    format!("request-{idx}")
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{
+        json,
+        Value
+    };
+    use wiremock::{
+        matchers::{
+            method,
+            path
+        },
+        Mock,
+        MockServer,
+        ResponseTemplate
+    };
+
+    use super::*;
+    use crate::models::ModelInfo;
+
+    fn request(prompt: &str) -> HashMap<String, Value> {
+        HashMap::from([(
+            "messages".to_string(),
+            json!([{ "role": "user", "content": prompt }])
+        )])
+    }
+
+    fn requests_info() -> RequestsInfo {
+        RequestsInfo {
+            requests: vec![request("first"), request("second")],
+            total_races: 2
+        }
+    }
+
+    fn params() -> TestParams {
+        serde_json::from_value(json!({
+            "initialBalance": 100.0,
+            "initialStake": 10.0,
+            "oddsRange": { "low": 1.5, "high": 10.0 },
+            "isFavoriteProtected": false
+        }))
+        .unwrap()
+    }
+
+    fn job(status: &str, output_file_id: Option<&str>) -> BatchJob {
+        serde_json::from_value(json!({
+            "batchId": "batch_1",
+            "inputFileId": "file-in",
+            "outputFileId": output_file_id,
+            "status": status,
+            "settings": settings(),
+            "model": ModelInfo::reasoning("o4-mini", 200_000, 1.1, 4.4),
+            "params": params(),
+            "requestCount": 2,
+            "totalRaces": 2,
+            "createdAt": DateTime::now(),
+            "finished": false
+        }))
+        .unwrap()
+    }
+
+    fn settings() -> Value {
+        json!({
+            "model": "o4-mini",
+            "instruction_name": "default",
+            "max_completion_tokens": null,
+            "frequency_penalty": null,
+            "logprobs": null,
+            "presence_penalty": null,
+            "reasoning_effort": null,
+            "seed": null,
+            "store": null,
+            "temperature": null,
+            "max_races": 10,
+            "races_per_request": 1,
+            "selected": true,
+            "max_retries": 0
+        })
+    }
+
+    fn batch(status: &str, output_file_id: Option<&str>) -> Value {
+        json!({
+            "id": "batch_1",
+            "object": "batch",
+            "endpoint": "/v1/chat/completions",
+            "input_file_id": "file-in",
+            "completion_window": "24h",
+            "status": status,
+            "output_file_id": output_file_id,
+            "created_at": 1_750_000_000
+        })
+    }
+
+    /// Chat completion answering one race, `track` tells the answers apart.
+    fn completion(track: &str) -> Value {
+        let race = json!({
+            "meta": { "date": "2025-06-02", "time": "14:36:00", "distance": 480, "track": track, "grade": "A3" },
+            "predictions": [
+                { "name": "Swift Blaze", "rawScore": 60.0, "percentage": 60.0, "rank": 1, "comment": null },
+                { "name": "Droopys Ace", "rawScore": 40.0, "percentage": 40.0, "rank": 2, "comment": null }
+            ],
+            "summary": null
+        });
+        json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "created": 1_750_000_000,
+            "model": "o4-mini",
+            "choices": [{
+                "index": 0,
+                "message": { "role": "assistant", "content": race.to_string() },
+                "finish_reason": "stop"
+            }],
+            "usage": { "prompt_tokens": 1000, "completion_tokens": 200, "total_tokens": 1200 }
+        })
+    }
+
+    async fn batch_client(server: &MockServer, files_dir: PathBuf) -> BatchClient {
+        let settings = serde_json::from_value(settings()).unwrap();
+        let model = ModelInfo::reasoning("o4-mini", 200_000, 1.1, 4.4);
+        let client = OpenAIClient::new(settings, model).with_api_base(&server.uri());
+        // Never connected to: the tests only call the API side.
+        let database = mongodb::Client::with_uri_str("mongodb://127.0.0.1:1")
+            .await
+            .unwrap()
+            .database("test");
+
+        BatchClient::new(client, &database).with_files_dir(files_dir)
+    }
+
+    #[tokio::test]
+    async fn create_uploads_every_request() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/files"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "id": "file-in",
+                "object": "file",
+                "bytes": 100,
+                "created_at": 1_750_000_000,
+                "filename": "batch.jsonl",
+                "purpose": "batch"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/batches"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(batch("validating", None)))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let job = batch_client(&server, dir.path().join("batches"))
+            .await
+            .create(&requests_info(), params())
+            .await
+            .unwrap();
+
+        assert_eq!(job.batch_id, "batch_1");
+        assert_eq!(job.input_file_id, "file-in");
+        assert_eq!(job.status, BatchStatus::Validating);
+        assert_eq!(job.request_count, 2);
+        assert!(!job.finished);
+
+        let files: Vec<_> = std::fs::read_dir(dir.path().join("batches")).unwrap().collect();
+        assert_eq!(files.len(), 1);
+        let lines: Vec<BatchRequestInput> = std::fs::read_to_string(files[0].as_ref().unwrap().path())
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        let ids: Vec<_> = lines.iter().map(|l| l.custom_id.as_str()).collect();
+        assert_eq!(ids, ["request-0", "request-1"]);
+    }
+
+    #[tokio::test]
+    async fn create_rejects_empty_batches() {
+        let server = MockServer::start().await;
+        let dir = tempfile::tempdir().unwrap();
+        let empty = RequestsInfo { requests: Vec::new(), total_races: 0 };
+
+        let result = batch_client(&server, dir.path().to_path_buf())
+            .await
+            .create(&empty, params())
+            .await;
+
+        assert!(result.is_err());
+        assert!(server.received_requests().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn refresh_takes_status_and_output_file() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/batches/batch_1"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(batch("completed", Some("file-out"))))
+            .mount(&server)
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut job = job("in_progress", None);
+        batch_client(&server, dir.path().to_path_buf())
+            .await
+            .refresh(&mut job)
+            .await
+            .unwrap();
+
+        assert_eq!(job.status, BatchStatus::Completed);
+        assert_eq!(job.output_file_id.as_deref(), Some("file-out"));
+    }
+
+    #[tokio::test]
+    async fn collect_validates_outputs_and_resends_failures() {
+        let server = MockServer::start().await;
+        let output = json!({
+            "id": "batch_req_1",
+            "custom_id": "request-0",
+            "response": { "status_code": 200, "request_id": "req_1", "body": completion("Batch") },
+            "error": null
+        });
+        let failed = json!({
+            "id": "batch_req_2",
+            "custom_id": "request-1",
+            "response": null,
+            "error": { "code": "server_error", "message": "failed" }
+        });
+        Mock::given(method("GET"))
+            .and(path("/files/file-out/content"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(format!("{output}\n{failed}\n")))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(completion("Resent")))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let execution = batch_client(&server, dir.path().to_path_buf())
+            .await
+            .collect(&job("completed", Some("file-out")), &requests_info().requests)
+            .await
+            .unwrap();
+
+        let mut tracks: Vec<_> = execution.predictions.iter().map(|p| p.meta.track.as_str()).collect();
+        tracks.sort();
+        assert_eq!(tracks, ["Batch", "Resent"]);
+        assert!(execution.failed.is_empty());
+        assert_eq!(execution.usage.prompt_tokens, 2000);
+
+        // The batch answer is billed at the batch price, the resent one in full.
+        let full = 1000.0 * 1.1 / 1e6 + 200.0 * 4.4 / 1e6;
+        assert!((execution.usage.cost - full * (1.0 + BATCH_PRICE_FACTOR)).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn collect_needs_an_output_file() {
+        let server = MockServer::start().await;
+        let dir = tempfile::tempdir().unwrap();
+
+        let result = batch_client(&server, dir.path().to_path_buf())
+            .await
+            .collect(&job("in_progress", None), &requests_info().requests)
+            .await;
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn request_cancel_takes_status() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/batches/batch_1/cancel"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(batch("cancelling", None)))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut job = job("in_progress", None);
+        batch_client(&server, dir.path().to_path_buf())
+            .await
+            .request_cancel(&mut job)
+            .await
+            .unwrap();
+
+        assert_eq!(job.status, BatchStatus::Cancelling);
+    }
+}
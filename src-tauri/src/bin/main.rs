@@ -20,11 +20,18 @@ async fn main() -> Result<()> {
     let mut opts = ClientOptions::parse(&conn_str).await?;
     opts.server_api = Some(ServerApi::builder().version(ServerApiVersion::V1).build());
     let client = Client::with_options(opts)?;
+    if let Some(database) = client.default_database() {
+        // Before the app can save instructions, so versions stay unique.
+        if let Err(err) = dogs_lib::instructions::migrate(&database).await {
+            eprintln!("Instructions not migrated: {err}");
+        }
+    }
 
     tauri::Builder::default()
         .plugin(tauri_plugin_store::Builder::default().build())
         .setup(move |app| {
             app.manage(client);
+            app.manage(dogs_lib::progress::Jobs::default());
             Ok(())
         })
         .plugin(
@@ -47,14 +54,44 @@ async fn main() -> Result<()> {
         .plugin(tauri_plugin_clipboard_manager::init())
         .invoke_handler(tauri::generate_handler![
             dogs_lib::commands::run_predict,
+            dogs_lib::commands::estimate_predict_prompt,
+            dogs_lib::commands::estimate_test_prompt,
+            dogs_lib::commands::cancel_job,
             dogs_lib::commands::add_instruction,
             dogs_lib::commands::read_instruction_names,
+            dogs_lib::commands::load_instruction_history,
+            dogs_lib::commands::load_instruction,
+            dogs_lib::commands::update_instruction,
+            dogs_lib::commands::rename_instruction,
+            dogs_lib::commands::delete_instruction,
+            dogs_lib::commands::diff_instructions,
+            dogs_lib::commands::export_instructions,
+            dogs_lib::commands::import_instructions,
             dogs_lib::commands::load_settings,
             dogs_lib::commands::save_settings,
             dogs_lib::commands::load_time_ranges,
             dogs_lib::commands::load_predictions,
             dogs_lib::commands::run_test,
-            dogs_lib::commands::copy_predict_request
+            dogs_lib::commands::train_logit_model,
+            dogs_lib::commands::validate_logit_model,
+            dogs_lib::commands::compute_trap_bias,
+            dogs_lib::commands::load_trap_bias,
+            dogs_lib::commands::compute_standard_times,
+            dogs_lib::commands::load_standard_times,
+            dogs_lib::commands::load_speed_figures,
+            dogs_lib::commands::update_ratings,
+            dogs_lib::commands::load_dog_rating,
+            dogs_lib::commands::load_rating_history,
+            dogs_lib::commands::copy_predict_request,
+            dogs_lib::commands::load_models,
+            dogs_lib::commands::save_model,
+            dogs_lib::commands::delete_model,
+            dogs_lib::commands::load_daily_usage,
+            dogs_lib::commands::clear_response_cache,
+            dogs_lib::commands::submit_test_batch,
+            dogs_lib::commands::load_batch_jobs,
+            dogs_lib::commands::poll_batch_job,
+            dogs_lib::commands::cancel_batch_job
         ])
         .run(tauri::generate_context!())?;
 
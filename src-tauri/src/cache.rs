@@ -0,0 +1,122 @@
+use anyhow::{
+    Context,
+    Result
+};
+use async_openai::types::{
+    CreateChatCompletionRequest,
+    CreateChatCompletionResponse
+};
+use mongodb::{
+    bson::{
+        doc,
+        DateTime,
+        Document
+    },
+    Collection,
+    Database
+};
+use sha2::{
+    Digest,
+    Sha256
+};
+
+use crate::{
+    constants::LLM_CACHE_COLLECTION,
+    models::CacheMode
+};
+
+/// Returned in cache-only mode when a request has no stored response.
+#[derive(Debug)]
+pub struct CacheMiss(pub String);
+
+impl std::fmt::Display for CacheMiss {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "No cached response for request {}", self.0)
+    }
+}
+
+impl std::error::Error for CacheMiss {}
+
+/// Content-addressed store of chat completion responses.
+/// The key is a SHA-256 of the whole serialized request: model,
+/// parameters, response format, instruction and user content.
+#[derive(Clone)]
+pub struct ResponseCache {
+    col: Collection<Document>,
+    mode: CacheMode,
+}
+
+impl ResponseCache {
+    pub fn new(database: &Database, mode: CacheMode) -> Self {
+        Self {
+            col: database.collection(LLM_CACHE_COLLECTION),
+            mode
+        }
+    }
+
+    pub fn mode(&self) -> CacheMode {
+        self.mode
+    }
+
+    pub fn key(request: &CreateChatCompletionRequest) -> Result<String> {
+        let canonical = serde_json::to_string(request)
+            .context("Failed to serialize request for cache key")?;
+        let digest = Sha256::digest(canonical.as_bytes());
+
+        Ok(format!("{:x}", digest))
+    }
+
+    /// Looks the response up unless the mode bypasses reads.
+    pub async fn get(&self, key: &str) -> Result<Option<CreateChatCompletionResponse>> {
+        if !matches!(self.mode, CacheMode::ReadThrough | CacheMode::CacheOnly) {
+            return Ok(None);
+        }
+
+        let found = self.col
+            .find_one(doc! { "key": key })
+            .await?;
+
+        let response = match found {
+            Some(d) => {
+                let raw = d.get_str("response")?;
+                Some(serde_json::from_str(raw).context("Failed to parse cached response")?)
+            }
+            None => None
+        };
+
+        Ok(response)
+    }
+
+    /// Stores the response unless the mode never writes.
+    pub async fn put(
+        &self,
+        key: &str,
+        model: &str,
+        response: &CreateChatCompletionResponse
+    ) -> Result<()> {
+        if matches!(self.mode, CacheMode::Disabled | CacheMode::CacheOnly) {
+            return Ok(());
+        }
+
+        let raw = serde_json::to_string(response)?;
+        self.col
+            .replace_one(
+                doc! { "key": key },
+                doc! {
+                    "key": key,
+                    "model": model,
+                    "response": raw,
+                    "createdAt": DateTime::now()
+                }
+            )
+            .upsert(true)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn clear(&self) -> Result<u64> {
+        let res = self.col.delete_many(doc! {}).await?;
+        Ok(res.deleted_count)
+    }
+}
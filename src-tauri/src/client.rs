@@ -1,6 +1,11 @@
 use std::{
     sync::Arc,
-    collections::HashMap
+    collections::{
+        BTreeMap, 
+        HashMap, 
+        VecDeque
+    },
+    time::Duration
 };
 use anyhow::{
     anyhow, 
@@ -8,15 +13,30 @@ use anyhow::{
     Result, 
     bail
 };
-use futures::stream::FuturesUnordered;
+use futures::future::{
+    self, 
+    Either
+};
+use futures::stream::{
+    self, 
+    FuturesUnordered, 
+    Stream
+};
 use futures::StreamExt;
+use tokio::sync::{
+    mpsc, 
+    Semaphore
+};
 use async_openai::{
-    config::OpenAIConfig,
+    config::{
+        Config, 
+        OpenAIConfig
+    },
     types::{
         ChatCompletionRequestMessage, 
+        CreateChatCompletionRequest, 
         CreateChatCompletionRequestArgs, 
         CreateChatCompletionResponse, 
-        ReasoningEffort, 
         ResponseFormat
     },
     Client
@@ -24,38 +44,173 @@ use async_openai::{
 use mongodb::Database;
 
 use crate::{
-    constants::DOG_INFO_COLLECTION, 
+    confidence, 
+    cache::{
+        CacheMiss, 
+        ResponseCache
+    }, 
     models::{
-        OddsRange, 
+        CacheMode, 
+        FailedRequest, 
+        InstructionRef, 
+        ModelInfo, 
+        MultiPredictResponse, 
         PredictResponse, 
+        PredictResults, 
         RequestsInfo, 
         Settings, 
-        TestResults
+        TestParams, 
+        TestResults, 
+        TokenUsage
     }, 
+    progress::{
+        Progress, 
+        ProgressEvent
+    }, 
+    retry::{
+        backoff_delay, 
+        retry_after, 
+        ApiStatusError, 
+        TokenBudget
+    }, 
+    schema, 
     utils::{
-        get_response_format_json_schema, 
-        process_test_results
+        estimate_tokens, 
+        settle
     }, 
-    MongoDogInfoRepo
+    validation::{
+        check_races, 
+        feedback_request, 
+        request_cards, 
+        request_race_count, 
+        Rejection
+    }
 };
 
+const DEFAULT_MAX_RETRIES: usize = 5;
+const DEFAULT_MAX_IN_FLIGHT: usize = 8;
+
 pub struct OpenAIClient {
     client: Arc<Client<OpenAIConfig>>,
-    config: Settings
+    http: reqwest::Client,
+    config: Settings,
+    model: ModelInfo,
+    cache: Option<ResponseCache>,
+    /// Requests in flight and tokens per minute, shared by every copy of
+    /// the client.
+    semaphore: Arc<Semaphore>,
+    budget: Option<Arc<TokenBudget>>,
+    sample: usize,
+    progress: Progress,
+    results: Option<ResultSender>
+}
+
+/// Receives the answers of every request as soon as it finishes, tagged
+/// with the request index. Failed requests send no races.
+pub type ResultSender = mpsc::UnboundedSender<(usize, Vec<PredictResponse>)>;
+
+pub struct Completion {
+    pub response: CreateChatCompletionResponse,
+    pub cache_key: String,
+    pub cached: bool
+}
+
+pub struct Execution {
+    pub predictions: Vec<PredictResponse>,
+    pub usage: TokenUsage,
+    pub failed: Vec<FailedRequest>
+}
+
+struct RequestOutcome {
+    prediction: std::result::Result<Vec<PredictResponse>, String>,
+    usage: TokenUsage,
+    attempts: usize
 }
 
 impl OpenAIClient {
-    pub fn new(config: Settings) -> Self {
-        let openai_cfg = OpenAIConfig::new();
+    pub fn new(config: Settings, model: ModelInfo) -> Self {
+        let mut openai_cfg = OpenAIConfig::new();
+        if let Ok(api_base) = std::env::var("OPENAI_API_BASE") {
+            openai_cfg = openai_cfg.with_api_base(api_base);
+        }
         let client = Arc::new(Client::with_config(openai_cfg));
+        let budget = config
+            .tokens_per_minute
+            .filter(|tpm| *tpm > 0)
+            .map(|tpm| Arc::new(TokenBudget::new(tpm)));
+        let max_in_flight = config.max_in_flight.unwrap_or(DEFAULT_MAX_IN_FLIGHT).max(1);
 
         Self {
             client,
-            config
+            http: reqwest::Client::new(),
+            config,
+            model,
+            cache: None,
+            semaphore: Arc::new(Semaphore::new(max_in_flight)),
+            budget,
+            sample: 0,
+            progress: Progress::none(),
+            results: None
         }
     }
 
-    pub async fn send(&self, data: HashMap<String, serde_json::Value>) -> Result<CreateChatCompletionResponse> {
+    /// Sends every request to `api_base` instead of the OpenAI API.
+    pub fn with_api_base(mut self, api_base: &str) -> Self {
+        self.client = Arc::new(Client::with_config(OpenAIConfig::new().with_api_base(api_base)));
+        self
+    }
+
+    pub fn with_cache(mut self, cache: ResponseCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    pub fn with_progress(mut self, progress: Progress) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Draws on the in-flight and token limits of `other`, so clients
+    /// sending side by side stay within one set of limits.
+    pub fn with_limits_of(mut self, other: &OpenAIClient) -> Self {
+        self.semaphore = Arc::clone(&other.semaphore);
+        self.budget = other.budget.clone();
+        self
+    }
+
+    pub fn progress(&self) -> &Progress {
+        &self.progress
+    }
+
+    /// A copy that also streams its answers. The channel closes when the
+    /// copy is dropped.
+    pub fn with_results(&self, results: ResultSender) -> Self {
+        let mut client = self.clone_inner();
+        client.results = Some(results);
+        client
+    }
+
+    /// A copy that draws an independent sample of the same requests:
+    /// the seed is offset and the cache keeps each sample apart.
+    pub fn with_sample(&self, sample: usize) -> Self {
+        let mut client = self.clone_inner();
+        client.sample = sample;
+        client
+    }
+
+    pub fn inner(&self) -> &Client<OpenAIConfig> {
+        &self.client
+    }
+
+    pub fn config(&self) -> &Settings {
+        &self.config
+    }
+
+    pub fn model(&self) -> &ModelInfo {
+        &self.model
+    }
+
+    pub fn build_request(&self, data: &HashMap<String, serde_json::Value>) -> Result<CreateChatCompletionRequest> {
         let messages_value = data
             .get("messages")
             .ok_or(anyhow!("Missing key 'messages'"))?;
@@ -63,126 +218,419 @@ impl OpenAIClient {
         let messages: Vec<ChatCompletionRequestMessage> = serde_json::from_value(messages_value.clone())
             .context("Failed to parse 'messages' value")?;
 
-        let request = CreateChatCompletionRequestArgs::default()
-            .model(self.config.model.to_string())
-            .frequency_penalty(self.config.frequency_penalty.unwrap_or_default())
-            .logprobs(self.config.logprobs.unwrap_or_default())
-            .presence_penalty(self.config.presence_penalty.unwrap_or_default())
-            .reasoning_effort(self.config.reasoning_effort.clone().unwrap_or(ReasoningEffort::Medium))
-            .temperature(self.config.temperature.unwrap_or(1.0))
-            // .max_completion_tokens(self.config.max_completion_tokens.unwrap_or(10000))
-            .response_format(ResponseFormat::JsonSchema { json_schema: get_response_format_json_schema() })
-            // .seed(self.config.seed.unwrap_or(0))
-            .messages(messages)
-            
+        let mut args = CreateChatCompletionRequestArgs::default();
+        args.model(self.model.id.as_str())
+            .response_format(ResponseFormat::JsonSchema { json_schema: schema::response_format(request_race_count(data), self.config.schema_locale) })
+            .messages(messages);
+
+        if let Some(max_completion_tokens) = self.config.max_completion_tokens {
+            args.max_completion_tokens(max_completion_tokens);
+        }
+        if let Some(store) = self.config.store {
+            args.store(store);
+        }
+        if self.model.supports_temperature {
+            if let Some(temperature) = self.config.temperature {
+                args.temperature(temperature);
+            }
+        }
+        if self.model.supports_penalties {
+            if let Some(frequency_penalty) = self.config.frequency_penalty {
+                args.frequency_penalty(frequency_penalty);
+            }
+            if let Some(presence_penalty) = self.config.presence_penalty {
+                args.presence_penalty(presence_penalty);
+            }
+        }
+        if self.model.supports_logprobs {
+            if let Some(logprobs) = self.config.logprobs {
+                args.logprobs(logprobs);
+            }
+        }
+        if self.model.supports_seed {
+            if let Some(seed) = self.config.seed {
+                args.seed(seed + self.sample as i64);
+            }
+        }
+        if self.model.supports_reasoning_effort {
+            if let Some(reasoning_effort) = self.config.reasoning_effort.clone() {
+                args.reasoning_effort(reasoning_effort);
+            }
+        }
+
+        args
             .build()
-            .context("Failed to build CreateChatCompletionRequestArgs")?;
+            .context("Failed to build CreateChatCompletionRequestArgs")
+    }
+
+    pub async fn send(&self, data: HashMap<String, serde_json::Value>) -> Result<Completion> {
+        let request = self.build_request(&data)?;
+
+        let cache_key = self.cache_key(&request)?;
+        if let Some(cache) = &self.cache {
+            if let Some(response) = cache.get(&cache_key).await? {
+                log::info!("Cache hit: {cache_key}");
+                return Ok(Completion { response, cache_key, cached: true });
+            }
+            if cache.mode() == CacheMode::CacheOnly {
+                return Err(CacheMiss(cache_key).into());
+            }
+        }
+
+        let estimated = match &self.budget {
+            Some(budget) => {
+                let estimated = estimate_tokens(&serde_json::to_string(&request.messages)?)
+                    + self.config.max_completion_tokens.unwrap_or_default() as usize;
+                budget.acquire(estimated).await;
+                estimated
+            }
+            None => 0
+        };
+
+        let response = self.post_chat(&request).await;
+
+        if let Some(budget) = &self.budget {
+            let actual = match &response {
+                Ok(r) => r.usage.as_ref().map(|u| u.total_tokens as usize).unwrap_or(estimated),
+                Err(_) => 0
+            };
+            budget.settle(estimated, actual).await;
+        }
+
+        Ok(Completion { response: response?, cache_key, cached: false })
+    }
+
+    pub fn cache_key(&self, request: &CreateChatCompletionRequest) -> Result<String> {
+        let key = ResponseCache::key(request)?;
+        Ok(match self.sample {
+            0 => key,
+            n => format!("{key}-{n}")
+        })
+    }
 
-        let response = self.client
-            .chat()
-            .create(request)
+    /// Posts the request directly so the response status and rate-limit
+    /// headers are visible to the retry logic.
+    async fn post_chat(&self, request: &CreateChatCompletionRequest) -> Result<CreateChatCompletionResponse> {
+        let config = self.client.config();
+        let resp = self.http
+            .post(config.url("/chat/completions"))
+            .headers(config.headers())
+            .json(request)
+            .send()
             .await
-            .map_err(|err| anyhow!("{err}"))?;
+            .context("Chat completion request failed")?;
+
+        let status = resp.status();
+        if !status.is_success() {
+            let retry_after = retry_after(resp.headers());
+            let message = resp.text().await.unwrap_or_default();
+            return Err(ApiStatusError {
+                status: status.as_u16(),
+                retry_after,
+                message
+            }.into());
+        }
 
-        Ok(response)
+        resp.json()
+            .await
+            .context("Failed to parse chat completion response")
     }
 
-    async fn execute_requests(
+    pub async fn execute_requests(
         &self,
-        mut requests: Vec<HashMap<String, serde_json::Value>>,
-    ) -> Vec<PredictResponse> {
-        const MAX_RETRIES: usize = 5;
+        requests: Vec<HashMap<String, serde_json::Value>>,
+    ) -> Execution {
+        let max_retries = self.config.max_retries.unwrap_or(DEFAULT_MAX_RETRIES);
         let client = Arc::new(self.clone_inner());
+        let semaphore = Arc::clone(&self.semaphore);
+
+        self.progress.emit(ProgressEvent::Requests { total: requests.len() });
+
+        let mut futs = FuturesUnordered::new();
+        let mut aborts = Vec::with_capacity(requests.len());
+        for (index, req) in requests.iter().cloned().enumerate() {
+            let c = Arc::clone(&client);
+            let sem = Arc::clone(&semaphore);
+            let handle = tokio::spawn(async move {
+                c.send_with_retry(req, index, &sem, max_retries).await
+            });
+            aborts.push(handle.abort_handle());
+            futs.push(async move { (index, handle.await) });
+        }
+
         let mut ok = Vec::with_capacity(requests.len());
+        let mut total_usage = TokenUsage::default();
+        let mut failed = Vec::new();
+        let mut done = vec![false; requests.len()];
+        let mut cancelled = Box::pin(self.progress.cancelled());
+        loop {
+            let (index, join_res) = match future::select(futs.next(), &mut cancelled).await {
+                Either::Left((Some(next), _)) => next,
+                Either::Left((None, _)) => break,
+                Either::Right(_) => {
+                    // Whatever is still in flight is dropped; finished
+                    // requests are returned as partial results.
+                    aborts.iter().for_each(|a| a.abort());
+                    for (index, _) in done.iter().enumerate().filter(|(_, d)| !**d) {
+                        failed.push(FailedRequest::new(index, &requests[index], 0, "Cancelled".to_string()));
+                    }
+                    break;
+                }
+            };
+            done[index] = true;
 
-        for _ in 0..MAX_RETRIES {
-            if requests.is_empty() {
-                break;
-            }
-
-            let mut futs = FuturesUnordered::new();
-            for req in requests.into_iter() {
-                let c = Arc::clone(&client);
-                futs.push(tokio::spawn(async move {
-                    let r = c.send(req.clone()).await;
-                    (req, r)
-                }));
-            }
-
-            let mut failed = Vec::new();
-            while let Some(join_res) = futs.next().await {
-                match join_res {
-                    Ok((orig_req, Ok(resp))) => {
-                        log::info!("{:#?}", resp);
-
-                        if self.response_ok(&resp) {
-                            if let Some(p) = self.parse_choice(&resp) {
-                                log::info!("Хороший ответ!");
-                                ok.push(p);
-                            } else {
-                                log::error!("Плохой ответ! Переотправка");
-                                failed.push(orig_req);
-                            }
-                        } else {
-                            log::error!("Плохой ответ! Переотправка");
-                            failed.push(orig_req);
+            let mut answered = Vec::new();
+            match join_res {
+                Ok(outcome) => {
+                    total_usage.add(&outcome.usage);
+                    match outcome.prediction {
+                        Ok(mut p) => {
+                            self.progress.emit(ProgressEvent::RequestSucceeded { index, races: p.len() });
+                            p.sort_by_key(|p| (p.meta.date, p.meta.time));
+                            p.iter_mut().for_each(PredictResponse::sort_predictions);
+                            answered = p;
+                        }
+                        Err(error) => {
+                            self.progress.emit(ProgressEvent::RequestFailed { index, error: error.clone() });
+                            failed.push(FailedRequest::new(index, &requests[index], outcome.attempts, error))
                         }
                     }
+                }
 
-                    Ok((orig_req, Err(err))) => {
-                        log::error!("{err}");
-                        failed.push(orig_req);
+                Err(join_err) => {
+                    log::error!("Task join error: {:?}", join_err);
+                    self.progress.emit(ProgressEvent::RequestFailed { index, error: join_err.to_string() });
+                    failed.push(FailedRequest::new(index, &requests[index], 0, join_err.to_string()));
+                }
+            }
+
+            if let Some(results) = &self.results {
+                // Nobody listening any more is not an error for the run.
+                let _ = results.send((index, answered.clone()));
+            }
+            ok.extend(answered);
+        }
+
+        ok.sort_by_key(|p| (p.meta.date, p.meta.time));
+        failed.sort_by_key(|f| f.index);
+
+        // println!("Response: {:?}", ok.clone());
+
+        log::info!(
+            "Usage: prompt {} / completion {} / reasoning {} tokens, cost ${:.4}; {} requests failed",
+            total_usage.prompt_tokens,
+            total_usage.completion_tokens,
+            total_usage.reasoning_tokens,
+            total_usage.cost,
+            failed.len()
+        );
+
+        Execution {
+            predictions: ok,
+            usage: total_usage,
+            failed
+        }
+    }
+
+    /// Sends one request until it yields a valid prediction. Waits for
+    /// `Retry-After` when the API provides it, otherwise backs off
+    /// exponentially with jitter. Non-retryable errors stop immediately.
+    /// A response that contradicts the race card is answered with the
+    /// validation errors and the model is asked again straight away.
+    async fn send_with_retry(
+        &self,
+        original: HashMap<String, serde_json::Value>,
+        index: usize,
+        semaphore: &Semaphore,
+        max_retries: usize
+    ) -> RequestOutcome {
+        let mut usage = TokenUsage::default();
+        let mut last_error = String::new();
+        let mut req = original.clone();
+
+        for attempt in 0..=max_retries {
+            let sent = {
+                let _permit = semaphore.acquire().await.expect("semaphore is never closed");
+                self.progress.emit(ProgressEvent::RequestSent { index, attempt: attempt + 1 });
+                self.send(req.clone()).await
+            };
+
+            let retry_after = match sent {
+                Ok(completion) => {
+                    // Cached responses were paid for by an earlier run.
+                    let completion_usage = self.completion_usage(&completion);
+                    if let Some(u) = &completion_usage {
+                        usage.add(u);
+                    }
+
+                    match self.accept(&completion, &req).await {
+                        Ok(mut p) => {
+                            if attempt > 0 {
+                                self.cache_for(&original, &completion).await;
+                            }
+                            share_usage(&mut p, completion_usage);
+                            return RequestOutcome { prediction: Ok(p), usage, attempts: attempt + 1 };
+                        }
+                        Err(rejection) => {
+                            last_error = rejection.to_string();
+                            match rejection {
+                                Rejection::Invalid { content, errors } => {
+                                    req = feedback_request(&original, &content, &errors);
+                                    Some(Duration::ZERO)
+                                }
+                                Rejection::Malformed => None
+                            }
+                        }
                     }
+                }
 
-                    Err(join_err) => {
-                        log::error!("Task join error: {:?}", join_err);
+                Err(err) => {
+                    log::error!("{err}");
+                    last_error = err.to_string();
+
+                    if err.is::<CacheMiss>() {
+                        return RequestOutcome { prediction: Err(last_error), usage, attempts: attempt + 1 };
+                    }
+                    match err.downcast_ref::<ApiStatusError>() {
+                        Some(status) if !status.is_retryable() => {
+                            return RequestOutcome { prediction: Err(last_error), usage, attempts: attempt + 1 };
+                        }
+                        Some(status) => status.retry_after,
+                        None => None,
                     }
                 }
+            };
+
+            if attempt < max_retries {
+                let delay = retry_after.unwrap_or_else(|| backoff_delay(attempt));
+                log::info!("Retrying in {:.1}s (attempt {}/{})", delay.as_secs_f64(), attempt + 1, max_retries);
+                tokio::time::sleep(delay).await;
             }
-            requests = failed;
         }
 
-        ok.sort_by_key(|p| (p.meta.date, p.meta.time));
+        RequestOutcome { prediction: Err(last_error), usage, attempts: max_retries + 1 }
+    }
 
-        // println!("Response: {:?}", ok.clone());
+    pub fn completion_usage(&self, completion: &Completion) -> Option<TokenUsage> {
+        if completion.cached {
+            return None;
+        }
 
-        ok
-    }
-
-    fn response_ok(&self, resp: &CreateChatCompletionResponse) -> bool {
-        resp.choices.iter().all(|c| {
-            c.message
-                .content
-                .as_deref()
-                .and_then(|s| serde_json::from_str::<PredictResponse>(s).ok())
-                .map(|p| {
-                    p.predictions
-                        .iter()
-                        .filter(|pred| pred.raw_score == 0.0)
-                        .nth(0)
-                        .is_none()
-                })
-                .unwrap_or(false)
-        })
+        completion.response
+            .usage
+            .as_ref()
+            .map(|u| TokenUsage::from_completion(u, &self.model))
+    }
+
+    /// Validates a completion against the races in `request`, repairing
+    /// what can be repaired, and stores good responses in the cache.
+    /// Yields one prediction per race sent.
+    pub async fn accept(
+        &self,
+        completion: &Completion,
+        request: &HashMap<String, serde_json::Value>
+    ) -> std::result::Result<Vec<PredictResponse>, Rejection> {
+        let resp = &completion.response;
+        log::info!("{:#?}", resp);
+
+        let Some((content, mut p)) = self.parse_choice(resp, request_race_count(request) > 1) else {
+            log::error!("Плохой ответ! Переотправка");
+            return Err(Rejection::Malformed);
+        };
+
+        let tokens = resp.choices
+            .iter()
+            .find(|c| c.message.content.as_deref() == Some(content.as_str()))
+            .and_then(|c| c.logprobs.as_ref())
+            .and_then(|l| l.content.as_deref());
+        if let Some(tokens) = tokens {
+            confidence::apply(&mut p, &content, tokens);
+        }
+
+        if !self.response_ok(&p) {
+            log::error!("Плохой ответ! Переотправка");
+            return Err(Rejection::Malformed);
+        }
+
+        let instruction = request
+            .get("meta")
+            .and_then(|m| m.get("instruction"))
+            .and_then(|i| serde_json::from_value::<InstructionRef>(i.clone()).ok());
+        for race in p.iter_mut() {
+            race.instruction = instruction.clone();
+        }
+
+        let errors = check_races(&mut p, &request_cards(request));
+        if !errors.is_empty() {
+            log::error!("Ответ не совпадает с карточками гонок: {:?}", errors);
+            return Err(Rejection::Invalid { content, errors });
+        }
+
+        log::info!("Хороший ответ!");
+        if !completion.cached {
+            self.cache_response(&completion.cache_key, resp).await;
+        }
+
+        Ok(p)
+    }
+
+    /// Also stores a response obtained after re-asking under the key of
+    /// the original request, so re-runs hit the cache straight away.
+    async fn cache_for(&self, original: &HashMap<String, serde_json::Value>, completion: &Completion) {
+        if self.cache.is_none() {
+            return;
+        }
+
+        match self.build_request(original).and_then(|r| self.cache_key(&r)) {
+            Ok(key) if key != completion.cache_key => self.cache_response(&key, &completion.response).await,
+            Ok(_) => {}
+            Err(err) => log::error!("Failed to cache response: {err}"),
+        }
+    }
+
+    async fn cache_response(&self, key: &str, resp: &CreateChatCompletionResponse) {
+        if let Some(cache) = &self.cache {
+            if let Err(err) = cache.put(key, &self.model.id, resp).await {
+                log::error!("Failed to cache response: {err}");
+            }
+        }
     }
 
-    fn parse_choice(&self, resp: &CreateChatCompletionResponse) -> Option<PredictResponse> {
+    fn response_ok(&self, races: &[PredictResponse]) -> bool {
+        !races.is_empty()
+            && races.iter().all(|p| {
+                p.predictions
+                    .iter()
+                    .filter(|pred| pred.raw_score == 0.0)
+                    .nth(0)
+                    .is_none()
+            })
+    }
+
+    fn parse_choice(&self, resp: &CreateChatCompletionResponse, multi: bool) -> Option<(String, Vec<PredictResponse>)> {
         resp.choices.iter().find_map(|c| {
-            c.message.content.as_ref().and_then(|s| serde_json::from_str(s).ok())
+            c.message.content.as_ref().and_then(|s| {
+                let races = if multi {
+                    serde_json::from_str::<MultiPredictResponse>(s).ok()?.races
+                } else {
+                    vec![serde_json::from_str(s).ok()?]
+                };
+                Some((s.clone(), races))
+            })
         })
     }
 
     pub async fn send_multiple(
         &self,
         requests: Vec<HashMap<String, serde_json::Value>>,
-    ) -> Result<Vec<PredictResponse>> {
+    ) -> Result<PredictResults> {
         if requests.is_empty() {
             bail!("No data to send");
         }
 
-        let responses = self
-            .execute_requests(requests)
-            .await
+        let execution = self.execute_requests(requests).await;
+        let predictions = execution
+            .predictions
             .into_iter()
             .map(|mut p| {
                 p.sort_predictions();
@@ -190,45 +638,142 @@ impl OpenAIClient {
             })
             .collect::<Vec<_>>();
 
-        Ok(responses)
+        Ok(PredictResults {
+            predictions,
+            usage: execution.usage,
+            failed: execution.failed
+        })
     }
 
     pub async fn test(
         &self,
         requests_info: RequestsInfo,
         database: Database,
-        initial_balance: f64,
-        initial_stake: f64,
-        odds_range: OddsRange,
-        is_favorite_protected: bool
+        params: &TestParams
     ) -> Result<TestResults> {
         if requests_info.requests.is_empty() {
             bail!("No data to send");
         }
 
-        let predictions  = self.execute_requests(requests_info.requests.clone()).await;
-        // log::debug!("Collected {} responses for test", responses.len());
-
-        let col = database.collection(DOG_INFO_COLLECTION);
-        let repo = MongoDogInfoRepo::new(col);
+        // Races are settled while later requests are still running, in
+        // request order so the balance evolves as in a sequential run.
+        let (tx, rx) = mpsc::unbounded_channel();
+        let streaming = self.with_results(tx);
+        let requests = requests_info.requests.clone();
+        let execute = async move { streaming.execute_requests(requests).await };
+        let settle = settle(in_request_order(rx), requests_info.total_races, &database, params, &self.progress);
 
-        let (meta, races) = process_test_results(
-            predictions, 
-            &repo,
-            requests_info.total_races, 
-            initial_balance, 
-            initial_stake, 
-            odds_range, 
-            is_favorite_protected
-        ).await?;
+        let (execution, settled) = futures::join!(execute, settle);
+        let (meta, races) = settled?;
 
-        Ok(TestResults::new(meta, races, requests_info.requests))
+        Ok(TestResults::new(meta, races, requests_info.requests, execution.usage, execution.failed))
     }
 
     fn clone_inner(&self) -> Self {
         Self {
             client: Arc::clone(&self.client),
             config: self.config.clone(),
+            model: self.model.clone(),
+            cache: self.cache.clone(),
+            semaphore: Arc::clone(&self.semaphore),
+            budget: self.budget.clone(),
+            http: self.http.clone(),
+            sample: self.sample,
+            progress: self.progress.clone(),
+            results: None,
+        }
+    }
+}
+
+/// The streamed answers one race at a time, in request order. An answer
+/// is held back until every earlier request has finished; requests that
+/// never finish (cancelled) release the rest once the channel closes.
+pub fn in_request_order(
+    rx: mpsc::UnboundedReceiver<(usize, Vec<PredictResponse>)>
+) -> impl Stream<Item = PredictResponse> {
+    let state = (rx, 0usize, BTreeMap::new(), VecDeque::new());
+
+    stream::unfold(state, |(mut rx, mut next, mut pending, mut ready)| async move {
+        loop {
+            if let Some(p) = ready.pop_front() {
+                return Some((p, (rx, next, pending, ready)));
+            }
+            if let Some(races) = pending.remove(&next) {
+                ready.extend(races);
+                next += 1;
+                continue;
+            }
+
+            match rx.recv().await {
+                Some((index, races)) => {
+                    pending.insert(index, races);
+                }
+                None if pending.is_empty() => return None,
+                None => {
+                    ready.extend(std::mem::take(&mut pending).into_values().flatten());
+                }
+            }
+        }
+    })
+}
+
+/// Splits the cost of one completion evenly across the races it answered.
+pub fn share_usage(races: &mut [PredictResponse], usage: Option<TokenUsage>) {
+    let parts = races.len();
+    for p in races.iter_mut() {
+        p.usage = usage.map(|u| u.share(parts));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn race(track: &str) -> PredictResponse {
+        let mut race = PredictResponse::default();
+        race.meta.track = track.to_string();
+        race
+    }
+
+    async fn order(sent: Vec<(usize, Vec<&str>)>) -> Vec<String> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        for (index, tracks) in sent {
+            tx.send((index, tracks.into_iter().map(race).collect())).unwrap();
         }
+        drop(tx);
+
+        in_request_order(rx).map(|p| p.meta.track).collect().await
+    }
+
+    #[tokio::test]
+    async fn in_request_order_holds_later_answers() {
+        let tracks = order(vec![(2, vec!["c"]), (0, vec!["a"]), (1, vec!["b1", "b2"])]).await;
+        assert_eq!(tracks, ["a", "b1", "b2", "c"]);
+    }
+
+    #[tokio::test]
+    async fn in_request_order_passes_failed_requests() {
+        let tracks = order(vec![(1, vec!["b"]), (0, vec![]), (2, vec!["c"])]).await;
+        assert_eq!(tracks, ["b", "c"]);
+    }
+
+    #[tokio::test]
+    async fn in_request_order_releases_the_rest_when_closed() {
+        let tracks = order(vec![(3, vec!["d"]), (2, vec!["c"])]).await;
+        assert_eq!(tracks, ["c", "d"]);
+    }
+
+    #[tokio::test]
+    async fn in_request_order_streams_before_close() {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut stream = Box::pin(in_request_order(rx));
+
+        tx.send((1, vec![race("b")])).unwrap();
+        tx.send((0, vec![race("a")])).unwrap();
+        assert_eq!(stream.next().await.unwrap().meta.track, "a");
+        assert_eq!(stream.next().await.unwrap().meta.track, "b");
+
+        drop(tx);
+        assert!(stream.next().await.is_none());
     }
 }
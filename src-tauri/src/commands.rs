@@ -1,24 +1,112 @@
+use std::path::PathBuf;
+
 use anyhow::Result;
-use chrono::{TimeZone, Utc};
+use chrono::{NaiveDate, TimeZone, Utc};
 use futures::TryStreamExt;
 use mongodb::{
     bson::{
-        self, doc, to_document, DateTime, Document
+        self, doc, to_document, Document
     }, 
     Client
 };
-use tauri::State;
+use tauri::{
+    AppHandle, 
+    Emitter, 
+    Manager, 
+    State
+};
 use crate::{
     constants::{
-        INSTRUCTION_COLLECTION, PREDICTIONS_COLLECTION, RACES_COLLECTION, SETTINGS_COLLECTION, TIME_RANGES_COLLECTION
+        BATCH_FILES_DIR, BATCH_JOBS_COLLECTION, INSTRUCTION_COLLECTION, INSTRUCTIONS_DIR, MODELS_COLLECTION, PREDICTIONS_COLLECTION, RACES_COLLECTION, SETTINGS_COLLECTION, TIME_RANGES_COLLECTION, USAGE_COLLECTION
     }, 
     models::{
-        AddInstructionInput, LoadPredictionsInput, LoadSettingsInput, LoadSettingsOutput, OddsRange, PredictInput, PredictResponse, SaveSettingsInput, Settings, TestDateTime, TestResults, Time, TimeRange
+        AddInstructionInput, BatchJob, BatchPollOutput, CacheMode, DailyUsage, DiffRow, EnsembleOptions, ImportSummary, InstructionDoc, LoadPredictionsInput, LoadSettingsInput, LoadSettingsOutput, LoadUsageInput, LogitMetrics, LogitModel, ModelInfo, OddsRange, PredictInput, PredictorBackend, PredictResponse, PredictResults, PromptEstimate, SaveSettingsInput, Settings, StakingPlan, StrategyKind, TestDateTime, TestParams, TestResults, Time, TimeRange, TrainLogitInput, TrapBias, TrapBiasInput, StandardTime, StandardTimesInput, DogRating, SpeedFigure
+    }, 
+    batch::BatchClient, 
+    cache::ResponseCache, 
+    features::FeatureContext, 
+    instructions, 
+    logit, 
+    client::OpenAIClient, 
+    progress::{
+        JobProgress, 
+        Jobs, 
+        Progress
     }, 
     predictor::Predictor, 
-    tester::Tester
+    tester::Tester, 
+    traps, 
+    standards, 
+    ratings, 
+    utils::load_model_info
 };
 
+#[tauri::command]
+pub async fn load_models(
+    client_state: State<'_, Client>
+) -> Result<Vec<ModelInfo>, String> {
+    let collection = client_state
+        .default_database()
+        .ok_or("No default DB")?
+        .collection::<ModelInfo>(MODELS_COLLECTION);
+
+    let mut models: Vec<ModelInfo> = collection
+        .find(doc! {})
+        .await
+        .map_err(|e| format!("Find error: {}", e))?
+        .try_collect()
+        .await
+        .map_err(|e| format!("Cursor error: {}", e))?;
+
+    if models.is_empty() {
+        models = ModelInfo::defaults();
+        collection
+            .insert_many(&models)
+            .await
+            .map_err(|e| format!("Insert error: {}", e))?;
+    }
+    models.sort_by(|a, b| a.id.cmp(&b.id));
+
+    Ok(models)
+}
+
+#[tauri::command]
+pub async fn save_model(
+    input: ModelInfo,
+    client_state: State<'_, Client>
+) -> Result<String, String> {
+    let collection = client_state
+        .default_database()
+        .ok_or("No default DB")?
+        .collection::<ModelInfo>(MODELS_COLLECTION);
+
+    collection
+        .replace_one(doc! { "id": &input.id }, &input)
+        .upsert(true)
+        .await
+        .map_err(|e| format!("Update error: {}", e))?;
+
+    Ok(format!("Model '{}' was saved!", input.id))
+}
+
+#[tauri::command]
+pub async fn delete_model(
+    id: String,
+    client_state: State<'_, Client>
+) -> Result<String, String> {
+    let collection = client_state
+        .default_database()
+        .ok_or("No default DB")?
+        .collection::<Document>(MODELS_COLLECTION);
+
+    collection
+        .delete_one(doc! { "id": &id })
+        .await
+        .map_err(|e| format!("Delete error: {}", e))?;
+
+    Ok(format!("Model '{}' was deleted!", id))
+}
+
 #[tauri::command]
 pub async fn load_settings(
     input: LoadSettingsInput,
@@ -81,17 +169,11 @@ pub async fn add_instruction(
 ) -> Result<String, String> {
     println!("add_instruction called with name: {}, content length: {}", input.name, input.content.len());
     
-    let collection = client_state
+    let db = client_state
         .default_database()
-        .ok_or("No default DB")?
-        .collection::<Document>(INSTRUCTION_COLLECTION);
+        .ok_or("No default DB")?;
 
-    collection
-        .insert_one(doc! {
-            "name": &input.name,
-            "content": &input.content,
-            "created_at": DateTime::now()
-        })
+    instructions::save_version(&db, &input.name, &input.content, input.author)
         .await
         .map_err(|e| format!("Insert error: {}", e))?;
 
@@ -99,6 +181,152 @@ pub async fn add_instruction(
     Ok("Added instruction!".into())
 }
 
+#[tauri::command]
+pub async fn load_instruction(
+    name: String,
+    version: Option<u32>,
+    client_state: State<'_, Client>,
+) -> Result<InstructionDoc, String> {
+    let db = client_state
+        .default_database()
+        .ok_or("No default DB")?;
+
+    instructions::load(&db, &name, version)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Saves the content as a new version of an existing instruction.
+#[tauri::command]
+pub async fn update_instruction(
+    input: AddInstructionInput,
+    client_state: State<'_, Client>,
+) -> Result<InstructionDoc, String> {
+    let db = client_state
+        .default_database()
+        .ok_or("No default DB")?;
+
+    instructions::update(&db, &input.name, &input.content, input.author)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn rename_instruction(
+    name: String,
+    new_name: String,
+    client_state: State<'_, Client>,
+) -> Result<String, String> {
+    let db = client_state
+        .default_database()
+        .ok_or("No default DB")?;
+
+    instructions::rename(&db, &name, &new_name)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(format!("Renamed instruction {name} to {new_name}"))
+}
+
+#[tauri::command]
+pub async fn delete_instruction(
+    name: String,
+    client_state: State<'_, Client>,
+) -> Result<u64, String> {
+    let db = client_state
+        .default_database()
+        .ok_or("No default DB")?;
+
+    instructions::delete(&db, &name)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Side-by-side diff of two instructions; a missing version means the newest.
+#[tauri::command]
+pub async fn diff_instructions(
+    left: String,
+    left_version: Option<u32>,
+    right: String,
+    right_version: Option<u32>,
+    client_state: State<'_, Client>,
+) -> Result<Vec<DiffRow>, String> {
+    let db = client_state
+        .default_database()
+        .ok_or("No default DB")?;
+
+    let left = instructions::load(&db, &left, left_version)
+        .await
+        .map_err(|e| e.to_string())?;
+    let right = instructions::load(&db, &right, right_version)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(instructions::diff(&left.content, &right.content))
+}
+
+/// The directory given, or the instructions directory under the app data
+/// directory.
+fn instructions_dir(app: &AppHandle, dir: Option<String>) -> Result<PathBuf, String> {
+    match dir {
+        Some(dir) => Ok(PathBuf::from(dir)),
+        None => Ok(app
+            .path()
+            .app_data_dir()
+            .map_err(|e| e.to_string())?
+            .join(INSTRUCTIONS_DIR)),
+    }
+}
+
+#[tauri::command]
+pub async fn export_instructions(
+    app: AppHandle,
+    dir: Option<String>,
+    client_state: State<'_, Client>,
+) -> Result<Vec<String>, String> {
+    let db = client_state
+        .default_database()
+        .ok_or("No default DB")?;
+    let dir = instructions_dir(&app, dir)?;
+
+    let paths = instructions::export_markdown(&db, &dir)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(paths.iter().map(|p| p.display().to_string()).collect())
+}
+
+#[tauri::command]
+pub async fn import_instructions(
+    app: AppHandle,
+    dir: Option<String>,
+    author: Option<String>,
+    client_state: State<'_, Client>,
+) -> Result<ImportSummary, String> {
+    let db = client_state
+        .default_database()
+        .ok_or("No default DB")?;
+    let dir = instructions_dir(&app, dir)?;
+
+    instructions::import_markdown(&db, &dir, author)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn load_instruction_history(
+    name: String,
+    client_state: State<'_, Client>,
+) -> Result<Vec<InstructionDoc>, String> {
+    let db = client_state
+        .default_database()
+        .ok_or("No default DB")?;
+
+    instructions::history(&db, &name)
+        .await
+        .map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 pub async fn read_instruction_names(
     client_state: State<'_, Client>,
@@ -108,17 +336,14 @@ pub async fn read_instruction_names(
         .ok_or("No default DB")?
         .collection::<Document>(INSTRUCTION_COLLECTION);
 
-    let mut cursor = collection
-        .find(doc! {})
+    // Every version is a separate document.
+    let names = collection
+        .distinct("name", doc! {})
         .await
-        .map_err(|e| format!("Find error: {}", e))?;
-
-    let mut names = Vec::new();
-    while let Some(doc) = cursor.try_next().await.map_err(|e| format!("Cursor error: {}", e))? {
-        if let Ok(name) = doc.get_str("name") {
-            names.push(name.to_string());
-        }
-    }
+        .map_err(|e| format!("Find error: {}", e))?
+        .into_iter()
+        .filter_map(|name| name.as_str().map(str::to_string))
+        .collect();
 
     Ok(names)
 }
@@ -164,16 +389,120 @@ pub async fn load_predictions(
         .try_collect()
         .await
         .map_err(|e| e.to_string())?;
-    predictions.sort_unstable_by(|a, b| a.meta.time.cmp(&b.meta.time));
+    predictions.sort_unstable_by_key(|p| p.meta.time);
 
     Ok(predictions)
 }
 
+#[tauri::command]
+pub async fn load_daily_usage(
+    client_state: State<'_, Client>,
+    input: LoadUsageInput,
+) -> Result<Vec<DailyUsage>, String> {
+    let db = client_state
+        .default_database()
+        .ok_or("No default database")?;
+
+    let pipeline = vec![
+        doc! {
+            "$match": {
+                "date": {
+                    "$gte": input.start_date.to_string(),
+                    "$lte": input.end_date.to_string()
+                }
+            }
+        },
+        doc! {
+            "$group": {
+                "_id": { "date": "$date", "kind": "$kind" },
+                "promptTokens": { "$sum": "$usage.promptTokens" },
+                "completionTokens": { "$sum": "$usage.completionTokens" },
+                "reasoningTokens": { "$sum": "$usage.reasoningTokens" },
+                "cost": { "$sum": "$usage.cost" }
+            }
+        },
+        doc! {
+            "$project": {
+                "_id": 0,
+                "date": "$_id.date",
+                "kind": "$_id.kind",
+                "usage": {
+                    "promptTokens": "$promptTokens",
+                    "completionTokens": "$completionTokens",
+                    "reasoningTokens": "$reasoningTokens",
+                    "cost": "$cost"
+                }
+            }
+        },
+        doc! { "$sort": { "date": 1_i32, "kind": 1_i32 } },
+    ];
+
+    let docs: Vec<Document> = db
+        .collection::<Document>(USAGE_COLLECTION)
+        .aggregate(pipeline)
+        .await
+        .map_err(|e| e.to_string())?
+        .try_collect()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    docs.into_iter()
+        .map(|d| bson::from_document::<DailyUsage>(d).map_err(|e| e.to_string()))
+        .collect()
+}
+
+#[tauri::command]
+pub async fn clear_response_cache(
+    client_state: State<'_, Client>,
+) -> Result<u64, String> {
+    let db = client_state
+        .default_database()
+        .ok_or("No default database")?;
+
+    ResponseCache::new(&db, CacheMode::Refresh)
+        .clear()
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Registers the job and forwards its progress as `job-progress` events.
+/// Jobs started without an id report nothing and cannot be cancelled.
+fn start_job(app: &AppHandle, jobs: &Jobs, job_id: Option<&str>) -> Progress {
+    let Some(job_id) = job_id else {
+        return Progress::none();
+    };
+
+    let app = app.clone();
+    let id = job_id.to_string();
+    let progress = Progress::new(move |event| {
+        let payload = JobProgress { job_id: id.clone(), event };
+        if let Err(e) = app.emit("job-progress", payload) {
+            log::warn!("Failed to emit progress: {e}");
+        }
+    });
+    jobs.start(job_id, progress.clone());
+
+    progress
+}
+
+/// Stops a running predict or test job. In-flight API calls are
+/// aborted and the job returns what finished so far.
+#[tauri::command]
+pub async fn cancel_job(
+    job_id: String,
+    jobs: State<'_, Jobs>,
+) -> Result<bool, String> {
+    Ok(jobs.cancel(&job_id))
+}
+
 #[tauri::command]
 pub async fn run_predict(
+    app: AppHandle,
     client_state: State<'_, Client>,
+    jobs: State<'_, Jobs>,
     input: PredictInput,
-) -> Result<Vec<PredictResponse>, String> {
+    job_id: Option<String>,
+) -> Result<PredictResults, String> {
     let db_client = client_state.inner().clone();
     let config = db_client
         .default_database()
@@ -183,29 +512,101 @@ pub async fn run_predict(
         .await
         .map_err(|e| e.to_string())?
         .ok_or("No settings for selected model")?;
-
-    let predictor = Predictor::new(config, db_client.clone(), input.clone()).await;
-    
-    let mut result = predictor.run()
+    let model = load_model_info(&db_client.default_database().ok_or("No default database")?, &config.model)
         .await
         .map_err(|e| e.to_string())?;
-    result.sort_unstable_by(|a, b| a.meta.time.cmp(&b.meta.time));
+
+    let progress = start_job(&app, &jobs, job_id.as_deref());
+    let predictor = Predictor::new(config, model, db_client.clone(), input.clone())
+        .await
+        .with_progress(progress);
+    
+    let result = predictor.run().await;
+    if let Some(job_id) = &job_id {
+        jobs.finish(job_id);
+    }
+    let mut result = result.map_err(|e| e.to_string())?;
+    result.predictions.sort_unstable_by_key(|p| p.meta.time);
     
     Ok(result)
 }
 
+/// Token estimate of the races `run_predict` would send, per prompt format.
+#[tauri::command]
+pub async fn estimate_predict_prompt(
+    client_state: State<'_, Client>,
+    input: PredictInput,
+) -> Result<Vec<PromptEstimate>, String> {
+    let db_client = client_state.inner().clone();
+    let db = db_client
+        .default_database()
+        .ok_or("No default database")?;
+    let config = db
+        .collection::<Settings>(SETTINGS_COLLECTION)
+        .find_one(doc! { "selected": true })
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or("No settings for selected model")?;
+    let model = load_model_info(&db, &config.model)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Predictor::new(config, model, db_client, input)
+        .await
+        .estimate_prompts()
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Token estimate of the races `run_test` would send, per prompt format.
+#[tauri::command]
+pub async fn estimate_test_prompt(
+    client_state: State<'_, Client>,
+    date_time: TestDateTime,
+    distances: Vec<i32>,
+) -> Result<Vec<PromptEstimate>, String> {
+    let db_client = client_state.inner().clone();
+    let db = db_client
+        .default_database()
+        .ok_or("No default database")?;
+    let config = db
+        .collection::<Settings>(SETTINGS_COLLECTION)
+        .find_one(doc! { "selected": true })
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or("No settings for selected model")?;
+    let model = load_model_info(&db, &config.model)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Tester::new(config, model, db_client, date_time, distances)
+        .estimate_prompts()
+        .await
+        .map_err(|e| e.to_string())
+}
+
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub async fn run_test(
+    app: AppHandle,
     client_state: State<'_, Client>,
+    jobs: State<'_, Jobs>,
+    job_id: Option<String>,
     date_time: TestDateTime,
     distances: Vec<i32>,
     initial_stake: f64,
     initial_balance: f64,
     is_favorite_protected: bool,
-    odds_range: OddsRange
+    odds_range: OddsRange,
+    cache_mode: Option<CacheMode>,
+    ensemble: Option<EnsembleOptions>,
+    min_confidence: Option<f32>,
+    backend: Option<PredictorBackend>,
+    strategy: Option<StrategyKind>,
+    staking: Option<StakingPlan>
 ) -> Result<TestResults, String> {
     let db_client = client_state.inner().clone();
-    let config = db_client
+    let mut config = db_client
         .default_database()
         .ok_or("No default database")?
         .collection::<Settings>(SETTINGS_COLLECTION)
@@ -213,15 +614,298 @@ pub async fn run_test(
         .await
         .map_err(|e| e.to_string())?
         .ok_or("No settings for selected model")?;
+    let model = load_model_info(&db_client.default_database().ok_or("No default database")?, &config.model)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if let Some(mode) = cache_mode {
+        config.cache_mode = mode;
+    }
 
-    let tester = Tester::new(config, db_client, date_time, distances);
+    let progress = start_job(&app, &jobs, job_id.as_deref());
+    let tester = Tester::new(config, model, db_client, date_time, distances)
+        .with_ensemble(ensemble)
+        .with_backend(backend.unwrap_or_default())
+        .with_progress(progress);
+    let params = TestParams {
+        initial_balance,
+        initial_stake,
+        odds_range,
+        is_favorite_protected,
+        min_confidence,
+        strategy: strategy.unwrap_or_default(),
+        staking: staking.unwrap_or_default()
+    };
     
-    let result = tester
-        .run(initial_balance, initial_stake, odds_range, is_favorite_protected)
+    let result = tester.run(params).await;
+    if let Some(job_id) = &job_id {
+        jobs.finish(job_id);
+    }
+
+    result.map_err(|err| err.to_string())
+}
+
+/// Trains a logit model on the settled races of the period and saves it
+/// as the next version.
+#[tauri::command]
+pub async fn train_logit_model(
+    client_state: State<'_, Client>,
+    input: TrainLogitInput,
+) -> Result<LogitModel, String> {
+    let db = client_state
+        .default_database()
+        .ok_or("No default database")?;
+
+    logit::train(&db, &input).await.map_err(|e| e.to_string())
+}
+
+/// Scores a saved logit model on the settled races of the period; a
+/// missing version means the newest.
+#[tauri::command]
+pub async fn validate_logit_model(
+    client_state: State<'_, Client>,
+    from: NaiveDate,
+    to: NaiveDate,
+    version: Option<u32>,
+) -> Result<LogitMetrics, String> {
+    let db = client_state
+        .default_database()
+        .ok_or("No default database")?;
+
+    let model = match version {
+        Some(version) => logit::load(&db, version).await,
+        None => logit::latest(&db).await,
+    }
+    .map_err(|e| e.to_string())?;
+
+    logit::validate(&db, &model, from, to).await.map_err(|e| e.to_string())
+}
+
+/// Recomputes the trap bias figures from the settled races of the period,
+/// replacing the ones stored for the same period.
+#[tauri::command]
+pub async fn compute_trap_bias(
+    client_state: State<'_, Client>,
+    input: TrapBiasInput,
+) -> Result<Vec<TrapBias>, String> {
+    let db = client_state
+        .default_database()
+        .ok_or("No default database")?;
+
+    traps::compute(&db, &input).await.map_err(|e| e.to_string())
+}
+
+/// Stored trap bias figures, optionally for one track and distance.
+#[tauri::command]
+pub async fn load_trap_bias(
+    client_state: State<'_, Client>,
+    track: Option<String>,
+    distance: Option<u32>,
+) -> Result<Vec<TrapBias>, String> {
+    let db = client_state
+        .default_database()
+        .ok_or("No default database")?;
+
+    traps::stored(&db, track.as_deref(), distance).await.map_err(|e| e.to_string())
+}
+
+/// Rederives the standard times from the winners of the period,
+/// replacing the ones stored for the same period.
+#[tauri::command]
+pub async fn compute_standard_times(
+    client_state: State<'_, Client>,
+    input: StandardTimesInput,
+) -> Result<Vec<StandardTime>, String> {
+    let db = client_state
+        .default_database()
+        .ok_or("No default database")?;
+
+    standards::compute(&db, &input).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn load_standard_times(
+    client_state: State<'_, Client>
+) -> Result<Vec<StandardTime>, String> {
+    let db = client_state
+        .default_database()
+        .ok_or("No default database")?;
+
+    standards::stored(&db).await.map_err(|e| e.to_string())
+}
+
+/// Stored speed figures of a dog's runs, oldest first.
+#[tauri::command]
+pub async fn load_speed_figures(
+    client_state: State<'_, Client>,
+    dog_name: String,
+) -> Result<Vec<SpeedFigure>, String> {
+    let db = client_state
+        .default_database()
+        .ok_or("No default database")?;
+
+    standards::history(&db, &dog_name).await.map_err(|e| e.to_string())
+}
+
+/// Rates the races added since the last update, or every race again from
+/// scratch with `replay`. Returns the number of races rated.
+#[tauri::command]
+pub async fn update_ratings(
+    client_state: State<'_, Client>,
+    replay: bool,
+) -> Result<usize, String> {
+    let db = client_state
+        .default_database()
+        .ok_or("No default database")?;
+
+    ratings::update(&db, replay).await.map_err(|e| e.to_string())
+}
+
+/// Rating of a dog going into a race on `date`.
+#[tauri::command]
+pub async fn load_dog_rating(
+    client_state: State<'_, Client>,
+    dog_name: String,
+    date: NaiveDate,
+) -> Result<Option<DogRating>, String> {
+    let db = client_state
+        .default_database()
+        .ok_or("No default database")?;
+
+    ratings::as_of(&db, &dog_name, date).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn load_rating_history(
+    client_state: State<'_, Client>,
+    dog_name: String,
+) -> Result<Vec<DogRating>, String> {
+    let db = client_state
+        .default_database()
+        .ok_or("No default database")?;
+
+    ratings::history(&db, &dog_name).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn submit_test_batch(
+    app: AppHandle,
+    client_state: State<'_, Client>,
+    date_time: TestDateTime,
+    distances: Vec<i32>,
+    initial_stake: f64,
+    initial_balance: f64,
+    is_favorite_protected: bool,
+    odds_range: OddsRange,
+    min_confidence: Option<f32>,
+    strategy: Option<StrategyKind>,
+    staking: Option<StakingPlan>
+) -> Result<BatchJob, String> {
+    let db_client = client_state.inner().clone();
+    let config = db_client
+        .default_database()
+        .ok_or("No default database")?
+        .collection::<Settings>(SETTINGS_COLLECTION)
+        .find_one(doc! { "selected": true })
         .await
-        .map_err(|err| err.to_string())?;
+        .map_err(|e| e.to_string())?
+        .ok_or("No settings for selected model")?;
+    let model = load_model_info(&db_client.default_database().ok_or("No default database")?, &config.model)
+        .await
+        .map_err(|e| e.to_string())?;
 
-    Ok(result)
+    let tester = Tester::new(config, model, db_client, date_time, distances);
+    let params = TestParams {
+        initial_balance,
+        initial_stake,
+        odds_range,
+        is_favorite_protected,
+        min_confidence,
+        strategy: strategy.unwrap_or_default(),
+        staking: staking.unwrap_or_default()
+    };
+
+    let files_dir = app
+        .path()
+        .app_data_dir()
+        .map_err(|e| e.to_string())?
+        .join(BATCH_FILES_DIR);
+
+    tester
+        .submit_batch(params, files_dir)
+        .await
+        .map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+pub async fn load_batch_jobs(
+    client_state: State<'_, Client>,
+) -> Result<Vec<BatchJob>, String> {
+    let db = client_state
+        .default_database()
+        .ok_or("No default database")?;
+
+    let mut jobs: Vec<BatchJob> = db
+        .collection::<BatchJob>(BATCH_JOBS_COLLECTION)
+        .find(doc! {})
+        .await
+        .map_err(|e| e.to_string())?
+        .try_collect()
+        .await
+        .map_err(|e| e.to_string())?;
+    jobs.sort_by_key(|j| std::cmp::Reverse(j.created_at));
+
+    Ok(jobs)
+}
+
+async fn find_batch_job(db: &mongodb::Database, batch_id: &str) -> Result<BatchJob, String> {
+    db.collection::<BatchJob>(BATCH_JOBS_COLLECTION)
+        .find_one(doc! { "batchId": batch_id })
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("No batch job with id {batch_id}"))
+}
+
+#[tauri::command]
+pub async fn poll_batch_job(
+    client_state: State<'_, Client>,
+    batch_id: String,
+) -> Result<BatchPollOutput, String> {
+    let db = client_state
+        .default_database()
+        .ok_or("No default database")?;
+
+    let mut job = find_batch_job(&db, &batch_id).await?;
+    let cache = ResponseCache::new(&db, job.settings.cache_mode);
+    let client = OpenAIClient::new(job.settings.clone(), job.model.clone()).with_cache(cache);
+
+    let results = BatchClient::new(client, &db)
+        .poll_test(&mut job, &db)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(BatchPollOutput { job, results })
+}
+
+#[tauri::command]
+pub async fn cancel_batch_job(
+    client_state: State<'_, Client>,
+    batch_id: String,
+) -> Result<BatchJob, String> {
+    let db = client_state
+        .default_database()
+        .ok_or("No default database")?;
+
+    let mut job = find_batch_job(&db, &batch_id).await?;
+    let client = OpenAIClient::new(job.settings.clone(), job.model.clone());
+
+    BatchClient::new(client, &db)
+        .cancel(&mut job)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(job)
 }
 
 #[tauri::command]
@@ -269,7 +953,7 @@ pub async fn copy_predict_request(
         doc! { "$sort": { "race_date_time": 1_i32 } },
     ];
 
-    let races: Vec<Document> = db
+    let mut races: Vec<Document> = db
         .collection::<Document>(RACES_COLLECTION)
         .aggregate(pipeline)
         .await
@@ -277,6 +961,8 @@ pub async fn copy_predict_request(
         .try_collect()
         .await
         .map_err(|e| e.to_string())?;
+    let context = FeatureContext::load(&db, &races).await;
+    races.iter_mut().for_each(|race| context.annotate(race));
 
     let json = serde_json::to_string_pretty(&races).map_err(|e| e.to_string())?;
     Ok(json)
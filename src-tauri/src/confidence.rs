@@ -0,0 +1,219 @@
+use async_openai::types::ChatCompletionTokenLogprob;
+
+use crate::models::PredictResponse;
+
+/// Fields whose tokens measure how sure the model was about a dog.
+const SCORED_FIELDS: [&str; 2] = ["rank", "percentage"];
+
+/// Sets per-dog and per-race confidence from the token logprobs of the
+/// message `content` the races were parsed from.
+///
+/// A dog's confidence is the geometric mean probability of the tokens
+/// spelling its `rank` and `percentage` values; a race's is the mean over
+/// its dogs. Dogs are matched to values in the order they appear, so this
+/// must run before predictions are reordered.
+pub fn apply(races: &mut [PredictResponse], content: &str, tokens: &[ChatCompletionTokenLogprob]) {
+    let Some(offsets) = token_offsets(content, tokens) else {
+        log::warn!("Logprobs do not match the response content, confidence skipped");
+        return;
+    };
+
+    let spans: Vec<Vec<(usize, usize)>> = SCORED_FIELDS
+        .iter()
+        .map(|field| value_spans(content, field))
+        .collect();
+
+    let mut dog = 0;
+    for race in races.iter_mut() {
+        for p in race.predictions.iter_mut() {
+            let logprobs: Vec<f32> = spans
+                .iter()
+                .filter_map(|field| field.get(dog))
+                .flat_map(|&(start, end)| {
+                    offsets
+                        .iter()
+                        .zip(tokens)
+                        .filter(move |((t_start, t_end), _)| *t_start < end && *t_end > start)
+                        .map(|(_, t)| t.logprob)
+                })
+                .collect();
+
+            if !logprobs.is_empty() {
+                let mean = logprobs.iter().sum::<f32>() / logprobs.len() as f32;
+                p.confidence = Some(mean.exp());
+            }
+            dog += 1;
+        }
+
+        let scored: Vec<f32> = race.predictions.iter().filter_map(|p| p.confidence).collect();
+        if !scored.is_empty() {
+            race.confidence = Some(scored.iter().sum::<f32>() / scored.len() as f32);
+        }
+    }
+}
+
+/// Byte range of every token in `content`, or `None` when the tokens do
+/// not spell the content exactly.
+fn token_offsets(content: &str, tokens: &[ChatCompletionTokenLogprob]) -> Option<Vec<(usize, usize)>> {
+    let mut offsets = Vec::with_capacity(tokens.len());
+    let mut pos = 0;
+    for t in tokens {
+        let bytes = t.bytes.as_deref().unwrap_or(t.token.as_bytes());
+        let end = pos + bytes.len();
+        if content.as_bytes().get(pos..end)? != bytes {
+            return None;
+        }
+        offsets.push((pos, end));
+        pos = end;
+    }
+
+    (pos == content.len()).then_some(offsets)
+}
+
+/// Byte ranges of the numeric values of every `"field":` key, in order.
+/// Keys quoted inside string values are escaped and never match.
+fn value_spans(content: &str, field: &str) -> Vec<(usize, usize)> {
+    let key = format!("\"{field}\"");
+    let bytes = content.as_bytes();
+
+    content
+        .match_indices(&key)
+        .filter(|(i, _)| *i == 0 || bytes[i - 1] != b'\\')
+        .filter_map(|(i, _)| {
+            let mut pos = i + key.len();
+            while bytes.get(pos).is_some_and(u8::is_ascii_whitespace) {
+                pos += 1;
+            }
+            if bytes.get(pos) != Some(&b':') {
+                return None;
+            }
+            pos += 1;
+            while bytes.get(pos).is_some_and(u8::is_ascii_whitespace) {
+                pos += 1;
+            }
+
+            let start = pos;
+            while bytes.get(pos).is_some_and(|b| b.is_ascii_digit() || matches!(b, b'-' | b'+' | b'.' | b'e' | b'E')) {
+                pos += 1;
+            }
+            (pos > start).then_some((start, pos))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::models::Prediction;
+
+    fn tokens(pieces: &[(&str, f32)]) -> Vec<ChatCompletionTokenLogprob> {
+        pieces
+            .iter()
+            .map(|(token, logprob)| ChatCompletionTokenLogprob {
+                token: token.to_string(),
+                logprob: *logprob,
+                bytes: Some(token.as_bytes().to_vec()),
+                top_logprobs: Vec::new(),
+            })
+            .collect()
+    }
+
+    /// Two dogs, the first one's percentage split over two tokens.
+    fn sample() -> (String, Vec<ChatCompletionTokenLogprob>) {
+        let tokens = tokens(&[
+            ("{\"predictions\":[{", 0.0),
+            ("\"rank\":", 0.0),
+            ("1", -0.2),
+            (",\"percentage\":", 0.0),
+            ("6", -0.1),
+            ("0", -0.5),
+            ("},{", 0.0),
+            ("\"rank\": ", 0.0),
+            ("2", -1.0),
+            (",\"percentage\":", 0.0),
+            ("40", -1.0),
+            ("}]}", 0.0),
+        ]);
+        let content = tokens.iter().map(|t| t.token.as_str()).collect();
+
+        (content, tokens)
+    }
+
+    fn race(dogs: usize) -> PredictResponse {
+        PredictResponse {
+            predictions: vec![Prediction::default(); dogs],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn value_spans_cover_the_numbers_after_each_key() {
+        let (content, _) = sample();
+
+        let spans: Vec<&str> = value_spans(&content, "percentage")
+            .into_iter()
+            .map(|(start, end)| &content[start..end])
+            .collect();
+        assert_eq!(spans, ["60", "40"]);
+    }
+
+    #[test]
+    fn value_spans_skip_keys_escaped_in_strings() {
+        let content = r#"{"summary":"not \"rank\": 5","rank": 3}"#;
+
+        let spans = value_spans(content, "rank");
+        assert_eq!(spans.len(), 1);
+        assert_eq!(&content[spans[0].0..spans[0].1], "3");
+    }
+
+    #[test]
+    fn token_offsets_follow_the_token_bytes() {
+        let (content, tokens) = sample();
+
+        let offsets = token_offsets(&content, &tokens).unwrap();
+        assert_eq!(offsets.len(), tokens.len());
+        assert_eq!(offsets[0].0, 0);
+        assert_eq!(offsets.last().unwrap().1, content.len());
+        assert!(offsets.windows(2).all(|w| w[0].1 == w[1].0));
+        assert_eq!(&content[offsets[4].0..offsets[5].1], "60");
+    }
+
+    #[test]
+    fn token_offsets_reject_tokens_not_spelling_the_content() {
+        let (content, mut tokens) = sample();
+
+        tokens[2].bytes = Some(b"7".to_vec());
+        assert_eq!(token_offsets(&content, &tokens), None);
+
+        let (content, mut tokens) = sample();
+        tokens.pop();
+        assert_eq!(token_offsets(&content, &tokens), None);
+    }
+
+    #[test]
+    fn apply_scores_dogs_by_their_value_tokens() {
+        let (content, tokens) = sample();
+        let mut races = [race(2)];
+
+        apply(&mut races, &content, &tokens);
+
+        let first = races[0].predictions[0].confidence.unwrap();
+        let second = races[0].predictions[1].confidence.unwrap();
+        assert!((first - (-0.8f32 / 3.0).exp()).abs() < 1e-6);
+        assert!((second - (-1.0f32).exp()).abs() < 1e-6);
+        assert!((races[0].confidence.unwrap() - (first + second) / 2.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn apply_leaves_confidence_unset_when_tokens_do_not_match() {
+        let (content, mut tokens) = sample();
+        tokens.pop();
+        let mut races = [race(2)];
+
+        apply(&mut races, &content, &tokens);
+
+        assert_eq!(races[0].confidence, None);
+        assert!(races[0].predictions.iter().all(|p| p.confidence.is_none()));
+    }
+}
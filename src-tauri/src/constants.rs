@@ -12,4 +12,21 @@ pub const INSTRUCTION_COLLECTION: &str = "instructions";
 pub const SETTINGS_COLLECTION: &str = "settings";
 pub const PREDICTIONS_COLLECTION: &str = "predictions";
 pub const TIME_RANGES_COLLECTION: &str = "time_ranges";
+pub const MODELS_COLLECTION: &str = "models";
+pub const USAGE_COLLECTION: &str = "usage";
+pub const TEST_RUNS_COLLECTION: &str = "test_runs";
+pub const LLM_CACHE_COLLECTION: &str = "llm_cache";
+pub const BATCH_JOBS_COLLECTION: &str = "batch_jobs";
+pub const BATCH_REQUESTS_COLLECTION: &str = "batch_requests";
+pub const LOGIT_MODELS_COLLECTION: &str = "logit_models";
+pub const TRAP_BIAS_COLLECTION: &str = "trap_bias";
+pub const STANDARD_TIMES_COLLECTION: &str = "standard_times";
+pub const DOG_RATINGS_COLLECTION: &str = "dog_ratings";
+pub const SPEED_FIGURES_COLLECTION: &str = "speed_figures";
+/// Under the app data directory, like `INSTRUCTIONS_DIR`.
+pub const BATCH_FILES_DIR: &str = "batches";
+/// Default for instruction exports and imports.
+pub const INSTRUCTIONS_DIR: &str = "instructions";
+/// Batch API requests are billed at half the synchronous price.
+pub const BATCH_PRICE_FACTOR: f64 = 0.5;
 pub const BETFAIR_PERCENTAGE: f64 = 0.975;
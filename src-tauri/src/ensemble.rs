@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+
+use anyhow::{
+    bail,
+    Context,
+    Result
+};
+use futures::future::join_all;
+use mongodb::{
+    bson::doc,
+    Database
+};
+
+use crate::{
+    cache::ResponseCache,
+    client::{
+        Execution,
+        OpenAIClient
+    },
+    constants::SETTINGS_COLLECTION,
+    models::{
+        EnsembleInfo,
+        EnsembleMethod,
+        EnsembleOptions,
+        FailedRequest,
+        PredictResponse,
+        PredictResults,
+        Prediction,
+        RequestsInfo,
+        Settings,
+        TestParams,
+        TestResults,
+        TokenUsage
+    },
+    utils::{
+        backtest,
+        load_model_info
+    }
+};
+
+/// Sends the same requests through several clients, one per model and
+/// sample, and merges their answers race by race.
+pub struct Ensemble {
+    clients: Vec<OpenAIClient>,
+    method: EnsembleMethod,
+}
+
+impl Ensemble {
+    /// Builds the members from the selected client and the saved settings
+    /// of every extra model. Requests are built once with the selected
+    /// instruction; profiles only change the model and its parameters.
+    /// Every member and sample shares the request limits of the selected
+    /// client.
+    pub async fn load(
+        database: &Database,
+        selected: OpenAIClient,
+        options: &EnsembleOptions
+    ) -> Result<Self> {
+        let samples = options.samples.max(1);
+        let cache_mode = selected.config().cache_mode;
+
+        let mut members = Vec::with_capacity(options.models.len() + 1);
+        for name in &options.models {
+            if name == &selected.model().id {
+                continue;
+            }
+
+            let mut config = database
+                .collection::<Settings>(SETTINGS_COLLECTION)
+                .find_one(doc! { "model": name.as_str() })
+                .await?
+                .with_context(|| format!("No settings for model {name}"))?;
+            config.cache_mode = cache_mode;
+
+            let model = load_model_info(database, name).await?;
+            members.push(
+                OpenAIClient::new(config, model)
+                    .with_cache(ResponseCache::new(database, cache_mode))
+                    .with_progress(selected.progress().clone())
+                    .with_limits_of(&selected)
+            );
+        }
+        members.insert(0, selected);
+
+        let clients = members
+            .iter()
+            .flat_map(|member| (0..samples).map(move |sample| member.with_sample(sample)))
+            .collect();
+
+        Ok(Self {
+            clients,
+            method: options.method
+        })
+    }
+
+    /// Runs every member. A request only counts as failed when no member
+    /// answered it.
+    pub async fn execute_requests(&self, requests: Vec<HashMap<String, serde_json::Value>>) -> Execution {
+        let runs = join_all(
+            self.clients
+                .iter()
+                .map(|c| c.execute_requests(requests.clone()))
+        ).await;
+
+        let mut usage = TokenUsage::default();
+        let mut answers = Vec::with_capacity(runs.len());
+        let mut failed: Option<Vec<FailedRequest>> = None;
+        for run in runs {
+            usage.add(&run.usage);
+            answers.push(run.predictions);
+            failed = Some(match failed {
+                None => run.failed,
+                Some(prev) => prev
+                    .into_iter()
+                    .filter(|f| run.failed.iter().any(|g| g.index == f.index))
+                    .collect()
+            });
+        }
+
+        Execution {
+            predictions: combine(answers, self.method),
+            usage,
+            failed: failed.unwrap_or_default()
+        }
+    }
+
+    pub async fn send_multiple(&self, requests: Vec<HashMap<String, serde_json::Value>>) -> Result<PredictResults> {
+        if requests.is_empty() {
+            bail!("No data to send");
+        }
+
+        let execution = self.execute_requests(requests).await;
+
+        Ok(PredictResults {
+            predictions: execution.predictions,
+            usage: execution.usage,
+            failed: execution.failed
+        })
+    }
+
+    pub async fn test(
+        &self,
+        requests_info: RequestsInfo,
+        database: &Database,
+        params: &TestParams
+    ) -> Result<TestResults> {
+        if requests_info.requests.is_empty() {
+            bail!("No data to send");
+        }
+
+        let execution = self.execute_requests(requests_info.requests.clone()).await;
+
+        let progress = self.clients[0].progress();
+        backtest(execution, requests_info, database, params, progress).await
+    }
+}
+
+/// Groups the answers of every run by race and merges each group.
+pub fn combine(runs: Vec<Vec<PredictResponse>>, method: EnsembleMethod) -> Vec<PredictResponse> {
+    let mut groups: Vec<(String, Vec<PredictResponse>)> = Vec::new();
+    for p in runs.into_iter().flatten() {
+        let key = race_key(&p);
+        match groups.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, group)) => group.push(p),
+            None => groups.push((key, vec![p])),
+        }
+    }
+
+    let mut combined: Vec<PredictResponse> = groups
+        .into_iter()
+        .map(|(_, group)| combine_race(group, method))
+        .collect();
+    combined.sort_by_key(|p| (p.meta.date, p.meta.time));
+
+    combined
+}
+
+fn race_key(p: &PredictResponse) -> String {
+    match p.race_id {
+        Some(id) => id.to_string(),
+        None => format!("{} {} {}", p.meta.date, p.meta.time, p.meta.track),
+    }
+}
+
+fn combine_race(members: Vec<PredictResponse>, method: EnsembleMethod) -> PredictResponse {
+    let mut names: Vec<&str> = Vec::new();
+    for p in &members {
+        for d in &p.predictions {
+            if !names.contains(&d.name.as_str()) {
+                names.push(&d.name);
+            }
+        }
+    }
+
+    let n = names.len();
+    let find = |p: &'_ PredictResponse, name: &str| p.predictions.iter().find(|d| d.name == name).cloned();
+    // A dog left out of an answer is treated as ranked last with no chance.
+    let ranks = |name: &str| -> Vec<f32> {
+        members
+            .iter()
+            .map(|p| find(p, name).map_or(n as f32, |d| d.rank as f32))
+            .collect()
+    };
+
+    let mut dogs: Vec<(Prediction, f32)> = names
+        .iter()
+        .map(|name| {
+            let found: Vec<Prediction> = members.iter().filter_map(|p| find(p, name)).collect();
+            let ranks = ranks(name);
+
+            let percentage = found.iter().map(|d| d.percentage).sum::<f32>() / members.len() as f32;
+            let raw_score = found.iter().map(|d| d.raw_score).sum::<f32>() / found.len() as f32;
+            let comment = found.iter().find_map(|d| d.comment.clone());
+            let confidence = mean(found.iter().filter_map(|d| d.confidence));
+
+            let score = match method {
+                EnsembleMethod::MeanProbability => percentage,
+                EnsembleMethod::Borda => ranks.iter().map(|r| n as f32 - r).sum(),
+                EnsembleMethod::MedianRank => -median(ranks),
+            };
+
+            let prediction = Prediction {
+                name: name.to_string(),
+                raw_score,
+                percentage,
+                rank: 0,
+                comment,
+                confidence
+            };
+            (prediction, score)
+        })
+        .collect();
+
+    dogs.sort_by(|a, b| b.1.total_cmp(&a.1).then(b.0.percentage.total_cmp(&a.0.percentage)));
+    for (i, (d, _)) in dogs.iter_mut().enumerate() {
+        d.rank = i as u8 + 1;
+    }
+
+    let agreement = kendall_w(&names.iter().map(|name| ranks(name)).collect::<Vec<_>>());
+    let usage = members
+        .iter()
+        .filter_map(|p| p.usage)
+        .reduce(|mut total, u| {
+            total.add(&u);
+            total
+        });
+
+    let first = &members[0];
+    PredictResponse {
+        race_id: first.race_id,
+        meta: first.meta.clone(),
+        predictions: dogs.into_iter().map(|(d, _)| d).collect(),
+        summary: first.summary.clone(),
+        usage,
+        ensemble: Some(EnsembleInfo {
+            method,
+            members: members.len(),
+            agreement
+        }),
+        confidence: mean(members.iter().filter_map(|p| p.confidence)),
+        instruction: first.instruction.clone()
+    }
+}
+
+fn mean(values: impl Iterator<Item = f32>) -> Option<f32> {
+    let (sum, count) = values.fold((0.0, 0), |(sum, count), v| (sum + v, count + 1));
+    (count > 0).then(|| sum / count as f32)
+}
+
+fn median(mut values: Vec<f32>) -> f32 {
+    values.sort_by(f32::total_cmp);
+    let mid = values.len() / 2;
+
+    if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+/// Kendall's coefficient of concordance for `ranks[dog][member]`.
+fn kendall_w(ranks: &[Vec<f32>]) -> f32 {
+    let n = ranks.len() as f32;
+    let m = ranks.first().map_or(0, Vec::len) as f32;
+    if n < 2.0 || m < 2.0 {
+        return 1.0;
+    }
+
+    let mean = m * (n + 1.0) / 2.0;
+    let s: f32 = ranks
+        .iter()
+        .map(|r| (r.iter().sum::<f32>() - mean).powi(2))
+        .sum();
+
+    (12.0 * s / (m * m * (n.powi(3) - n))).clamp(0.0, 1.0)
+}
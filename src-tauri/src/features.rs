@@ -0,0 +1,486 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        Mutex
+    }
+};
+
+use chrono::NaiveDate;
+use mongodb::{
+    bson::{
+        to_bson,
+        Bson,
+        Document
+    },
+    Database
+};
+use serde::Serialize;
+
+use crate::{
+    models::{
+        DateWindow,
+        DogRaceInfo,
+        RaceCard
+    },
+    ratings,
+    standards,
+    traps
+};
+
+/// Most recent runs taken into account.
+pub const MAX_RUNS: usize = 6;
+/// Weight of each older run relative to the one after it.
+const RECENCY_DECAY: f64 = 0.8;
+
+/// One past run of a dog, read from a form line or a `dog_race_info`
+/// record.
+#[derive(Debug, Clone, Default)]
+pub struct Run {
+    pub date: Option<NaiveDate>,
+    pub track: Option<String>,
+    pub distance: Option<f64>,
+    pub trap: Option<u32>,
+    pub position: Option<f64>,
+    pub time: Option<f64>,
+    pub sectional: Option<f64>,
+    pub winners_time: Option<f64>,
+    /// Seconds the going added to the time.
+    pub going: f64,
+    pub weight: Option<f64>,
+    pub grade: Option<String>,
+    /// Positions at the bends, e.g. `3221`.
+    pub bends: Option<String>,
+    pub comment: Option<String>,
+}
+
+impl Run {
+    pub fn from_form(form: &Document) -> Self {
+        let positive = |key| number(form.get(key)).filter(|v| *v > 0.0);
+        let text = |key| form.get_str(key).ok().map(str::trim).filter(|s| !s.is_empty()).map(str::to_string);
+        Self {
+            date: form.get_str("raceDate").ok().and_then(parse_date),
+            track: text("trackName"),
+            distance: positive("distance"),
+            trap: ["trap", "trapNumber"]
+                .into_iter()
+                .find_map(|key| number(form.get(key)))
+                .map(|t| t as u32),
+            position: positive("resultPosition"),
+            time: positive("resultRunTime"),
+            sectional: positive("sectionalTime"),
+            winners_time: positive("raceWinnersTime"),
+            going: number(form.get("goingType")).unwrap_or(0.0) / 100.0,
+            weight: positive("resultDogWeight"),
+            grade: text("raceClass"),
+            bends: text("bndPos"),
+            comment: text("raceComment"),
+        }
+    }
+
+    pub fn from_info(info: &DogRaceInfo, winners_time: Option<f64>) -> Self {
+        let positive = |v: Option<f32>| v.map(f64::from).filter(|v| *v > 0.0);
+        Self {
+            date: chrono::DateTime::from_timestamp_millis(info.race_date_time.timestamp_millis())
+                .map(|dt| dt.date_naive()),
+            track: info.track_name.clone(),
+            distance: Some(info.distance as f64).filter(|d| *d > 0.0),
+            trap: info.trap_number,
+            position: Some(info.result_position as f64).filter(|p| *p > 0.0),
+            time: positive(info.result_run_time),
+            sectional: positive(info.result_sectional_time),
+            winners_time,
+            going: info.race_going.unwrap_or(0) as f64 / 100.0,
+            weight: positive(info.result_dog_weight),
+            grade: info.race_class.clone(),
+            bends: None,
+            comment: info.result_comment.clone(),
+        }
+    }
+
+    /// Time with the going taken out.
+    fn adjusted_time(&self) -> Option<f64> {
+        Some(standards::adjusted(self.time?, self.going))
+    }
+
+    /// Speed figure against the standard time of the track and distance
+    /// going into a race on `date`.
+    pub fn figure(&self, standards: &standards::Table, date: NaiveDate) -> Option<f64> {
+        standards.figure(self.track.as_deref()?, self.distance? as u32, self.adjusted_time()?, date)
+    }
+}
+
+/// The race the features are computed for.
+pub struct Conditions<'a> {
+    pub date: NaiveDate,
+    pub track: &'a str,
+    pub distance: u32,
+    pub grade: Option<&'a str>,
+}
+
+/// What the form of a runner says before a race. `None` where it does not
+/// tell, e.g. no earlier run at this track and distance.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RunnerFeatures {
+    pub name: String,
+    /// Trap drawn today.
+    pub trap: Option<u32>,
+    pub runs: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub days_since_last_run: Option<f64>,
+    /// Last weight against the average of the runs before it, in kg.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub weight_change: Option<f64>,
+    /// Going-adjusted calc times at this track and distance.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub average_time: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub best_time: Option<f64>,
+    /// First sectional at this distance.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sectional_average: Option<f64>,
+    /// Average position at the first bend, lower is faster away. Only
+    /// scraped form lines have bend positions, never backtest ones.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub early_pace: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub average_position: Option<f64>,
+    /// Standard deviation of the finishing positions.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finish_consistency: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub win_rate: Option<f64>,
+    /// Today's grade number minus the last one within the same grade
+    /// letter; positive means dropped in class.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grade_change: Option<f64>,
+    /// -1 always on the rail, 1 always wide, from the race comments.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rail_preference: Option<f64>,
+    /// Recency-weighted going-adjusted speed in m/s.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speed: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speed_at_distance: Option<f64>,
+    /// Recency-weighted seconds per 100m behind the winner.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub winner_gap: Option<f64>,
+    /// Recency-weighted speed figures, comparable across tracks.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speed_figure: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub best_figure: Option<f64>,
+    /// How often today's trap wins at this track and distance, from the
+    /// stored trap bias figures.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trap_win_rate: Option<f64>,
+    /// Glicko rating going into the race and its deviation, from the
+    /// stored ratings.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rating: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rating_deviation: Option<f64>,
+}
+
+impl RunnerFeatures {
+    /// Features from the runs of a dog, newest first. Runs on or after
+    /// the race date are ignored.
+    pub fn compute(
+        name: &str,
+        trap: Option<u32>,
+        history: &[Run],
+        race: &Conditions,
+        standards: &standards::Table
+    ) -> Self {
+        let runs: Vec<&Run> = history
+            .iter()
+            .filter(|r| r.date.is_none_or(|d| d < race.date))
+            .take(MAX_RUNS)
+            .collect();
+        let at_distance = |r: &&&Run| r.distance.map(|d| d as u32) == Some(race.distance);
+        let at_track = |r: &&&Run| r.track.as_deref().is_some_and(|t| t.eq_ignore_ascii_case(race.track));
+
+        let positions: Vec<f64> = runs.iter().filter_map(|r| r.position).collect();
+        let course_times: Vec<f64> = runs
+            .iter()
+            .filter(at_distance)
+            .filter(at_track)
+            .filter_map(|r| r.adjusted_time())
+            .collect();
+
+        Self {
+            name: name.to_string(),
+            trap,
+            runs: runs.len(),
+            days_since_last_run: runs
+                .first()
+                .and_then(|r| r.date)
+                .map(|d| (race.date - d).num_days() as f64),
+            weight_change: runs.first().and_then(|r| r.weight).and_then(|last| {
+                mean(runs.iter().skip(1).filter_map(|r| r.weight)).map(|before| last - before)
+            }),
+            average_time: mean(course_times.iter().copied()),
+            best_time: course_times.iter().copied().reduce(f64::min),
+            sectional_average: mean(runs.iter().filter(at_distance).filter_map(|r| r.sectional)),
+            early_pace: mean(runs.iter().filter_map(|r| first_bend(r.bends.as_deref()?))),
+            average_position: mean(positions.iter().copied()),
+            finish_consistency: (positions.len() > 1).then(|| deviation(&positions)),
+            win_rate: (!positions.is_empty())
+                .then(|| positions.iter().filter(|p| **p == 1.0).count() as f64 / positions.len() as f64),
+            grade_change: race.grade.and_then(|today| {
+                grade_change(today, runs.iter().find_map(|r| r.grade.as_deref())?)
+            }),
+            rail_preference: mean(runs.iter().filter_map(|r| running_line(r.comment.as_deref()?))),
+            speed: weighted(runs.iter().map(|r| Some(r.distance? / r.adjusted_time()?))),
+            speed_at_distance: mean(
+                runs.iter()
+                    .filter(at_distance)
+                    .filter_map(|r| Some(r.distance? / r.adjusted_time()?))
+            ),
+            winner_gap: weighted(
+                runs.iter()
+                    .map(|r| Some((r.time? - r.winners_time?).max(0.0) / r.distance? * 100.0))
+            ),
+            speed_figure: weighted(runs.iter().map(|r| r.figure(standards, race.date))),
+            best_figure: runs.iter().filter_map(|r| r.figure(standards, race.date)).reduce(f64::max),
+            trap_win_rate: None,
+            rating: None,
+            rating_deviation: None,
+        }
+    }
+}
+
+/// Features of every runner of a race, in card order.
+pub type RaceFeatures = Vec<RunnerFeatures>;
+
+/// Runner features of the races of one job, each race computed once, and
+/// the stored figures they use. Every predict, backtest, export or
+/// training builds its own, so concurrent jobs never read or clear each
+/// other's figures.
+#[derive(Default)]
+pub struct FeatureContext {
+    standards: standards::Table,
+    traps: traps::Table,
+    ratings: ratings::Table,
+    cache: Mutex<HashMap<u64, Arc<RaceFeatures>>>,
+}
+
+impl FeatureContext {
+    /// Context for `races`, with the stored trap bias figures, standard
+    /// times and ratings the features are computed with. Missing ones
+    /// only leave their features out.
+    pub async fn load(database: &Database, races: &[Document]) -> Self {
+        let names = races
+            .iter()
+            .flat_map(dogs)
+            .filter_map(|dog| dog.get_str("dogName").ok())
+            .collect();
+
+        Self::for_dogs(database, names).await
+    }
+
+    /// `load` for races known by the names of their runners, e.g. settled
+    /// races read from `dog_race_info`.
+    pub async fn for_dogs(database: &Database, mut names: Vec<&str>) -> Self {
+        let standards = standards::Table::load(database).await.unwrap_or_else(|err| {
+            log::warn!("Standard times not loaded: {err}");
+            Default::default()
+        });
+        let traps = traps::Table::load(database).await.unwrap_or_else(|err| {
+            log::warn!("Trap bias figures not loaded: {err}");
+            Default::default()
+        });
+        names.sort_unstable();
+        names.dedup();
+        let ratings = ratings::Table::load(database, &names).await.unwrap_or_else(|err| {
+            log::warn!("Ratings not loaded: {err}");
+            Default::default()
+        });
+
+        Self { standards, traps, ratings, ..Default::default() }
+    }
+
+    /// Features of one runner from its runs, newest first, with its trap
+    /// figures and rating as of the race date.
+    pub fn runner(&self, name: &str, trap: Option<u32>, history: &[Run], race: &Conditions) -> RunnerFeatures {
+        let mut features = RunnerFeatures::compute(name, trap, history, race, &self.standards);
+        features.trap_win_rate = trap
+            .and_then(|trap| self.traps.lookup(race.track, race.distance, race.grade, trap, race.date))
+            .map(|stats| stats.win_rate);
+        if let Some((rating, deviation)) = self.ratings.get(name, race.date) {
+            features.rating = Some(rating);
+            features.rating_deviation = Some(deviation);
+        }
+
+        features
+    }
+
+    /// Features of the runners of a race document, computed once per race
+    /// id. `None` when the document is not a race card.
+    pub fn for_race(&self, race: &Document) -> Option<Arc<RaceFeatures>> {
+        let card = RaceCard::from_document(race)?;
+        if let Some(cached) = self.cache.lock().expect("features lock poisoned").get(&card.race_id) {
+            return Some(cached.clone());
+        }
+
+        let conditions = Conditions {
+            date: card.date,
+            track: &card.track,
+            distance: card.distance,
+            grade: card.grade.as_deref(),
+        };
+        let features: Arc<RaceFeatures> = Arc::new(
+            dogs(race)
+                .filter_map(|dog| {
+                    let name = dog.get_str("dogName").ok()?;
+                    let trap = number(dog.get("trapNumber")).map(|t| t as u32);
+                    let history: Vec<Run> = forms(dog).into_iter().map(Run::from_form).collect();
+                    Some(self.runner(name, trap, &history, &conditions))
+                })
+                .collect()
+        );
+
+        // Races without an id would all share one entry.
+        if card.race_id != 0 {
+            self.cache
+                .lock()
+                .expect("features lock poisoned")
+                .insert(card.race_id, features.clone());
+        }
+
+        Some(features)
+    }
+
+    /// Windows of the standard times races before `date` can use.
+    pub fn standard_windows(&self, date: NaiveDate) -> Vec<DateWindow> {
+        self.standards.windows(date)
+    }
+
+    /// Adds a `features` document to every dog of the race and a
+    /// `speedFigure` to every form line with a standard, for prompts and
+    /// exports. The figures are computed as of the race date, so they may
+    /// use newer standards than the ones stored by `standards::compute`,
+    /// which are figured as of the day of the run.
+    pub fn annotate(&self, race: &mut Document) {
+        let (Some(card), Some(features)) = (RaceCard::from_document(race), self.for_race(race)) else {
+            return;
+        };
+        let Ok(dogs) = race.get_array_mut("dogs") else {
+            return;
+        };
+
+        for dog in dogs.iter_mut().filter_map(Bson::as_document_mut) {
+            let Ok(name) = dog.get_str("dogName") else {
+                continue;
+            };
+            if let Some(Ok(value)) = features.iter().find(|f| f.name == name).map(to_bson) {
+                dog.insert("features", value);
+            }
+
+            let forms = match dog.get_document_mut("form") {
+                Ok(form) => form.get_array_mut("forms"),
+                Err(_) => dog.get_array_mut("forms"),
+            };
+            for form in forms.into_iter().flatten().filter_map(Bson::as_document_mut) {
+                if let Some(figure) = Run::from_form(form).figure(&self.standards, card.date) {
+                    form.insert("speedFigure", (figure * 10.0).round() / 10.0);
+                }
+            }
+        }
+    }
+}
+
+pub fn dogs(race: &Document) -> impl Iterator<Item = &Document> {
+    race.get_array("dogs")
+        .into_iter()
+        .flatten()
+        .filter_map(Bson::as_document)
+}
+
+/// Recent runs of a dog: `form.forms` on scraped races, `forms` on
+/// backtest races.
+pub fn forms(dog: &Document) -> Vec<&Document> {
+    dog.get_array("forms")
+        .or_else(|_| dog.get_document("form").and_then(|f| f.get_array("forms")))
+        .map(|forms| forms.iter().filter_map(Bson::as_document).collect())
+        .unwrap_or_default()
+}
+
+pub fn number(value: Option<&Bson>) -> Option<f64> {
+    match value? {
+        Bson::Double(v) => Some(*v),
+        Bson::Int32(v) => Some(*v as f64),
+        Bson::Int64(v) => Some(*v as f64),
+        Bson::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn parse_date(raw: &str) -> Option<NaiveDate> {
+    let raw = raw.trim();
+    ["%Y-%m-%d", "%d/%m/%Y", "%d%b%y", "%d %b %Y"]
+        .iter()
+        .find_map(|format| NaiveDate::parse_from_str(raw, format).ok())
+        .or_else(|| NaiveDate::parse_from_str(raw.get(..10)?, "%Y-%m-%d").ok())
+}
+
+fn mean(values: impl Iterator<Item = f64>) -> Option<f64> {
+    let (sum, count) = values.fold((0.0, 0), |(sum, count), v| (sum + v, count + 1));
+    (count > 0).then(|| sum / count as f64)
+}
+
+/// Recency-weighted mean of values given newest first; missing values
+/// still age the ones after them.
+fn weighted(values: impl Iterator<Item = Option<f64>>) -> Option<f64> {
+    let (mut sum, mut weights, mut weight) = (0.0, 0.0, 1.0);
+    for value in values {
+        if let Some(value) = value {
+            sum += weight * value;
+            weights += weight;
+        }
+        weight *= RECENCY_DECAY;
+    }
+    (weights > 0.0).then(|| sum / weights)
+}
+
+fn deviation(values: &[f64]) -> f64 {
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    (values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / values.len() as f64).sqrt()
+}
+
+fn first_bend(bends: &str) -> Option<f64> {
+    bends.chars().find_map(|c| c.to_digit(10)).map(f64::from)
+}
+
+/// Grade letters and number, `A3` -> (`A`, 3).
+fn split_grade(grade: &str) -> Option<(&str, u32)> {
+    let grade = grade.trim();
+    let digits = grade.find(|c: char| c.is_ascii_digit())?;
+    Some((&grade[..digits], grade[digits..].parse().ok()?))
+}
+
+fn grade_change(today: &str, last: &str) -> Option<f64> {
+    let (today_class, today_number) = split_grade(today)?;
+    let (last_class, last_number) = split_grade(last)?;
+    today_class
+        .eq_ignore_ascii_case(last_class)
+        .then_some(today_number as f64 - last_number as f64)
+}
+
+/// Where the comment puts the dog: -1 on the rail, 0 in the middle, 1
+/// wide. Comments look like `QAw,Rls,ALed`.
+fn running_line(comment: &str) -> Option<f64> {
+    comment.split([',', ' ']).find_map(|part| {
+        let part = part.trim();
+        if part.contains("Rls") || part.contains("RIs") {
+            Some(-1.0)
+        } else if part == "W" || part.starts_with("Wd") || part.starts_with("Wide") || part.ends_with("W") {
+            Some(1.0)
+        } else if part == "Mid" || part.starts_with("Mid") {
+            Some(0.0)
+        } else {
+            None
+        }
+    })
+}
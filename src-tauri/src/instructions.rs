@@ -0,0 +1,508 @@
+use std::{
+    collections::HashSet,
+    path::{
+        Path,
+        PathBuf
+    }
+};
+
+use anyhow::{
+    bail,
+    Context,
+    Result
+};
+use futures::TryStreamExt;
+use mongodb::{
+    bson::{
+        doc,
+        oid::ObjectId,
+        DateTime,
+        Document
+    },
+    Collection,
+    Database
+};
+
+use crate::{
+    constants::{
+        INSTRUCTION_COLLECTION,
+        SETTINGS_COLLECTION
+    },
+    models::{
+        DiffKind,
+        DiffRow,
+        ImportSummary,
+        InstructionDoc,
+        RaceCard
+    },
+    utils::{
+        is_duplicate_key,
+        unique_index
+    }
+};
+
+/// Placeholders an instruction can use, rendered from the races in the
+/// request. Requests with several races join distinct values with ", ".
+pub const PLACEHOLDERS: [&str; 7] = ["track", "distance", "grade", "runners", "date", "time", "races"];
+
+fn collection(database: &Database) -> Collection<InstructionDoc> {
+    database.collection(INSTRUCTION_COLLECTION)
+}
+
+pub async fn exists(database: &Database, name: &str) -> Result<bool> {
+    let count = collection(database)
+        .count_documents(doc! { "name": name })
+        .limit(1)
+        .await?;
+
+    Ok(count > 0)
+}
+
+/// A given version of the instruction, or the newest one.
+pub async fn load(database: &Database, name: &str, version: Option<u32>) -> Result<InstructionDoc> {
+    match version {
+        Some(version) => collection(database)
+            .find_one(doc! { "name": name, "version": version as i64 })
+            .await?
+            .with_context(|| format!("Instruction {name} has no version {version}")),
+        None => latest(database, name).await,
+    }
+}
+
+/// Newest version of the instruction.
+pub async fn latest(database: &Database, name: &str) -> Result<InstructionDoc> {
+    newest(database, name)
+        .await?
+        .with_context(|| format!("Not instruction with such name: {name}"))
+}
+
+/// Newest version of the instruction, `None` when there is none yet.
+async fn newest(database: &Database, name: &str) -> Result<Option<InstructionDoc>> {
+    Ok(collection(database)
+        .find_one(doc! { "name": name })
+        .sort(doc! { "version": -1, "created_at": -1 })
+        .await?)
+}
+
+/// Every version of the instruction, newest first.
+pub async fn history(database: &Database, name: &str) -> Result<Vec<InstructionDoc>> {
+    let versions = collection(database)
+        .find(doc! { "name": name })
+        .sort(doc! { "version": -1, "created_at": -1 })
+        .await?
+        .try_collect()
+        .await?;
+
+    Ok(versions)
+}
+
+/// Saves taken by concurrent ones before giving up.
+const MAX_SAVE_ATTEMPTS: usize = 5;
+
+/// Numbers the documents written before versioning, oldest first, and
+/// guards versions with a unique `{name, version}` index. Run once at
+/// startup; a migrated collection is left as it is.
+///
+/// A name's unversioned documents take the numbers right below its first
+/// version, so the newest of them stays version 0 where versions were
+/// already saved on top of it, and start at 1 otherwise.
+pub async fn migrate(database: &Database) -> Result<()> {
+    let raw = database.collection::<Document>(INSTRUCTION_COLLECTION);
+    let legacy: Vec<Document> = raw
+        .find(doc! { "version": { "$exists": false } })
+        .sort(doc! { "name": 1, "created_at": 1, "_id": 1 })
+        .await?
+        .try_collect()
+        .await?;
+
+    let mut by_name: Vec<(String, Vec<ObjectId>)> = Vec::new();
+    for document in &legacy {
+        let (Ok(name), Ok(id)) = (document.get_str("name"), document.get_object_id("_id")) else {
+            continue;
+        };
+        match by_name.last_mut() {
+            Some((last, ids)) if last == name => ids.push(id),
+            _ => by_name.push((name.to_string(), vec![id])),
+        }
+    }
+
+    for (name, ids) in by_name {
+        let first_version = raw
+            .find_one(doc! { "name": &name, "version": { "$exists": true } })
+            .sort(doc! { "version": 1 })
+            .await?
+            .and_then(|d| d.get_i64("version").ok().or_else(|| d.get_i32("version").ok().map(i64::from)));
+        let start = match first_version {
+            Some(first) if first >= ids.len() as i64 => first - ids.len() as i64,
+            Some(_) => {
+                let last = newest(database, &name).await?.map_or(0, |doc| doc.version);
+                log::warn!("Instruction {name}: old documents numbered after version {last}");
+                i64::from(last) + 1
+            }
+            None => 1,
+        };
+        for (version, id) in (start..).zip(&ids) {
+            raw.update_one(doc! { "_id": id }, doc! { "$set": { "version": version } }).await?;
+        }
+        log::info!("Instruction {name}: {} old documents numbered from version {start}", ids.len());
+    }
+
+    unique_index(&collection(database), doc! { "name": 1, "version": 1 }).await
+}
+
+/// Stores `content` as the next version of `name`, the first one for a
+/// new name. Versions are never modified, so results can always be traced
+/// to the exact text.
+pub async fn save_version(
+    database: &Database,
+    name: &str,
+    content: &str,
+    author: Option<String>
+) -> Result<InstructionDoc> {
+    let name = name.trim();
+    if name.is_empty() {
+        bail!("Instruction name is empty");
+    }
+
+    for _ in 0..MAX_SAVE_ATTEMPTS {
+        let version = newest(database, name).await?.map_or(1, |doc| doc.version + 1);
+        match insert(database, name, content, version, author.clone()).await {
+            Err(err) if err.downcast_ref().is_some_and(is_duplicate_key) => continue,
+            saved => return saved,
+        }
+    }
+
+    bail!("Instruction {name} is being saved concurrently, try again")
+}
+
+/// Adds an instruction under a name that is not taken yet.
+pub async fn create(
+    database: &Database,
+    name: &str,
+    content: &str,
+    author: Option<String>
+) -> Result<InstructionDoc> {
+    let name = name.trim();
+    if name.is_empty() {
+        bail!("Instruction name is empty");
+    }
+
+    // Version 1 is unique, so of two concurrent creates only one succeeds.
+    match insert(database, name, content, 1, author).await {
+        Err(err) if err.downcast_ref().is_some_and(is_duplicate_key) => {
+            bail!("Instruction {name} already exists")
+        }
+        created => created,
+    }
+}
+
+/// Inserts one version; fails on a version that exists already.
+async fn insert(
+    database: &Database,
+    name: &str,
+    content: &str,
+    version: u32,
+    author: Option<String>
+) -> Result<InstructionDoc> {
+    let author = author
+        .filter(|a| !a.trim().is_empty())
+        .or_else(|| std::env::var("USER").or_else(|_| std::env::var("USERNAME")).ok());
+
+    let doc = InstructionDoc {
+        name: name.to_string(),
+        content: content.to_string(),
+        version,
+        author,
+        created_at: Some(DateTime::now()),
+    };
+    collection(database).insert_one(&doc).await?;
+
+    Ok(doc)
+}
+
+/// Saves a new version of an existing instruction. Content equal to the
+/// newest version does not create a version.
+pub async fn update(
+    database: &Database,
+    name: &str,
+    content: &str,
+    author: Option<String>
+) -> Result<InstructionDoc> {
+    let current = latest(database, name).await?;
+    if current.content == content {
+        return Ok(current);
+    }
+
+    save_version(database, name, content, author).await
+}
+
+/// Renames every version and the settings that select the instruction.
+pub async fn rename(database: &Database, name: &str, new_name: &str) -> Result<()> {
+    let new_name = new_name.trim();
+    if new_name.is_empty() {
+        bail!("Instruction name is empty");
+    }
+    if !exists(database, name).await? {
+        bail!("Not instruction with such name: {name}");
+    }
+    if exists(database, new_name).await? {
+        bail!("Instruction {new_name} already exists");
+    }
+
+    collection(database)
+        .update_many(doc! { "name": name }, doc! { "$set": { "name": new_name } })
+        .await?;
+    database
+        .collection::<Document>(SETTINGS_COLLECTION)
+        .update_many(
+            doc! { "instruction_name": name },
+            doc! { "$set": { "instruction_name": new_name } }
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Deletes every version. Instructions still selected in settings are kept.
+pub async fn delete(database: &Database, name: &str) -> Result<u64> {
+    let users: Vec<String> = database
+        .collection::<Document>(SETTINGS_COLLECTION)
+        .find(doc! { "instruction_name": name })
+        .await?
+        .try_collect::<Vec<Document>>()
+        .await?
+        .iter()
+        .filter_map(|d| d.get_str("model").ok().map(str::to_string))
+        .collect();
+    if !users.is_empty() {
+        bail!("Instruction {name} is used by settings for {}", users.join(", "));
+    }
+
+    let res = collection(database)
+        .delete_many(doc! { "name": name })
+        .await?;
+
+    Ok(res.deleted_count)
+}
+
+/// Line diff of two texts as side-by-side rows. A run of removed lines
+/// followed by added lines is paired into `Changed` rows.
+pub fn diff(left: &str, right: &str) -> Vec<DiffRow> {
+    let a: Vec<&str> = left.lines().collect();
+    let b: Vec<&str> = right.lines().collect();
+
+    // lcs[i][j] = length of the LCS of a[i..] and b[j..]
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut rows = Vec::new();
+    let (mut removed, mut added): (Vec<&str>, Vec<&str>) = (Vec::new(), Vec::new());
+    let flush = |rows: &mut Vec<DiffRow>, removed: &mut Vec<&str>, added: &mut Vec<&str>| {
+        let paired = removed.len().min(added.len());
+        for k in 0..removed.len().max(added.len()) {
+            let kind = if k < paired {
+                DiffKind::Changed
+            } else if k < removed.len() {
+                DiffKind::Removed
+            } else {
+                DiffKind::Added
+            };
+            rows.push(DiffRow {
+                kind,
+                left: removed.get(k).map(|s| s.to_string()),
+                right: added.get(k).map(|s| s.to_string()),
+            });
+        }
+        removed.clear();
+        added.clear();
+    };
+
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            flush(&mut rows, &mut removed, &mut added);
+            rows.push(DiffRow {
+                kind: DiffKind::Same,
+                left: Some(a[i].to_string()),
+                right: Some(b[j].to_string()),
+            });
+            i += 1;
+            j += 1;
+        } else if j < b.len() && (i == a.len() || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            added.push(b[j]);
+            j += 1;
+        } else {
+            removed.push(a[i]);
+            i += 1;
+        }
+    }
+    flush(&mut rows, &mut removed, &mut added);
+
+    rows
+}
+
+/// Writes the newest version of every instruction to `<dir>/<name>.md`.
+pub async fn export_markdown(database: &Database, dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut names: Vec<String> = collection(database)
+        .distinct("name", doc! {})
+        .await?
+        .into_iter()
+        .filter_map(|n| n.as_str().map(str::to_string))
+        .collect();
+    names.sort();
+
+    tokio::fs::create_dir_all(dir)
+        .await
+        .with_context(|| format!("Failed to create {}", dir.display()))?;
+    let mut stems = HashSet::new();
+    let mut paths = Vec::with_capacity(names.len());
+    for name in names {
+        let doc = latest(database, &name).await?;
+        let path = dir.join(format!("{}.md", unique_stem(&name, &mut stems)));
+        tokio::fs::write(&path, to_markdown(&doc))
+            .await
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        paths.push(path);
+    }
+
+    Ok(paths)
+}
+
+/// Reads every `.md` file in `dir`. New names are created, changed
+/// content becomes a new version, identical content is skipped.
+pub async fn import_markdown(database: &Database, dir: &Path, author: Option<String>) -> Result<ImportSummary> {
+    let mut entries = tokio::fs::read_dir(dir)
+        .await
+        .with_context(|| format!("Failed to read {}", dir.display()))?;
+    let mut files: Vec<PathBuf> = Vec::new();
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path.extension().is_some_and(|ext| ext == "md") {
+            files.push(path);
+        }
+    }
+    files.sort();
+
+    let mut summary = ImportSummary::default();
+    for path in files {
+        let text = tokio::fs::read_to_string(&path)
+            .await
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or_default();
+        let (name, content) = from_markdown(&text, stem);
+
+        if !exists(database, &name).await? {
+            create(database, &name, &content, author.clone()).await?;
+            summary.created.push(name);
+        } else if latest(database, &name).await?.content != content {
+            update(database, &name, &content, author.clone()).await?;
+            summary.updated.push(name);
+        } else {
+            summary.unchanged.push(name);
+        }
+    }
+
+    Ok(summary)
+}
+
+fn to_markdown(doc: &InstructionDoc) -> String {
+    format!(
+        "---\nname: {}\nversion: {}\nauthor: {}\n---\n\n{}\n",
+        doc.name,
+        doc.version,
+        doc.author.as_deref().unwrap_or_default(),
+        doc.content
+    )
+}
+
+/// Splits the front matter written by `to_markdown` from the content.
+/// Files without front matter are named after the file.
+fn from_markdown(text: &str, file_stem: &str) -> (String, String) {
+    let text = text.replace("\r\n", "\n");
+    let parsed = text
+        .strip_prefix("---\n")
+        .and_then(|rest| rest.split_once("\n---\n"));
+
+    let Some((front, body)) = parsed else {
+        return (file_stem.to_string(), text.trim_end_matches('\n').to_string());
+    };
+
+    let name = front
+        .lines()
+        .find_map(|l| l.strip_prefix("name:"))
+        .map(|n| n.trim().to_string())
+        .filter(|n| !n.is_empty())
+        .unwrap_or_else(|| file_stem.to_string());
+    let body = body.strip_prefix('\n').unwrap_or(body);
+    let content = body.strip_suffix('\n').unwrap_or(body).to_string();
+
+    (name, content)
+}
+
+fn file_stem(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_alphanumeric() || matches!(c, '-' | '_' | '.') { c } else { '_' })
+        .collect()
+}
+
+/// `file_stem` of the name, suffixed with `-2`, `-3`, ... when another
+/// name already maps to it. Case is ignored, as on most desktop file
+/// systems. The name itself survives in the front matter.
+fn unique_stem(name: &str, taken: &mut HashSet<String>) -> String {
+    let stem = file_stem(name);
+    let mut candidate = stem.clone();
+    let mut n = 1;
+    while !taken.insert(candidate.to_lowercase()) {
+        n += 1;
+        candidate = format!("{stem}-{n}");
+    }
+
+    candidate
+}
+
+/// Substitutes `{{placeholder}}` with values from the race cards.
+/// Unknown placeholders are left as they are.
+pub fn render(template: &str, cards: &[RaceCard]) -> String {
+    let mut rendered = template.to_string();
+
+    for name in PLACEHOLDERS {
+        let token = format!("{{{{{name}}}}}");
+        if !rendered.contains(&token) {
+            continue;
+        }
+
+        let values: Vec<String> = match name {
+            "track" => cards.iter().map(|c| c.track.clone()).collect(),
+            "distance" => cards.iter().map(|c| c.distance.to_string()).collect(),
+            "grade" => cards.iter().filter_map(|c| c.grade.clone()).collect(),
+            "runners" => cards.iter().map(|c| c.runners.len().to_string()).collect(),
+            "date" => cards.iter().map(|c| c.date.to_string()).collect(),
+            "time" => cards.iter().map(|c| c.time.format("%H:%M").to_string()).collect(),
+            "races" => vec![cards.len().to_string()],
+            _ => unreachable!(),
+        };
+
+        let mut distinct: Vec<String> = Vec::with_capacity(values.len());
+        for v in values {
+            if !distinct.contains(&v) {
+                distinct.push(v);
+            }
+        }
+
+        rendered = rendered.replace(&token, &distinct.join(", "));
+    }
+
+    if rendered.contains("{{") {
+        log::warn!("Instruction has unknown placeholders; known: {}", PLACEHOLDERS.join(", "));
+    }
+
+    rendered
+}
@@ -6,6 +6,27 @@ pub mod scrapper;
 pub mod client;
 pub mod utils;
 pub mod tester;
+pub mod cache;
+pub mod batch;
+pub mod retry;
+pub mod validation;
+pub mod ensemble;
+pub mod confidence;
+pub mod instructions;
+pub mod prompt;
+pub mod schema;
+pub mod progress;
+pub mod features;
+pub mod speed;
+pub mod baseline;
+pub mod market;
+pub mod logit;
+pub mod traps;
+pub mod standards;
+pub mod ratings;
+pub mod strategy;
+pub mod staking;
+pub mod window;
 
 use anyhow::Result;
 use async_trait::async_trait;
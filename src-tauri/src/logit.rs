@@ -0,0 +1,661 @@
+use std::collections::HashMap;
+
+use anyhow::{
+    bail,
+    Context,
+    Result
+};
+use chrono::{
+    Days,
+    NaiveDate
+};
+use futures::TryStreamExt;
+use mongodb::{
+    bson::{
+        doc,
+        from_document,
+        DateTime,
+        Document
+    },
+    Collection,
+    Database
+};
+
+use crate::{
+    constants::{
+        DOG_INFO_COLLECTION,
+        LOGIT_MODELS_COLLECTION
+    },
+    models::{
+        CalibrationBin,
+        DateWindow,
+        DogRaceInfo,
+        LogitMetrics,
+        LogitModel,
+        Meta,
+        PredictResponse,
+        Prediction,
+        RaceCard,
+        TrainLogitInput
+    },
+    features::{
+        Conditions,
+        FeatureContext,
+        Run,
+        RunnerFeatures,
+        MAX_RUNS
+    },
+    speed::{
+        rank,
+        softmax
+    },
+    utils::{
+        is_duplicate_key,
+        unique_index
+    },
+    window::{
+        self,
+        day_start
+    }
+};
+
+/// Runner features in vector order, named as in `RunnerFeatures`. Early
+/// pace is left out: `dog_race_info` has no bend positions, so it would
+/// be missing for every training runner.
+pub const FEATURES: [&str; 19] = [
+    "speed",
+    "speedAtDistance",
+    "speedFigure",
+    "rating",
+    "ratingDeviation",
+    "winnerGap",
+    "winRate",
+    "averagePosition",
+    "finishConsistency",
+    "sectionalAverage",
+    "averageTime",
+    "bestTime",
+    "daysSinceLastRun",
+    "weightChange",
+    "gradeChange",
+    "railPreference",
+    "runs",
+    "railTrap",
+    "wideTrap",
+];
+const N: usize = FEATURES.len();
+
+/// History loaded before the first training race.
+const LOOKBACK_DAYS: u64 = 365;
+const MIN_RACES: usize = 50;
+
+const DEFAULT_HOLDOUT: f64 = 0.2;
+const DEFAULT_EPOCHS: usize = 400;
+const DEFAULT_LEARNING_RATE: f64 = 0.3;
+const DEFAULT_L2: f64 = 0.001;
+
+fn vector(f: &RunnerFeatures) -> [Option<f64>; N] {
+    [
+        f.speed,
+        f.speed_at_distance,
+        f.speed_figure,
+        f.rating,
+        f.rating_deviation,
+        f.winner_gap,
+        f.win_rate,
+        f.average_position,
+        f.finish_consistency,
+        f.sectional_average,
+        f.average_time,
+        f.best_time,
+        f.days_since_last_run,
+        f.weight_change,
+        f.grade_change,
+        f.rail_preference,
+        Some((f.runs as f64).ln_1p()),
+        f.trap.map(|t| (t == 1) as u8 as f64),
+        f.trap.map(|t| (t == 6) as u8 as f64),
+    ]
+}
+
+fn mean(values: impl Iterator<Item = f64>) -> Option<f64> {
+    let (sum, count) = values.fold((0.0, 0), |(sum, count), v| (sum + v, count + 1));
+    (count > 0).then(|| sum / count as f64)
+}
+
+/// Fills what a runner lacks with the field mean: the model only compares
+/// runners within a race, so the mean favours no one.
+fn impute(field: &[[Option<f64>; N]]) -> Vec<[f64; N]> {
+    let means: Vec<f64> = (0..N)
+        .map(|k| mean(field.iter().filter_map(|f| f[k])).unwrap_or(0.0))
+        .collect();
+
+    field
+        .iter()
+        .map(|f| std::array::from_fn(|k| f[k].unwrap_or(means[k])))
+        .collect()
+}
+
+/// A training race: standardised runner features and who won.
+struct Sample {
+    runners: Vec<[f64; N]>,
+    winner: usize,
+}
+
+fn utilities(weights: &[f64], runners: &[[f64; N]]) -> Vec<f64> {
+    runners
+        .iter()
+        .map(|x| x.iter().zip(weights).map(|(x, w)| x * w).sum())
+        .collect()
+}
+
+/// Full-batch gradient descent on the mean negative log-likelihood of the
+/// winners, with L2 regularisation.
+fn fit(samples: &[Sample], epochs: usize, learning_rate: f64, l2: f64) -> Vec<f64> {
+    let mut weights = vec![0.0; N];
+
+    let share = 1.0 / samples.len() as f64;
+
+    for _ in 0..epochs {
+        let mut gradient: Vec<f64> = weights.iter().map(|w| l2 * w).collect();
+        for sample in samples {
+            // Expected features under the model minus the winner's.
+            let p = softmax(&utilities(&weights, &sample.runners), 1.0);
+            for (i, (runner, p)) in sample.runners.iter().zip(&p).enumerate() {
+                let won = (i == sample.winner) as u8 as f64;
+                for (g, x) in gradient.iter_mut().zip(runner) {
+                    *g += (p - won) * x * share;
+                }
+            }
+        }
+        for (w, g) in weights.iter_mut().zip(&gradient) {
+            *w -= learning_rate * g;
+        }
+    }
+
+    weights
+}
+
+fn log_loss(samples: &[Sample], weights: &[f64], temperature: f64) -> f64 {
+    samples
+        .iter()
+        .map(|s| -softmax(&utilities(weights, &s.runners), temperature)[s.winner].max(1e-12).ln())
+        .sum::<f64>()
+        / samples.len() as f64
+}
+
+/// Temperature with the lowest holdout log loss, by golden-section search
+/// over its logarithm.
+fn fit_temperature(samples: &[Sample], weights: &[f64]) -> f64 {
+    let ratio = (5f64.sqrt() - 1.0) / 2.0;
+    let (mut low, mut high) = (0.25f64.ln(), 4f64.ln());
+    let loss = |t: f64| log_loss(samples, weights, t.exp());
+
+    for _ in 0..40 {
+        let a = high - ratio * (high - low);
+        let b = low + ratio * (high - low);
+        if loss(a) < loss(b) {
+            high = b;
+        } else {
+            low = a;
+        }
+    }
+
+    ((low + high) / 2.0).exp()
+}
+
+fn metrics(samples: &[Sample], weights: &[f64], temperature: f64) -> LogitMetrics {
+    const BINS: usize = 10;
+    let mut bins = vec![(0usize, 0.0, 0.0); BINS];
+    let (mut log_loss, mut uniform, mut brier, mut hits) = (0.0, 0.0, 0.0, 0);
+
+    for sample in samples {
+        let p = softmax(&utilities(weights, &sample.runners), temperature);
+        log_loss -= p[sample.winner].max(1e-12).ln();
+        uniform += (p.len() as f64).ln();
+
+        let top = (0..p.len()).max_by(|a, b| p[*a].total_cmp(&p[*b])).unwrap_or(0);
+        hits += (top == sample.winner) as usize;
+
+        for (i, p) in p.iter().enumerate() {
+            let won = (i == sample.winner) as u8 as f64;
+            brier += (p - won).powi(2);
+
+            let bin = &mut bins[((p * BINS as f64) as usize).min(BINS - 1)];
+            bin.0 += 1;
+            bin.1 += p;
+            bin.2 += won;
+        }
+    }
+
+    let races = samples.len().max(1) as f64;
+    LogitMetrics {
+        races: samples.len(),
+        log_loss: log_loss / races,
+        uniform_log_loss: uniform / races,
+        brier: brier / races,
+        top_pick_accuracy: hits as f64 / races,
+        calibration: bins
+            .into_iter()
+            .enumerate()
+            .filter(|(_, (runners, _, _))| *runners > 0)
+            .map(|(i, (runners, predicted, observed))| CalibrationBin {
+                from: i as f64 / BINS as f64,
+                to: (i + 1) as f64 / BINS as f64,
+                runners,
+                predicted: predicted / runners as f64,
+                observed: observed / runners as f64,
+            })
+            .collect(),
+    }
+}
+
+/// Settled races of a period as raw feature values, each runner described
+/// by its runs before the race, with the winner's index.
+struct Loaded {
+    races: Vec<(Vec<[Option<f64>; N]>, usize)>,
+    /// Windows of the standard times the speed figures could use.
+    standard_windows: Vec<DateWindow>,
+}
+
+async fn load_races(database: &Database, from: NaiveDate, to: NaiveDate) -> Result<Loaded> {
+    let first = day_start(from);
+
+    let documents: Vec<Document> = database
+        .collection::<Document>(DOG_INFO_COLLECTION)
+        .find(window::period(from - Days::new(LOOKBACK_DAYS), to))
+        .sort(doc! { "raceDateTime": 1_i32 })
+        .await?
+        .try_collect()
+        .await?;
+    let read = documents.len();
+    let records: Vec<DogRaceInfo> = documents
+        .into_iter()
+        .filter_map(|d| from_document(d).ok())
+        .collect();
+    if records.len() < read {
+        log::warn!(
+            "Logit: {} of {read} dog_race_info records between {} and {to} are malformed and left out",
+            read - records.len(),
+            from - Days::new(LOOKBACK_DAYS)
+        );
+    }
+
+    // Races in the order they were run.
+    let mut races: Vec<Vec<DogRaceInfo>> = Vec::new();
+    let mut index: HashMap<u64, usize> = HashMap::new();
+    for record in records {
+        let i = *index.entry(record.race_id).or_insert_with(|| {
+            races.push(Vec::new());
+            races.len() - 1
+        });
+        races[i].push(record);
+    }
+
+    // Ratings as stored, as of each race, like the ones the model later
+    // sees when predicting.
+    let names = races
+        .iter()
+        .filter(|race| race[0].race_date_time >= first)
+        .flatten()
+        .map(|r| r.dog_name.as_str())
+        .collect();
+    let context = FeatureContext::for_dogs(database, names).await;
+
+    let mut histories: HashMap<u32, Vec<Run>> = HashMap::new();
+    let mut samples = Vec::new();
+    for race in &races {
+        let winners = race.iter().filter(|r| r.result_position == 1).count();
+        let counted = race[0].race_date_time >= first && (5..=6).contains(&race.len()) && winners == 1;
+
+        if counted {
+            let first_run = Run::from_info(&race[0], None);
+            let conditions = Conditions {
+                date: first_run.date.context("Race without a date")?,
+                track: first_run.track.as_deref().unwrap_or_default(),
+                distance: race[0].distance,
+                grade: first_run.grade.as_deref(),
+            };
+            let field: Vec<_> = race
+                .iter()
+                .map(|r| {
+                    let history: Vec<Run> = histories
+                        .get(&r.dog_id)
+                        .map(|runs| runs.iter().rev().take(MAX_RUNS).cloned().collect())
+                        .unwrap_or_default();
+                    vector(&context.runner(&r.dog_name, r.trap_number, &history, &conditions))
+                })
+                .collect();
+            let winner = race.iter().position(|r| r.result_position == 1).expect("one winner");
+            samples.push((field, winner));
+        }
+
+        let winners_time = race
+            .iter()
+            .filter_map(|r| r.result_run_time)
+            .filter(|t| *t > 0.0)
+            .map(f64::from)
+            .reduce(f64::min);
+        for record in race {
+            histories
+                .entry(record.dog_id)
+                .or_default()
+                .push(Run::from_info(record, winners_time));
+        }
+    }
+
+    Ok(Loaded {
+        races: samples,
+        standard_windows: context.standard_windows(to + Days::new(1)),
+    })
+}
+
+fn standardise(races: Vec<(Vec<[Option<f64>; N]>, usize)>, means: &[f64], scales: &[f64]) -> Vec<Sample> {
+    races
+        .into_iter()
+        .map(|(field, winner)| Sample {
+            runners: impute(&field)
+                .into_iter()
+                .map(|x| std::array::from_fn(|k| (x[k] - means[k]) / scales[k]))
+                .collect(),
+            winner,
+        })
+        .collect()
+}
+
+fn collection(database: &Database) -> Collection<LogitModel> {
+    database.collection(LOGIT_MODELS_COLLECTION)
+}
+
+pub async fn latest(database: &Database) -> Result<LogitModel> {
+    collection(database)
+        .find_one(doc! {})
+        .sort(doc! { "version": -1 })
+        .await?
+        .context("No trained logit model, train one first")
+}
+
+pub async fn load(database: &Database, version: u32) -> Result<LogitModel> {
+    collection(database)
+        .find_one(doc! { "version": version })
+        .await?
+        .with_context(|| format!("No logit model version {version}"))
+}
+
+/// Fails for models trained on other features than the current ones, or
+/// whose stored vectors do not have one value per feature.
+pub fn check(model: &LogitModel) -> Result<()> {
+    if model.features != FEATURES {
+        bail!(
+            "Logit model v{} was trained on other features, retrain it",
+            model.version
+        );
+    }
+    for (name, values) in [("means", &model.means), ("scales", &model.scales), ("weights", &model.weights)] {
+        if values.len() != N {
+            bail!(
+                "Logit model v{} has {} {name} for {N} features, retrain it",
+                model.version,
+                values.len()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Fits a model on the races between `from` and `to` and saves it as the
+/// next version. The latest races are held out: the older half fits the
+/// temperature, the newer half measures the holdout metrics.
+pub async fn train(database: &Database, input: &TrainLogitInput) -> Result<LogitModel> {
+    if input.from > input.to {
+        bail!("Training period starts after it ends");
+    }
+
+    let Loaded { races, standard_windows } = load_races(database, input.from, input.to).await?;
+    if races.len() < MIN_RACES {
+        bail!("Only {} settled races in the period, need at least {MIN_RACES}", races.len());
+    }
+
+    let holdout_share = input.holdout.unwrap_or(DEFAULT_HOLDOUT).clamp(0.0, 0.5);
+    let split = races.len() - (races.len() as f64 * holdout_share) as usize;
+    // The older half of the holdout fits the temperature, the newer half
+    // scores the calibrated model on races neither fit has seen.
+    let (train_races, calibration_races, holdout_races) = {
+        let mut races = races;
+        let mut calibration = races.split_off(split);
+        let holdout = calibration.split_off(calibration.len() / 2);
+        (races, calibration, holdout)
+    };
+
+    let rows: Vec<[f64; N]> = train_races.iter().flat_map(|(field, _)| impute(field)).collect();
+    let means: Vec<f64> = (0..N).map(|k| rows.iter().map(|x| x[k]).sum::<f64>() / rows.len() as f64).collect();
+    let scales: Vec<f64> = (0..N)
+        .map(|k| {
+            let variance = rows.iter().map(|x| (x[k] - means[k]).powi(2)).sum::<f64>() / rows.len() as f64;
+            if variance > 1e-12 { variance.sqrt() } else { 1.0 }
+        })
+        .collect();
+
+    let train_samples = standardise(train_races, &means, &scales);
+    let calibration_samples = standardise(calibration_races, &means, &scales);
+    let holdout_samples = standardise(holdout_races, &means, &scales);
+
+    let weights = fit(
+        &train_samples,
+        input.epochs.unwrap_or(DEFAULT_EPOCHS),
+        input.learning_rate.unwrap_or(DEFAULT_LEARNING_RATE),
+        input.l2.unwrap_or(DEFAULT_L2)
+    );
+    let temperature = if calibration_samples.is_empty() {
+        1.0
+    } else {
+        fit_temperature(&calibration_samples, &weights)
+    };
+
+    let mut model = LogitModel {
+        version: 0,
+        features: FEATURES.iter().map(|f| f.to_string()).collect(),
+        train: metrics(&train_samples, &weights, temperature),
+        holdout: (!holdout_samples.is_empty()).then(|| metrics(&holdout_samples, &weights, temperature)),
+        means,
+        scales,
+        weights,
+        temperature,
+        trained_from: input.from,
+        trained_to: input.to,
+        standard_windows,
+        created_at: Some(DateTime::now()),
+    };
+    save(database, &mut model).await?;
+    log::info!(
+        "Logit model v{}: train log loss {:.4} over {} races, holdout {:?}",
+        model.version,
+        model.train.log_loss,
+        model.train.races,
+        model.holdout.as_ref().map(|m| m.log_loss)
+    );
+
+    Ok(model)
+}
+
+/// Saves taken by concurrent trainings before giving up.
+const MAX_SAVE_ATTEMPTS: usize = 5;
+
+/// Stores the model as the next version, the first one on an empty
+/// collection.
+async fn save(database: &Database, model: &mut LogitModel) -> Result<()> {
+    let models = collection(database);
+    unique_index(&models, doc! { "version": 1 }).await?;
+
+    for _ in 0..MAX_SAVE_ATTEMPTS {
+        model.version = models
+            .find_one(doc! {})
+            .sort(doc! { "version": -1 })
+            .await?
+            .map_or(1, |latest| latest.version + 1);
+        match models.insert_one(&*model).await {
+            Ok(_) => return Ok(()),
+            Err(err) if is_duplicate_key(&err) => continue,
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    bail!("Logit models are being saved concurrently, try again")
+}
+
+/// Scores a saved model on the settled races between `from` and `to`.
+pub async fn validate(database: &Database, model: &LogitModel, from: NaiveDate, to: NaiveDate) -> Result<LogitMetrics> {
+    check(model)?;
+
+    let races = load_races(database, from, to).await?.races;
+    if races.is_empty() {
+        bail!("No settled races between {from} and {to}");
+    }
+    let samples = standardise(races, &model.means, &model.scales);
+
+    Ok(metrics(&samples, &model.weights, model.temperature))
+}
+
+/// Win probabilities of the runners of a race from their form lines.
+/// `None` when the document is not a race card or the model fails `check`.
+pub fn predict(model: &LogitModel, race: &Document, context: &FeatureContext) -> Option<PredictResponse> {
+    check(model).ok()?;
+    let card = RaceCard::from_document(race)?;
+    let runners = context.for_race(race)?;
+    if runners.is_empty() {
+        return None;
+    }
+    let names: Vec<String> = runners.iter().map(|f| f.name.clone()).collect();
+    let field: Vec<[Option<f64>; N]> = runners.iter().map(vector).collect();
+
+    let runners: Vec<[f64; N]> = impute(&field)
+        .into_iter()
+        .map(|x| std::array::from_fn(|k| (x[k] - model.means[k]) / model.scales[k]))
+        .collect();
+    let scores = utilities(&model.weights, &runners);
+    let probabilities = softmax(&scores, model.temperature);
+
+    let mut predictions: Vec<Prediction> = names
+        .into_iter()
+        .zip(scores.iter().zip(&probabilities))
+        .map(|(name, (score, probability))| Prediction {
+            name,
+            raw_score: *score as f32,
+            percentage: (probability * 100.0) as f32,
+            rank: 0,
+            comment: None,
+            confidence: None,
+        })
+        .collect();
+    rank(&mut predictions);
+
+    Some(PredictResponse {
+        race_id: Some(card.race_id),
+        meta: Meta {
+            date: card.date,
+            time: card.time,
+            distance: card.distance,
+            track: card.track,
+            grade: card.grade,
+        },
+        predictions,
+        summary: Some(format!("Conditional logit v{}", model.version)),
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Races of `runners` dogs where the first feature alone decides the
+    /// winner; the others are noise.
+    fn decided_by_first_feature(races: usize, runners: usize) -> Vec<Sample> {
+        let mut seed = 7u64;
+        let mut noise = move || {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) as f64 / (1u64 << 31) as f64 - 0.5
+        };
+
+        (0..races)
+            .map(|race| {
+                let winner = race % runners;
+                let runners = (0..runners)
+                    .map(|i| std::array::from_fn(|k| match k {
+                        0 if i == winner => 1.0,
+                        0 => -1.0 + noise(),
+                        _ => noise(),
+                    }))
+                    .collect();
+                Sample { runners, winner }
+            })
+            .collect()
+    }
+
+    fn model() -> LogitModel {
+        LogitModel {
+            version: 1,
+            features: FEATURES.iter().map(|f| f.to_string()).collect(),
+            means: vec![0.0; N],
+            scales: vec![1.0; N],
+            weights: vec![0.0; N],
+            temperature: 1.0,
+            trained_from: NaiveDate::from_ymd_opt(2024, 1, 1).unwrap(),
+            trained_to: NaiveDate::from_ymd_opt(2024, 12, 31).unwrap(),
+            standard_windows: Vec::new(),
+            train: LogitMetrics::default(),
+            holdout: None,
+            created_at: None,
+        }
+    }
+
+    #[test]
+    fn fit_lowers_the_loss_when_one_feature_decides() {
+        let samples = decided_by_first_feature(60, 6);
+
+        let weights = fit(&samples, 200, DEFAULT_LEARNING_RATE, DEFAULT_L2);
+
+        let untrained = log_loss(&samples, &[0.0; N], 1.0);
+        assert!((untrained - 6f64.ln()).abs() < 1e-9);
+        assert!(log_loss(&samples, &weights, 1.0) < untrained / 2.0);
+        assert!(weights[1..].iter().all(|w| w.abs() < weights[0]));
+    }
+
+    #[test]
+    fn fit_temperature_stays_in_bounds() {
+        let samples = decided_by_first_feature(40, 6);
+        let mut weights = vec![0.0; N];
+
+        for w in [0.0, 0.5, 50.0, -50.0] {
+            weights[0] = w;
+            let temperature = fit_temperature(&samples, &weights);
+            assert!((0.25..=4.0).contains(&temperature), "{temperature} for weight {w}");
+        }
+    }
+
+    #[test]
+    fn calibration_bins_count_every_runner() {
+        let samples = decided_by_first_feature(30, 5);
+        let weights = fit(&samples, 50, DEFAULT_LEARNING_RATE, DEFAULT_L2);
+
+        let metrics = metrics(&samples, &weights, 1.0);
+
+        let runners: usize = samples.iter().map(|s| s.runners.len()).sum();
+        assert_eq!(metrics.races, 30);
+        assert_eq!(metrics.calibration.iter().map(|bin| bin.runners).sum::<usize>(), runners);
+        assert!(metrics.calibration.iter().all(|bin| bin.from <= bin.predicted && bin.predicted <= bin.to));
+    }
+
+    #[test]
+    fn check_rejects_vectors_of_another_length() {
+        assert!(check(&model()).is_ok());
+
+        let mut short = model();
+        short.scales.pop();
+        assert!(check(&short).is_err());
+
+        let mut other = model();
+        other.features[0] = "earlyPace".to_string();
+        assert!(check(&other).is_err());
+    }
+}
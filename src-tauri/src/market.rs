@@ -0,0 +1,173 @@
+use anyhow::Result;
+use mongodb::bson::Document;
+
+use crate::{
+    models::{
+        Meta,
+        PredictResponse,
+        Prediction,
+        RaceCard
+    },
+    speed::rank,
+    DogInfoRepo
+};
+
+/// Ranks the runners of a race by the Betfair price a minute before the
+/// off. Only settled races have a price, so this is a backtest baseline.
+/// `None` when the race has no priced runners in `dog_race_info`.
+pub async fn predict<R: DogInfoRepo>(race: &Document, repo: &R) -> Result<Option<PredictResponse>> {
+    match RaceCard::from_document(race) {
+        Some(card) => predict_card(card, repo).await,
+        None => Ok(None),
+    }
+}
+
+/// `predict` for a race known by its card, as sent to the model.
+pub async fn predict_card<R: DogInfoRepo>(card: RaceCard, repo: &R) -> Result<Option<PredictResponse>> {
+    let runners: Vec<(String, Option<f64>)> = repo
+        .race_participants(card.date, card.time)
+        .await?
+        .into_iter()
+        .filter(|d| d.race_id == card.race_id)
+        .map(|d| (d.dog_name, Some(d.bf_odds_1_minute).filter(|odds| *odds > 1.0)))
+        .collect();
+
+    // Implied probabilities add up to more than 1 by the bookmaker's
+    // margin; scaling them down removes it proportionally.
+    let book: f64 = runners.iter().filter_map(|(_, odds)| *odds).map(|odds| 1.0 / odds).sum();
+    if book <= 0.0 {
+        return Ok(None);
+    }
+
+    let mut predictions: Vec<Prediction> = runners
+        .into_iter()
+        .map(|(name, odds)| {
+            let implied = odds.map_or(0.0, |odds| 1.0 / odds);
+            Prediction {
+                name,
+                raw_score: implied as f32,
+                percentage: (implied / book * 100.0) as f32,
+                rank: 0,
+                comment: Some(match odds {
+                    Some(odds) => format!("Betfair {odds:.2}"),
+                    None => "No price".to_string(),
+                }),
+                confidence: None,
+            }
+        })
+        .collect();
+    rank(&mut predictions);
+
+    Ok(Some(PredictResponse {
+        race_id: Some(card.race_id),
+        meta: Meta {
+            date: card.date,
+            time: card.time,
+            distance: card.distance,
+            track: card.track,
+            grade: card.grade,
+        },
+        predictions,
+        summary: Some(format!("Market baseline, overround {:.1}%", (book - 1.0) * 100.0)),
+        ..Default::default()
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{
+        NaiveDate,
+        NaiveTime
+    };
+
+    use super::*;
+    use crate::models::DogRaceInfo;
+
+    /// Records of the race at 19:00, whatever the time asked for.
+    struct FakeRepo(Vec<DogRaceInfo>);
+
+    #[async_trait::async_trait]
+    impl DogInfoRepo for FakeRepo {
+        async fn race_participants(&self, _date: NaiveDate, _time: NaiveTime) -> Result<Vec<DogRaceInfo>> {
+            Ok(self.0.clone())
+        }
+
+        async fn dog_record(
+            &self,
+            _date: NaiveDate,
+            _time: NaiveTime,
+            _distance: u32,
+            dog_name: &str,
+        ) -> Result<Option<DogRaceInfo>> {
+            Ok(self.0.iter().find(|d| d.dog_name == dog_name).cloned())
+        }
+    }
+
+    fn runner(race_id: u64, name: &str, odds: f64) -> DogRaceInfo {
+        DogRaceInfo {
+            race_id,
+            dog_name: name.to_string(),
+            bf_odds_1_minute: odds,
+            ..Default::default()
+        }
+    }
+
+    fn card() -> RaceCard {
+        RaceCard {
+            race_id: 1,
+            date: NaiveDate::from_ymd_opt(2024, 6, 20).unwrap(),
+            time: NaiveTime::from_hms_opt(19, 0, 0).unwrap(),
+            distance: 480,
+            track: "Romford".to_string(),
+            grade: Some("A5".to_string()),
+            runners: Vec::new(),
+        }
+    }
+
+    fn percentage(response: &PredictResponse, name: &str) -> f32 {
+        response.predictions.iter().find(|p| p.name == name).unwrap().percentage
+    }
+
+    #[tokio::test]
+    async fn removes_the_overround() {
+        let repo = FakeRepo(vec![
+            runner(1, "Evens", 2.0),
+            runner(1, "Threes", 3.0),
+            runner(1, "Fours", 4.0),
+            runner(2, "Other race", 1.5),
+        ]);
+
+        let response = predict_card(card(), &repo).await.unwrap().unwrap();
+
+        let book = 1.0 / 2.0 + 1.0 / 3.0 + 1.0 / 4.0;
+        assert_eq!(response.predictions.len(), 3);
+        assert!((percentage(&response, "Evens") as f64 - 50.0 / book).abs() < 1e-3);
+        assert!((percentage(&response, "Fours") as f64 - 25.0 / book).abs() < 1e-3);
+        let total: f32 = response.predictions.iter().map(|p| p.percentage).sum();
+        assert!((total - 100.0).abs() < 1e-3);
+        assert_eq!(response.predictions[0].name, "Evens");
+    }
+
+    #[tokio::test]
+    async fn runners_without_a_price_get_nothing() {
+        let repo = FakeRepo(vec![
+            runner(1, "Priced", 2.0),
+            runner(1, "Evens or less", 1.0),
+            runner(1, "Unpriced", 0.0),
+        ]);
+
+        let response = predict_card(card(), &repo).await.unwrap().unwrap();
+
+        assert_eq!(percentage(&response, "Evens or less"), 0.0);
+        assert_eq!(percentage(&response, "Unpriced"), 0.0);
+        assert!((percentage(&response, "Priced") - 100.0).abs() < 1e-3);
+        assert_eq!(response.predictions[0].name, "Priced");
+    }
+
+    #[tokio::test]
+    async fn race_without_prices_gives_nothing() {
+        let repo = FakeRepo(vec![runner(1, "Unpriced", 0.0), runner(2, "Other race", 2.0)]);
+
+        assert!(predict_card(card(), &repo).await.unwrap().is_none());
+    }
+}
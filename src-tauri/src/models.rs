@@ -1,8 +1,9 @@
-use std::{collections::HashMap, fmt};
+use std::collections::HashMap;
 
-use async_openai::types::ReasoningEffort;
+use async_openai::types::{BatchStatus, CompletionUsage, ReasoningEffort};
 use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
-use mongodb::bson::{oid::ObjectId, DateTime};
+use mongodb::bson::{oid::ObjectId, Bson, DateTime, Document};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Deserialize, Clone)]
@@ -33,22 +34,191 @@ pub enum TestDateTime {
     RangeDateTime(RangeDateTime)
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+/// How the LLM response cache is used for a run.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
-pub enum Model {
-    O3Mini,
-    O4Mini,
-    O3
-}
-
-impl fmt::Display for Model {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let s = match self {
-            Model::O3Mini => "o3-mini",
-            Model::O4Mini => "o4-mini",
-            Model::O3     => "o3"
-        };
-        f.write_str(s)
+pub enum CacheMode {
+    /// Serve cached responses, call the API and store on a miss.
+    #[default]
+    ReadThrough,
+    /// Always call the API and overwrite the cached response.
+    Refresh,
+    /// Serve cached responses only, a miss is an error.
+    CacheOnly,
+    Disabled,
+}
+
+/// How the races are written into the user message.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PromptFormat {
+    /// The race documents as JSON.
+    #[default]
+    Json,
+    /// One header per race and one row per recent run of every dog.
+    Table,
+    /// A CSV block of recent runs for all races in the request.
+    Csv,
+}
+
+impl PromptFormat {
+    pub const ALL: [PromptFormat; 3] = [PromptFormat::Json, PromptFormat::Table, PromptFormat::Csv];
+}
+
+/// Language of the field descriptions in the response schema.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SchemaLocale {
+    /// The doc comments of the response types.
+    En,
+    #[default]
+    Ru,
+}
+
+/// Estimated prompt size of the races to send in one format.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PromptEstimate {
+    pub format: PromptFormat,
+    pub requests: usize,
+    pub tokens: usize,
+    pub cost: f64,
+}
+
+/// Catalogue entry describing an OpenAI model and which request
+/// parameters it accepts. Prices are in USD per 1M tokens.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelInfo {
+    pub id: String,
+    pub supports_temperature: bool,
+    pub supports_penalties: bool,
+    pub supports_logprobs: bool,
+    pub supports_reasoning_effort: bool,
+    pub supports_seed: bool,
+    pub context_window: u32,
+    pub input_price: f64,
+    pub output_price: f64,
+}
+
+impl ModelInfo {
+    /// Reasoning models (o-series): accept `reasoning_effort`,
+    /// reject sampling parameters.
+    pub fn reasoning(id: &str, context_window: u32, input_price: f64, output_price: f64) -> Self {
+        Self {
+            id: id.to_string(),
+            supports_temperature: false,
+            supports_penalties: false,
+            supports_logprobs: false,
+            supports_reasoning_effort: true,
+            supports_seed: false,
+            context_window,
+            input_price,
+            output_price,
+        }
+    }
+
+    /// Chat models (gpt-series): accept sampling parameters,
+    /// reject `reasoning_effort`.
+    pub fn chat(id: &str, context_window: u32, input_price: f64, output_price: f64) -> Self {
+        Self {
+            id: id.to_string(),
+            supports_temperature: true,
+            supports_penalties: true,
+            supports_logprobs: true,
+            supports_reasoning_effort: false,
+            supports_seed: true,
+            context_window,
+            input_price,
+            output_price,
+        }
+    }
+
+    /// Entry for a model missing from the catalogue: only parameters
+    /// every model accepts will be sent.
+    pub fn unknown(id: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            supports_temperature: false,
+            supports_penalties: false,
+            supports_logprobs: false,
+            supports_reasoning_effort: false,
+            supports_seed: false,
+            context_window: 0,
+            input_price: 0.0,
+            output_price: 0.0,
+        }
+    }
+
+    /// Catalogue used to seed an empty `models` collection.
+    pub fn defaults() -> Vec<Self> {
+        vec![
+            Self::reasoning("o3-mini", 200_000, 1.1, 4.4),
+            Self::reasoning("o4-mini", 200_000, 1.1, 4.4),
+            Self::reasoning("o3", 200_000, 2.0, 8.0),
+            Self::chat("gpt-4.1", 1_047_576, 2.0, 8.0),
+            Self::chat("gpt-4.1-mini", 1_047_576, 0.4, 1.6),
+            Self::chat("gpt-4o", 128_000, 2.5, 10.0),
+            Self::chat("gpt-4o-mini", 128_000, 0.15, 0.6),
+        ]
+    }
+}
+
+/// Tokens billed for one or more chat completions and their cost in USD.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub reasoning_tokens: u64,
+    pub cost: f64,
+}
+
+impl TokenUsage {
+    pub fn from_completion(usage: &CompletionUsage, model: &ModelInfo) -> Self {
+        let prompt_tokens = usage.prompt_tokens as u64;
+        let completion_tokens = usage.completion_tokens as u64;
+        let reasoning_tokens = usage
+            .completion_tokens_details
+            .as_ref()
+            .and_then(|d| d.reasoning_tokens)
+            .unwrap_or_default() as u64;
+
+        // Reasoning tokens are already included in `completion_tokens`
+        // and billed at the output price.
+        let cost = (prompt_tokens as f64 * model.input_price
+            + completion_tokens as f64 * model.output_price)
+            / 1_000_000.0;
+
+        Self {
+            prompt_tokens,
+            completion_tokens,
+            reasoning_tokens,
+            cost,
+        }
+    }
+
+    pub fn scaled(mut self, factor: f64) -> Self {
+        self.cost *= factor;
+        self
+    }
+
+    /// One of `parts` equal shares, for requests that answered several
+    /// races. Token counts are rounded down.
+    pub fn share(self, parts: usize) -> Self {
+        let parts = parts.max(1);
+        Self {
+            prompt_tokens: self.prompt_tokens / parts as u64,
+            completion_tokens: self.completion_tokens / parts as u64,
+            reasoning_tokens: self.reasoning_tokens / parts as u64,
+            cost: self.cost / parts as f64,
+        }
+    }
+
+    pub fn add(&mut self, other: &TokenUsage) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.reasoning_tokens += other.reasoning_tokens;
+        self.cost += other.cost;
     }
 }
 
@@ -56,6 +226,91 @@ impl fmt::Display for Model {
 pub struct PredictInput {
     pub time: Time,
     pub distances: Vec<i32>,
+    #[serde(default)]
+    pub ensemble: Option<EnsembleOptions>,
+    #[serde(default)]
+    pub backend: PredictorBackend,
+}
+
+/// What produces the predictions. Everything but the LLM runs locally at
+/// no API cost.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PredictorBackend {
+    #[default]
+    Llm,
+    /// Ranks runners by going-adjusted speed from their recent form.
+    SpeedRating,
+    /// Ranks runners by their exchange price, backtests only.
+    Market,
+    /// Latest trained conditional logit model.
+    Logit,
+}
+
+impl PredictorBackend {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PredictorBackend::Llm => "llm",
+            PredictorBackend::SpeedRating => "speed-rating",
+            PredictorBackend::Market => "market",
+            PredictorBackend::Logit => "logit",
+        }
+    }
+}
+
+/// How the answers of several samples or models are merged per race.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EnsembleMethod {
+    /// Average win percentage, ranked by it.
+    #[default]
+    MeanProbability,
+    /// Each answer gives `n - rank` points to a dog.
+    Borda,
+    MedianRank,
+}
+
+impl EnsembleMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::MeanProbability => "mean-probability",
+            Self::Borda => "borda",
+            Self::MedianRank => "median-rank",
+        }
+    }
+}
+
+/// Queries `samples` answers per race from the selected settings and
+/// from every settings profile named in `models`, then combines them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EnsembleOptions {
+    #[serde(default)]
+    pub samples: usize,
+    #[serde(default)]
+    pub models: Vec<String>,
+    #[serde(default)]
+    pub method: EnsembleMethod,
+}
+
+impl EnsembleOptions {
+    /// Name the run is recorded under, e.g. `gpt-4.1+o3 x3 borda`.
+    pub fn label(&self, selected_model: &str) -> String {
+        let mut models = vec![selected_model];
+        models.extend(self.models.iter().map(String::as_str).filter(|m| *m != selected_model));
+
+        format!("{} x{} {}", models.join("+"), self.samples.max(1), self.method.as_str())
+    }
+}
+
+/// How a combined prediction was produced. `agreement` is Kendall's W
+/// over the members' rankings: 1 when all agree, 0 when unrelated.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EnsembleInfo {
+    pub method: EnsembleMethod,
+    pub members: usize,
+    pub agreement: f32,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -213,7 +468,10 @@ pub struct SkipInfo {
     skipped_races_lt5: i32,
     skipped_races_gt6: i32,
     skipped_odds_range: i32,
-    skipped_favorite: i32
+    skipped_favorite: i32,
+    skipped_low_confidence: i32,
+    /// Races without a confidence while a minimum was set.
+    skipped_no_confidence: i32
 }
 
 impl SkipInfo {
@@ -221,13 +479,17 @@ impl SkipInfo {
         skipped_races_lt5: i32,
         skipped_races_gt6: i32,
         skipped_odds_range: i32,
-        skipped_favorite: i32
+        skipped_favorite: i32,
+        skipped_low_confidence: i32,
+        skipped_no_confidence: i32
     ) -> Self {
         Self {
             skipped_races_lt5,
             skipped_races_gt6,
             skipped_odds_range,
-            skipped_favorite
+            skipped_favorite,
+            skipped_low_confidence,
+            skipped_no_confidence
         }
     }
 }
@@ -262,6 +524,14 @@ pub struct TestResultsMeta {
     errors: TestErrors,
     initial_stake: f64,
     percentage: f64,
+    staking: StakingPlan,
+    /// Race at which the balance of the chosen plan could not cover a bet
+    /// and it stopped betting.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stopped_at: Option<u64>,
+    /// The same bets under the other staking plans.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    staking_plans: Vec<StakingResults>,
 }
 
 impl TestResultsMeta {
@@ -284,12 +554,38 @@ impl TestResultsMeta {
             balance,
             errors,
             initial_stake,
-            percentage
+            percentage,
+            staking: StakingPlan::default(),
+            stopped_at: None,
+            staking_plans: Vec::new()
         }
     }
+
+    pub fn with_staking(mut self, staking: StakingPlan, staking_plans: Vec<StakingResults>) -> Self {
+        self.staking = staking;
+        self.staking_plans = staking_plans;
+        self
+    }
+
+    pub fn with_stopped_at(mut self, stopped_at: Option<u64>) -> Self {
+        self.stopped_at = stopped_at;
+        self
+    }
 }
 
-#[derive(Debug, Serialize)]
+/// How a backtest's bets did under one staking plan.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StakingResults {
+    pub plan: StakingPlan,
+    pub tracked_races: usize,
+    pub final_balance: f64,
+    pub percentage: f64,
+    /// The balance could not cover a bet and betting stopped there.
+    pub stopped: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct TestResultsRaceMeta {
     date: NaiveDate,
@@ -323,7 +619,7 @@ impl TestResultsRaceMeta {
     }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct TestResultsRealResults {
     rank: u8,
@@ -339,7 +635,7 @@ impl TestResultsRealResults {
     }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct TestResultsDog {
     dog_name: String,
@@ -361,13 +657,15 @@ impl TestResultsDog {
     }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct TestResultsRace {
     #[serde(rename = "raceId")]
     race_id: u64,
     meta: TestResultsRaceMeta,
     dogs: Vec<TestResultsDog>,
-    summary: String
+    summary: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    bets: Vec<SettledBet>
 }
 
 impl TestResultsRace {
@@ -381,16 +679,68 @@ impl TestResultsRace {
             race_id,
             meta,
             dogs,
-            summary
+            summary,
+            bets: Vec::new()
         }
     }
+
+    pub fn with_bets(mut self, bets: Vec<SettledBet>) -> Self {
+        self.bets = bets;
+        self
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BetSide {
+    Back,
+    #[default]
+    Lay,
+}
+
+impl BetSide {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BetSide::Back => "back",
+            BetSide::Lay => "lay",
+        }
+    }
+}
+
+/// A backtest bet and how it settled.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SettledBet {
+    pub dog_name: String,
+    pub side: BetSide,
+    pub odds: f64,
+    pub stake: f64,
+    /// What the bet could lose: the stake of a back, `stake * (odds - 1)`
+    /// of a lay.
+    pub liability: f64,
+    /// Profit or loss after its share of the market commission.
+    pub profit: f64,
+}
+
+/// Settlement of the same backtest with the predictions of a baseline.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BaselineResults {
+    pub backend: PredictorBackend,
+    pub meta: TestResultsMeta,
 }
 
 #[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct TestResults {
     meta: TestResultsMeta,
     races: Vec<TestResultsRace>,
-    requests: Vec<HashMap<String, serde_json::Value>>
+    requests: Vec<HashMap<String, serde_json::Value>>,
+    usage: TokenUsage,
+    failed_requests: Vec<FailedRequest>,
+    instruction: Option<InstructionRef>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    baselines: Vec<BaselineResults>
 }
 
 impl TestResults {
@@ -398,7 +748,14 @@ impl TestResults {
         meta: TestResultsMeta,
         races: Vec<TestResultsRace>,
         requests: Vec<HashMap<String, serde_json::Value>>,
+        usage: TokenUsage,
+        failed_requests: Vec<FailedRequest>,
     ) -> Self {
+        let instruction = requests
+            .iter()
+            .find_map(|r| r.get("meta").and_then(|m| m.get("instruction")))
+            .and_then(|i| serde_json::from_value(i.clone()).ok());
+
         let requests = requests
             .into_iter()
             .map(|mut obj| {
@@ -424,10 +781,307 @@ impl TestResults {
             })
             .collect();
 
-        Self { meta, races, requests }
+        Self { meta, races, requests, usage, failed_requests, instruction, baselines: Vec::new() }
+    }
+
+    pub fn with_baseline(mut self, baseline: BaselineResults) -> Self {
+        self.baselines.push(baseline);
+        self
+    }
+
+    pub fn usage(&self) -> TokenUsage {
+        self.usage
+    }
+
+    /// Races that were predicted and settled.
+    pub fn race_ids(&self) -> impl Iterator<Item = u64> + '_ {
+        self.races.iter().map(|r| r.race_id)
+    }
+
+    pub fn instruction(&self) -> Option<&InstructionRef> {
+        self.instruction.as_ref()
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TestParams {
+    pub initial_balance: f64,
+    pub initial_stake: f64,
+    pub odds_range: OddsRange,
+    pub is_favorite_protected: bool,
+    /// Races whose confidence is below this are not bet on, nor are races
+    /// without a confidence (logprobs disabled or not found).
+    #[serde(default)]
+    pub min_confidence: Option<f32>,
+    #[serde(default)]
+    pub strategy: StrategyKind,
+    #[serde(default)]
+    pub staking: StakingPlan
+}
+
+/// How much is staked on each bet of a backtest, see `staking`. Fixed
+/// plans are sized by the initial stake.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case", rename_all_fields = "camelCase")]
+pub enum StakingPlan {
+    /// The initial stake on every bet.
+    #[default]
+    FixedStake,
+    /// Risk the initial stake: the liability of a lay, the stake of a
+    /// back.
+    FixedLiability,
+    /// Risk a percentage of the current balance.
+    PercentOfBank { percent: f64 },
+    /// A fraction of the Kelly stake from the predicted win chance.
+    Kelly { fraction: f64 },
+}
+
+/// Which dogs a backtest bets on, see `strategy`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case", rename_all_fields = "camelCase")]
+pub enum StrategyKind {
+    /// Lay the shorter-priced of the two lowest-ranked dogs.
+    #[default]
+    LayBottomTwo,
+    /// Lay the dog of one predicted rank, e.g. 6.
+    LayRank { rank: u8 },
+    /// Lay every dog predicted a win chance below `percentage`.
+    LayBelow { percentage: f32 },
+    /// Back the dog of one predicted rank, e.g. the top one.
+    BackRank { rank: u8 },
+}
+
+impl StrategyKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StrategyKind::LayBottomTwo => "lay-bottom-two",
+            StrategyKind::LayRank { .. } => "lay-rank",
+            StrategyKind::LayBelow { .. } => "lay-below",
+            StrategyKind::BackRank { .. } => "back-rank",
+        }
     }
 }
 
+/// Conditional logit over runner features. Every training run is saved
+/// as a new version; features are stored by name so a model only scores
+/// races with the features it was trained on.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LogitModel {
+    pub version: u32,
+    pub features: Vec<String>,
+    /// Standardisation applied before the weights.
+    pub means: Vec<f64>,
+    pub scales: Vec<f64>,
+    pub weights: Vec<f64>,
+    /// Divides the utilities, fitted on the older half of the holdout
+    /// races.
+    pub temperature: f64,
+    pub trained_from: NaiveDate,
+    pub trained_to: NaiveDate,
+    /// Windows of the standard times its speed figures were computed
+    /// against, each race using the latest that ended before it.
+    #[serde(default)]
+    pub standard_windows: Vec<DateWindow>,
+    pub train: LogitMetrics,
+    /// Metrics on the newer half of the holdout races, which neither the
+    /// weights nor the temperature were fitted on.
+    #[serde(default)]
+    pub holdout: Option<LogitMetrics>,
+    #[serde(default)]
+    pub created_at: Option<DateTime>,
+}
+
+/// How well win probabilities matched the results of a set of races.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LogitMetrics {
+    pub races: usize,
+    /// Mean negative log-probability of the winner.
+    pub log_loss: f64,
+    /// Log loss of picking every runner equally.
+    pub uniform_log_loss: f64,
+    pub brier: f64,
+    /// Share of races won by the top-rated runner.
+    pub top_pick_accuracy: f64,
+    pub calibration: Vec<CalibrationBin>,
+}
+
+/// Runners whose predicted chance fell in `[from, to)`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CalibrationBin {
+    pub from: f64,
+    pub to: f64,
+    pub runners: usize,
+    pub predicted: f64,
+    pub observed: f64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrainLogitInput {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    /// Latest share of the races kept out of training, half to fit the
+    /// temperature and half to report holdout metrics.
+    #[serde(default)]
+    pub holdout: Option<f64>,
+    #[serde(default)]
+    pub epochs: Option<usize>,
+    #[serde(default)]
+    pub learning_rate: Option<f64>,
+    #[serde(default)]
+    pub l2: Option<f64>,
+}
+
+/// How each trap fared at a track and distance, and grade when split by
+/// grade, over a window of settled races.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrapBias {
+    pub track: String,
+    pub distance: u32,
+    /// `None` for all grades together.
+    #[serde(default)]
+    pub grade: Option<String>,
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub races: usize,
+    pub traps: Vec<TrapStats>,
+    #[serde(default)]
+    pub created_at: Option<DateTime>,
+}
+
+/// Results from one trap. Intervals are 95% and rates are shares, not
+/// percentages.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrapStats {
+    pub trap: u32,
+    pub runs: usize,
+    pub wins: usize,
+    /// First or second.
+    pub places: usize,
+    pub win_rate: f64,
+    pub win_interval: (f64, f64),
+    pub place_rate: f64,
+    pub place_interval: (f64, f64),
+    pub average_position: f64,
+    pub position_interval: (f64, f64),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrapBiasInput {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    /// Also split every track and distance by grade.
+    #[serde(default)]
+    pub by_grade: bool,
+}
+
+/// Par time of a track and distance: the median going-adjusted winning
+/// time over a window of settled races.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StandardTime {
+    pub track: String,
+    pub distance: u32,
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub races: usize,
+    /// Seconds.
+    pub standard: f64,
+    /// Standard deviation of the winning times around their mean.
+    pub deviation: f64,
+    #[serde(default)]
+    pub created_at: Option<DateTime>,
+}
+
+/// Days a set of stored figures was computed over, both included.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct DateWindow {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StandardTimesInput {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+}
+
+/// Speed figure of a run against the standard times a race on its day
+/// could use. The records of a dog make up its figures over time.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpeedFigure {
+    pub dog_id: u32,
+    pub dog_name: String,
+    pub race_id: u64,
+    pub race_date_time: DateTime,
+    pub track: String,
+    pub distance: u32,
+    pub figure: f64,
+    /// Window of the standard time the run was figured against.
+    pub standard: DateWindow,
+}
+
+/// Glicko rating of a dog straight after one of its races. The records of
+/// a dog make up its rating over time.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DogRating {
+    pub dog_id: u32,
+    pub dog_name: String,
+    pub race_id: u64,
+    pub race_date_time: DateTime,
+    pub rating: f64,
+    /// Rating deviation, how unsure the rating is.
+    pub deviation: f64,
+    /// Rated races so far, this one included.
+    pub races: u32,
+}
+
+/// Persisted state of a backtest submitted through the Batch API.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchJob {
+    pub batch_id: String,
+    pub input_file_id: String,
+    pub output_file_id: Option<String>,
+    pub status: BatchStatus,
+    pub settings: Settings,
+    pub model: ModelInfo,
+    pub params: TestParams,
+    /// Requests are kept one document each in `batch_requests`, so big
+    /// batches stay within the document size limit.
+    #[serde(default)]
+    pub request_count: usize,
+    pub total_races: usize,
+    pub created_at: DateTime,
+    pub finished: bool
+}
+
+/// One request of a batch, `index` is its position in the input file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchRequestDoc {
+    pub batch_id: String,
+    pub index: usize,
+    pub request: HashMap<String, serde_json::Value>
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchPollOutput {
+    pub job: BatchJob,
+    pub results: Option<TestResults>
+}
 
 pub struct RequestsInfo {
     pub requests: Vec<HashMap<String, serde_json::Value>>,
@@ -437,7 +1091,9 @@ pub struct RequestsInfo {
 #[derive(Debug, Deserialize)]
 pub struct AddInstructionInput {
     pub name: String,
-    pub content: String
+    pub content: String,
+    #[serde(default)]
+    pub author: Option<String>
 }
 
 #[derive(Debug, Deserialize)]
@@ -458,6 +1114,21 @@ pub struct LoadSettingsOutput {
     pub temperature: Option<f32>,
     pub max_races: usize,
     pub races_per_request: usize,
+    #[serde(default)]
+    pub max_in_flight: Option<usize>,
+    #[serde(default)]
+    pub max_retries: Option<usize>,
+    #[serde(default)]
+    pub tokens_per_minute: Option<u32>,
+    #[serde(default)]
+    pub cache_mode: CacheMode,
+    #[serde(default)]
+    pub prompt_format: PromptFormat,
+    #[serde(default)]
+    pub schema_locale: SchemaLocale,
+    /// Adds computed runner features to every dog in the prompt.
+    #[serde(default)]
+    pub include_features: bool,
     pub instruction_name: String
 }
 
@@ -475,6 +1146,13 @@ impl Default for LoadSettingsOutput {
             temperature: None,
             max_races: 50,
             races_per_request: 1,
+            max_in_flight: None,
+            max_retries: None,
+            tokens_per_minute: None,
+            cache_mode: CacheMode::default(),
+            prompt_format: PromptFormat::default(),
+            schema_locale: SchemaLocale::default(),
+            include_features: false,
             instruction_name: String::new()
         }
     }
@@ -494,12 +1172,27 @@ pub struct SaveSettingsInput {
     pub temperature: Option<f32>,
     pub max_races: usize,
     pub races_per_request: usize,
+    #[serde(default)]
+    pub max_in_flight: Option<usize>,
+    #[serde(default)]
+    pub max_retries: Option<usize>,
+    #[serde(default)]
+    pub tokens_per_minute: Option<u32>,
+    #[serde(default)]
+    pub cache_mode: CacheMode,
+    #[serde(default)]
+    pub prompt_format: PromptFormat,
+    #[serde(default)]
+    pub schema_locale: SchemaLocale,
+    /// Adds computed runner features to every dog in the prompt.
+    #[serde(default)]
+    pub include_features: bool,
     pub instruction_name: String
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Settings {
-    pub model: Model,
+    pub model: String,
     pub instruction_name: String,
     pub max_completion_tokens: Option<u32>,
     pub frequency_penalty: Option<f32>,
@@ -512,47 +1205,336 @@ pub struct Settings {
     pub max_races: usize,
     pub races_per_request: usize,
     pub selected: bool,
+    #[serde(default)]
+    pub max_in_flight: Option<usize>,
+    #[serde(default)]
+    pub max_retries: Option<usize>,
+    #[serde(default)]
+    pub tokens_per_minute: Option<u32>,
+    #[serde(default)]
+    pub cache_mode: CacheMode,
+    #[serde(default)]
+    pub prompt_format: PromptFormat,
+    #[serde(default)]
+    pub schema_locale: SchemaLocale,
+    #[serde(default)]
+    pub include_features: bool,
 }
 
-#[derive(Debug, Deserialize)]
+/// One immutable version of an instruction. Editing inserts a new
+/// document with the next version; documents written before versioning
+/// are numbered by `instructions::migrate` at startup.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct InstructionDoc {
     pub name: String,
     pub content: String,
+    #[serde(default)]
+    pub version: u32,
+    #[serde(default)]
+    pub author: Option<String>,
+    #[serde(default)]
+    pub created_at: Option<DateTime>,
+}
+
+impl InstructionDoc {
+    pub fn reference(&self) -> InstructionRef {
+        InstructionRef {
+            name: self.name.clone(),
+            version: self.version
+        }
+    }
 }
 
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+/// One row of a side-by-side line diff.
+#[derive(Debug, Clone, Serialize)]
+pub struct DiffRow {
+    pub kind: DiffKind,
+    pub left: Option<String>,
+    pub right: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffKind {
+    Same,
+    Removed,
+    Added,
+    Changed,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ImportSummary {
+    pub created: Vec<String>,
+    pub updated: Vec<String>,
+    pub unchanged: Vec<String>,
+}
+
+/// Which instruction version produced a prediction or test run.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InstructionRef {
+    pub name: String,
+    pub version: u32,
+}
+
+/// Race the prediction is for.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Meta {
     // #[serde(skip)]
+    /// Race date (YYYY-MM-DD).
     pub date: NaiveDate,
+    /// Race start time (HH:MM:SS).
     pub time: NaiveTime,
+    /// Race distance in metres.
     pub distance: u32,
+    /// Track name.
     pub track: String,
+    /// Race grade.
     pub grade: Option<String>
 }
 
-#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+impl Meta {
+    /// Schema descriptions for `SchemaLocale::Ru`, keyed by field name.
+    pub const RU: &'static [(&'static str, &'static str)] = &[
+        ("date", "Дата гонки (YYYY-MM-DD). Я передаю ее в поле raceDateTime, будь внимателен!! Это очень важно!"),
+        ("time", "Время гонки (HH:MM:SS)"),
+        ("distance", "Дистанция гонки (например, 480)"),
+        ("track", "Название трека. Очень важно! Выдавай всегда"),
+        ("grade", "Класс гонки"),
+    ];
+}
+
+/// What was actually sent to the model for one race. Carried in the
+/// request meta so responses can be checked against it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RaceCard {
+    pub race_id: u64,
+    pub date: NaiveDate,
+    pub time: NaiveTime,
+    pub distance: u32,
+    pub track: String,
+    /// From `grade` on both shapes: backtest races copy it from
+    /// `raceClass`, scraped races from the meeting list when it gives one.
+    #[serde(default)]
+    pub grade: Option<String>,
+    pub runners: Vec<Runner>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Runner {
+    pub name: String,
+    pub trap: Option<u8>,
+}
+
+impl RaceCard {
+    /// Reads a race document in either shape sent to the model: scraped
+    /// races (`race_date_time`) or backtest races (`race_date` + `race_time`).
+    pub fn from_document(race: &Document) -> Option<Self> {
+        let int = |b: &Bson| match b {
+            Bson::Int32(v) => Some(*v as i64),
+            Bson::Int64(v) => Some(*v),
+            Bson::Double(v) => Some(*v as i64),
+            _ => None,
+        };
+
+        let race_id = race.get("race_id").and_then(int)? as u64;
+        let distance = race.get("distance").and_then(int)? as u32;
+
+        let (date, time) = match race.get_datetime("race_date_time") {
+            Ok(dt) => {
+                let dt = chrono::DateTime::from_timestamp_millis(dt.timestamp_millis())?.naive_utc();
+                (dt.date(), dt.time())
+            }
+            Err(_) => {
+                let date = NaiveDate::parse_from_str(race.get_str("race_date").ok()?, "%Y-%m-%d").ok()?;
+                let raw_time = race.get_str("race_time").ok()?;
+                let time = NaiveTime::parse_from_str(raw_time, "%H:%M:%S%.f")
+                    .or_else(|_| NaiveTime::parse_from_str(raw_time, "%H:%M"))
+                    .ok()?;
+                (date, time)
+            }
+        };
+
+        let dogs = race.get_array("dogs").ok()?;
+        let runners: Vec<Runner> = dogs
+            .iter()
+            .filter_map(Bson::as_document)
+            .filter_map(|dog| {
+                Some(Runner {
+                    name: dog.get_str("dogName").ok()?.to_string(),
+                    trap: dog.get("trapNumber").and_then(int).map(|t| t as u8),
+                })
+            })
+            .collect();
+
+        let track = dogs
+            .iter()
+            .filter_map(Bson::as_document)
+            .find_map(|dog| dog.get_str("trackName").ok())
+            .unwrap_or_default()
+            .to_string();
+
+        let grade = race.get_str("grade").ok().map(str::to_string);
+
+        Some(Self {
+            race_id,
+            date,
+            time,
+            distance,
+            track,
+            grade,
+            runners,
+        })
+    }
+}
+
+/// One dog in the ranking.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Prediction {
+    /// Dog name exactly as in the race card.
     pub name: String,
+    /// Total raw score.
     pub raw_score: f32,
+    /// Chance to win in percent.
+    #[schemars(range(min = 0, max = 100))]
     pub percentage: f32,
+    /// Position in the ranking, 1 is the favourite.
+    #[schemars(range(min = 1))]
     pub rank: u8,
-    pub comment: Option<String>
+    /// Short comment on the dog's chances.
+    pub comment: Option<String>,
+    /// Derived from token logprobs, 0..1. Never part of the model output.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(skip)]
+    pub confidence: Option<f32>
+}
+
+impl Prediction {
+    /// Schema descriptions for `SchemaLocale::Ru`, keyed by field name.
+    pub const RU: &'static [(&'static str, &'static str)] = &[
+        ("name", "Имя собаки"),
+        ("raw_score", "Суммарный Raw Score"),
+        ("percentage", "Шанс победы в процентах"),
+        ("rank", "Позиция в прогнозе (1 — фаворит)"),
+        ("comment", "Краткий комментарий по результату. Выдавай его всегда"),
+    ];
 }
 
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+/// Prediction for one race.
+// Fields skipped in the schema are filled in by the app, never by the model.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct PredictResponse {
+    // Only asked for when a request carries several races, see `schema`.
+    #[serde(rename = "raceId", default, skip_serializing_if = "Option::is_none")]
+    #[schemars(skip)]
+    pub race_id: Option<u64>,
     pub meta: Meta,
+    /// Dogs ranked by their chance to win.
     pub predictions: Vec<Prediction>,
-    pub summary: Option<String>
+    /// Short conclusion on the race.
+    pub summary: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(skip)]
+    pub usage: Option<TokenUsage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(skip)]
+    pub ensemble: Option<EnsembleInfo>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(skip)]
+    pub confidence: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(skip)]
+    pub instruction: Option<InstructionRef>
+}
+
+/// Response to a request carrying several races.
+#[derive(Debug, Deserialize)]
+pub struct MultiPredictResponse {
+    pub races: Vec<PredictResponse>
+}
+
+impl MultiPredictResponse {
+    /// Schema descriptions for `SchemaLocale::Ru`, keyed by field name.
+    pub const RU: &'static [(&'static str, &'static str)] = &[
+        ("races", "Прогнозы для каждой переданной гонки, по одному на race_id"),
+    ];
 }
 
 impl PredictResponse {
+    /// Schema descriptions for `SchemaLocale::Ru`, keyed by field name;
+    /// `""` describes the race itself. `race_id` is only in the schema of
+    /// requests with several races.
+    pub const RU: &'static [(&'static str, &'static str)] = &[
+        ("", "Прогноз на гонку"),
+        ("race_id", "Идентификатор гонки (race_id из входных данных)"),
+        ("meta", "Метаинформация о гонке"),
+        ("predictions", "Рейтинг собак по шансам на победу"),
+        ("summary", "Краткое заключение по прогнозу. Выдавай его всегда!"),
+    ];
+
     pub fn sort_predictions(&mut self) {
-        self.predictions.sort_by(|a, b| a.rank.cmp(&b.rank));
+        self.predictions.sort_by_key(|p| p.rank);
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PredictResults {
+    pub predictions: Vec<PredictResponse>,
+    pub usage: TokenUsage,
+    pub failed: Vec<FailedRequest>
+}
+
+/// Request that still had no valid response after all retries.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FailedRequest {
+    pub index: usize,
+    pub race_ids: Vec<u64>,
+    pub attempts: usize,
+    pub error: String
+}
+
+impl FailedRequest {
+    pub fn new(
+        index: usize,
+        request: &HashMap<String, serde_json::Value>,
+        attempts: usize,
+        error: String
+    ) -> Self {
+        let race_ids = request
+            .get("meta")
+            .and_then(|m| m.get("raceIds"))
+            .and_then(|ids| ids.as_array())
+            .map(|ids| ids.iter().filter_map(|id| id.as_u64()).collect())
+            .unwrap_or_default();
+
+        Self {
+            index,
+            race_ids,
+            attempts,
+            error
+        }
     }
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DailyUsage {
+    pub date: String,
+    pub kind: String,
+    pub usage: TokenUsage
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct LoadUsageInput {
+    pub start_date: NaiveDate,
+    pub end_date: NaiveDate
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct TimeRange {
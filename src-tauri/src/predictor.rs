@@ -2,6 +2,7 @@ use std::collections::HashMap;
 
 use anyhow::{
     anyhow, 
+    bail, 
     Result
 };
 use chrono::{
@@ -24,22 +25,39 @@ use mongodb::bson::{
 };
 use serde_json::json;
 use chrono_tz::Europe::London;
+use tokio::sync::mpsc;
 
 use crate::{
+    baseline,
+    cache::ResponseCache,
     client::OpenAIClient,
+    ensemble::Ensemble,
     constants::{
         MAX_REQUEST_DEFENCE, 
         PREDICTIONS_COLLECTION, 
         RACES_COLLECTION, TIME_RANGES_COLLECTION
     },
     models::{
+        EnsembleOptions, 
+        ModelInfo, 
         PredictInput, 
+        PredictorBackend, 
         PredictResponse, 
+        PredictResults, 
+        PromptEstimate, 
         Settings, 
         Time
     },
+    progress::{
+        Progress,
+        ProgressEvent
+    },
     scrapper::Scrapper,
-    utils::build_requests,
+    utils::{
+        build_requests, 
+        estimate_prompts, 
+        save_usage
+    },
 };
 
 #[allow(unused)]
@@ -47,34 +65,72 @@ pub struct Predictor {
     fixed_date: NaiveDate,
     db_client: mongodb::Client, 
     config: Settings,
+    model: ModelInfo,
     distances: Vec<i32>,
     time: Time,
+    ensemble: Option<EnsembleOptions>,
+    backend: PredictorBackend,
+    progress: Progress,
 }
 
 impl Predictor {
     pub async fn new(
         config: Settings,
+        model: ModelInfo,
         db_client: mongodb::Client,
         input: PredictInput,
     ) -> Self {
         let fixed_date = chrono::Utc::now().date_naive();
         let distances = input.distances;
         let time = input.time;
+        let ensemble = input.ensemble;
+        let backend = input.backend;
 
         Self {
             fixed_date,
             db_client,
             config,
+            model,
             distances,
-            time
+            time,
+            ensemble,
+            backend,
+            progress: Progress::none()
         }
     }
 
+    pub fn with_progress(mut self, progress: Progress) -> Self {
+        self.progress = progress;
+        self
+    }
+
     pub async fn create_request(&self) -> Result<Vec<HashMap<String, serde_json::Value>>> {
         let database = self.db_client
             .default_database()
             .ok_or_else(|| anyhow!("Not default DB"))?;
 
+        let races = self.load_races().await?;
+        let requests = build_requests(races, database, self.config.clone()).await?;
+        log::info!("{} requests", requests.len());
+
+        Ok(requests)
+    }
+
+    /// Token estimate of the requests in every prompt format.
+    pub async fn estimate_prompts(&self) -> Result<Vec<PromptEstimate>> {
+        let database = self.db_client
+            .default_database()
+            .ok_or_else(|| anyhow!("Not default DB"))?;
+
+        let races = self.load_races().await?;
+        estimate_prompts(races, database, self.config.clone(), &self.model).await
+    }
+
+    async fn load_races(&self) -> Result<Vec<Document>> {
+        let database = self.db_client
+            .default_database()
+            .ok_or_else(|| anyhow!("Not default DB"))?;
+
         let distances = &self.distances;
         let today = Utc::now().date_naive();
         
@@ -131,10 +187,7 @@ impl Predictor {
             log::info!("Defenced to {} requests", races.len());
         }
 
-        let requests = build_requests(races, database.clone(), self.config.clone()).await?;
-        log::info!("{} requests", requests.len());
-
-        Ok(requests)
+        Ok(races)
     }
 
     pub async fn scrape_races(&self) -> Result<()> {
@@ -169,7 +222,7 @@ impl Predictor {
             Ok(())
         } else {
             info!("Races scrapping");
-            let scrapper = Scrapper::new()?;
+            let scrapper = Scrapper::new()?.with_progress(self.progress.clone());
             
             let data = scrapper.get_all_dogs_data(&self.fixed_date).await?;
 
@@ -197,6 +250,42 @@ impl Predictor {
         }
     }
 
+    /// Sends the requests, saving and emitting every race as soon as its
+    /// request is answered.
+    async fn send_streaming(
+        &self,
+        client: OpenAIClient,
+        requests: Vec<HashMap<String, serde_json::Value>>
+    ) -> Result<PredictResults> {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let streaming = client.with_results(tx);
+
+        let send = async move { streaming.send_multiple(requests).await };
+        let publish = async {
+            let mut saved = Ok(());
+            while let Some((_, races)) = rx.recv().await {
+                if saved.is_ok() {
+                    saved = self.publish(&races).await;
+                }
+            }
+            saved
+        };
+
+        let (results, saved) = futures::join!(send, publish);
+        saved?;
+
+        results
+    }
+
+    async fn publish(&self, preds: &[PredictResponse]) -> Result<()> {
+        self.save_predictions(preds).await?;
+        for p in preds {
+            self.progress.emit(ProgressEvent::Prediction { prediction: Box::new(p.clone()) });
+        }
+
+        Ok(())
+    }
+
     pub async fn save_predictions(&self, preds: &[PredictResponse]) -> Result<()> {
         if preds.is_empty() {
             return Ok(());
@@ -276,17 +365,64 @@ impl Predictor {
         Ok(())
     }
 
-    pub async fn run(&self) -> Result<Vec<PredictResponse>> {
+    /// Predicts locally with a non-LLM backend, at no API cost.
+    async fn run_baseline(&self) -> Result<PredictResults> {
+        let database = self.db_client
+            .default_database()
+            .ok_or_else(|| anyhow!("No default DB"))?;
+
+        let races = self.load_races().await?;
+        self.progress.check()?;
+
+        let execution = baseline::execute(self.backend, &races, &database).await?;
+        self.publish(&execution.predictions).await?;
+        self.save_time_ranges().await?;
+
+        Ok(PredictResults {
+            predictions: execution.predictions,
+            usage: execution.usage,
+            failed: execution.failed
+        })
+    }
+
+    pub async fn run(&self) -> Result<PredictResults> {
+        if self.backend == PredictorBackend::Market {
+            bail!("Market odds are only known for settled races, use it in tests");
+        }
+
         self.scrape_races().await?;
 
-        let requests = self.create_request().await?;
+        let database = self.db_client
+            .default_database()
+            .ok_or_else(|| anyhow!("No default DB"))?;
 
-        let client = OpenAIClient::new(self.config.clone());
-        let responses = client.send_multiple(requests).await?;
+        if self.backend != PredictorBackend::Llm {
+            return self.run_baseline().await;
+        }
+
+        let requests = self.create_request().await?;
+        self.progress.check()?;
+
+        let cache = ResponseCache::new(&database, self.config.cache_mode);
+        let client = OpenAIClient::new(self.config.clone(), self.model.clone())
+            .with_cache(cache)
+            .with_progress(self.progress.clone());
+        let (results, model_label) = match &self.ensemble {
+            // Members are merged once all of them answered, so nothing
+            // can be shown before the end.
+            Some(options) => {
+                let ensemble = Ensemble::load(&database, client, options).await?;
+                let results = ensemble.send_multiple(requests).await?;
+                self.publish(&results.predictions).await?;
+                (results, options.label(&self.model.id))
+            }
+            None => (self.send_streaming(client, requests).await?, self.model.id.clone()),
+        };
 
-        self.save_predictions(&responses).await?;
         self.save_time_ranges().await?;
 
-        Ok(responses)
+        save_usage(&database, "predict", &model_label, results.usage).await?;
+
+        Ok(results)
     }
 }
@@ -0,0 +1,143 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        Mutex
+    }
+};
+
+use anyhow::{
+    bail,
+    Result
+};
+use serde::Serialize;
+use tokio::sync::watch;
+
+use crate::models::{
+    PredictResponse,
+    TestResultsRace
+};
+
+/// Something that happened in a running predict or test job.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum ProgressEvent {
+    /// Race cards fetched from the site so far.
+    Scraping { done: usize, total: usize },
+    /// Requests built and about to be sent.
+    Requests { total: usize },
+    #[serde(rename_all = "camelCase")]
+    RequestSent { index: usize, attempt: usize },
+    #[serde(rename_all = "camelCase")]
+    RequestSucceeded { index: usize, races: usize },
+    #[serde(rename_all = "camelCase")]
+    RequestFailed { index: usize, error: String },
+    /// A validated prediction, already saved.
+    Prediction { prediction: Box<PredictResponse> },
+    /// Backtest races settled against the real results so far.
+    RacesSettled { done: usize, total: usize },
+    /// One settled backtest race and the balance after it.
+    RaceSettled { race: Box<TestResultsRace>, balance: f64 },
+    Cancelled,
+}
+
+type Sink = Arc<dyn Fn(ProgressEvent) + Send + Sync>;
+
+/// Reports progress of one job and tells it when it was cancelled.
+/// Clones share the same sink and cancellation state.
+#[derive(Clone)]
+pub struct Progress {
+    sink: Option<Sink>,
+    cancel: Arc<watch::Sender<bool>>,
+}
+
+impl Default for Progress {
+    fn default() -> Self {
+        Self::none()
+    }
+}
+
+impl Progress {
+    /// Progress that goes nowhere and is only cancelled explicitly.
+    pub fn none() -> Self {
+        Self {
+            sink: None,
+            cancel: Arc::new(watch::Sender::new(false)),
+        }
+    }
+
+    pub fn new(sink: impl Fn(ProgressEvent) + Send + Sync + 'static) -> Self {
+        Self {
+            sink: Some(Arc::new(sink)),
+            ..Self::none()
+        }
+    }
+
+    pub fn emit(&self, event: ProgressEvent) {
+        if let Some(sink) = &self.sink {
+            sink(event);
+        }
+    }
+
+    pub fn cancel(&self) {
+        if !self.cancel.send_replace(true) {
+            self.emit(ProgressEvent::Cancelled);
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.cancel.borrow()
+    }
+
+    /// Fails when the job was cancelled, for steps that have nothing
+    /// partial to return.
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            bail!("Cancelled");
+        }
+        Ok(())
+    }
+
+    /// Resolves once the job is cancelled.
+    pub async fn cancelled(&self) {
+        let mut rx = self.cancel.subscribe();
+        // The sender lives as long as `self`, so this cannot fail.
+        let _ = rx.wait_for(|cancelled| *cancelled).await;
+    }
+}
+
+/// Payload of the `job-progress` event.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobProgress {
+    pub job_id: String,
+    pub event: ProgressEvent,
+}
+
+/// Running jobs by the id the frontend started them with.
+#[derive(Default)]
+pub struct Jobs(Mutex<HashMap<String, Progress>>);
+
+impl Jobs {
+    pub fn start(&self, job_id: &str, progress: Progress) {
+        self.0
+            .lock()
+            .expect("jobs lock poisoned")
+            .insert(job_id.to_string(), progress);
+    }
+
+    pub fn finish(&self, job_id: &str) {
+        self.0.lock().expect("jobs lock poisoned").remove(job_id);
+    }
+
+    /// Cancels the job; `false` when no such job is running.
+    pub fn cancel(&self, job_id: &str) -> bool {
+        match self.0.lock().expect("jobs lock poisoned").get(job_id) {
+            Some(progress) => {
+                progress.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use mongodb::bson::{
+    Bson,
+    Document
+};
+use serde_json::json;
+
+use crate::{
+    features::{
+        dogs,
+        forms
+    },
+    models::{
+        PromptFormat,
+        RaceCard
+    }
+};
+
+/// Columns of a recent run, in output order: short header and the form
+/// line keys it is read from (scraped and backtest races differ).
+const RUN_COLUMNS: [(&str, &[&str]); 12] = [
+    ("pos", &["resultPosition"]),
+    ("trap", &["trap", "trapNumber"]),
+    ("dist", &["distance"]),
+    ("grade", &["raceClass"]),
+    ("time", &["resultRunTime"]),
+    ("sec", &["sectionalTime"]),
+    ("win", &["raceWinnersTime"]),
+    ("btn", &["btnDistance"]),
+    ("going", &["goingType"]),
+    ("fig", &["speedFigure"]),
+    ("weight", &["resultDogWeight"]),
+    ("comment", &["raceComment"]),
+];
+
+/// Columns of the `features` block of a CSV prompt, see
+/// `features::RunnerFeatures`. Early pace is left out like in the logit:
+/// backtest form lines have no bend positions, so a backtest prompt would
+/// never show what a live one does.
+const FEATURE_COLUMNS: [&str; 18] = [
+    "runs",
+    "daysSinceLastRun",
+    "weightChange",
+    "averageTime",
+    "bestTime",
+    "sectionalAverage",
+    "averagePosition",
+    "finishConsistency",
+    "winRate",
+    "gradeChange",
+    "railPreference",
+    "speed",
+    "speedAtDistance",
+    "winnerGap",
+    "speedFigure",
+    "bestFigure",
+    "trapWinRate",
+    "rating",
+];
+
+/// User message content for the races of one request.
+pub fn render(races: &[Document], format: PromptFormat) -> String {
+    match format {
+        PromptFormat::Json => json!({ "races": races }).to_string(),
+        PromptFormat::Table => table(races),
+        PromptFormat::Csv => csv(races),
+    }
+}
+
+/// One block per race: a header line, the run columns, then every dog
+/// with one `|`-separated line per recent run.
+fn table(races: &[Document]) -> String {
+    let columns: Vec<&str> = RUN_COLUMNS.iter().map(|(header, _)| *header).collect();
+    let mut out = String::new();
+
+    for race in races {
+        let Some(card) = RaceCard::from_document(race) else {
+            log::warn!("Race without a card written as JSON");
+            out.push_str(&json!(race).to_string());
+            out.push('\n');
+            continue;
+        };
+
+        out.push_str(&format!(
+            "race {} | {} {} | {} | {}m | {}\n",
+            card.race_id,
+            card.date,
+            card.time.format("%H:%M"),
+            card.track,
+            card.distance,
+            card.grade.as_deref().unwrap_or("-")
+        ));
+        out.push_str(&format!("runs: {}\n", columns.join("|")));
+
+        for dog in dogs(race) {
+            out.push_str(&format!("T{} {}\n", trap(dog), dog.get_str("dogName").unwrap_or_default()));
+            if let Ok(features) = dog.get_document("features") {
+                let cells: Vec<String> = features
+                    .iter()
+                    .filter(|(key, _)| !matches!(key.as_str(), "name" | "trap"))
+                    .map(|(key, value)| format!("{key}={}", cell(value)))
+                    .collect();
+                out.push_str(&format!("  features: {}\n", cells.join("|")));
+            }
+            for run in forms(dog) {
+                let cells: Vec<String> = run_cells(run)
+                    .into_iter()
+                    .map(|c| c.replace('|', "/"))
+                    .collect();
+                out.push_str(&format!("  {}\n", cells.join("|")));
+            }
+        }
+        out.push('\n');
+    }
+
+    out.trim_end().to_string()
+}
+
+/// A CSV of the races followed by a CSV of every recent run, joined on
+/// `race_id` and `dog`, and a CSV of the runner features when the races
+/// carry them.
+fn csv(races: &[Document]) -> String {
+    let mut race_rows = vec!["race_id,date,time,track,distance,grade".to_string()];
+    let mut run_rows = vec![format!(
+        "race_id,dog_trap,dog,{}",
+        RUN_COLUMNS.iter().map(|(header, _)| *header).collect::<Vec<_>>().join(",")
+    )];
+    let mut feature_rows = vec![format!("race_id,dog,{}", FEATURE_COLUMNS.join(","))];
+
+    for race in races {
+        let race_id = race.get("race_id").map(cell).unwrap_or_default();
+        if let Some(card) = RaceCard::from_document(race) {
+            race_rows.push(csv_row([
+                card.race_id.to_string(),
+                card.date.to_string(),
+                card.time.format("%H:%M").to_string(),
+                card.track,
+                card.distance.to_string(),
+                card.grade.unwrap_or_default(),
+            ]));
+        }
+
+        for dog in dogs(race) {
+            let prefix = [race_id.clone(), trap(dog), dog.get_str("dogName").unwrap_or_default().to_string()];
+            if let Ok(features) = dog.get_document("features") {
+                let values = FEATURE_COLUMNS
+                    .iter()
+                    .map(|key| features.get(key).map(cell).unwrap_or_default());
+                feature_rows.push(csv_row([race_id.clone(), prefix[2].clone()].into_iter().chain(values)));
+            }
+            let runs = forms(dog);
+            if runs.is_empty() {
+                run_rows.push(csv_row(prefix));
+                continue;
+            }
+            for run in runs {
+                run_rows.push(csv_row(prefix.iter().cloned().chain(run_cells(run))));
+            }
+        }
+    }
+
+    let mut out = format!("races:\n{}\n\nruns:\n{}", race_rows.join("\n"), run_rows.join("\n"));
+    if feature_rows.len() > 1 {
+        out.push_str(&format!("\n\nfeatures:\n{}", feature_rows.join("\n")));
+    }
+
+    out
+}
+
+fn trap(dog: &Document) -> String {
+    dog.get("trapNumber").map(cell).unwrap_or_default()
+}
+
+fn run_cells(run: &Document) -> Vec<String> {
+    RUN_COLUMNS
+        .iter()
+        .map(|(_, keys)| {
+            keys.iter()
+                .find_map(|key| run.get(key))
+                .map(cell)
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+fn cell(value: &Bson) -> String {
+    match value {
+        Bson::String(s) => s.trim().to_string(),
+        Bson::Int32(v) => v.to_string(),
+        Bson::Int64(v) => v.to_string(),
+        Bson::Double(v) => format!("{:.2}", v).trim_end_matches('0').trim_end_matches('.').to_string(),
+        Bson::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+fn csv_row(cells: impl IntoIterator<Item = String>) -> String {
+    cells
+        .into_iter()
+        .map(|c| {
+            if c.contains([',', '"', '\n']) {
+                format!("\"{}\"", c.replace('"', "\"\""))
+            } else {
+                c
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
@@ -0,0 +1,422 @@
+use std::{
+    collections::HashMap,
+    f64::consts::{
+        LN_10,
+        PI
+    }
+};
+
+use anyhow::Result;
+use chrono::{
+    NaiveDate,
+    TimeZone,
+    Utc
+};
+use futures::TryStreamExt;
+use mongodb::{
+    bson::{
+        doc,
+        from_document,
+        DateTime,
+        Document
+    },
+    Collection,
+    Database
+};
+
+use crate::{
+    constants::{
+        DOG_INFO_COLLECTION,
+        DOG_RATINGS_COLLECTION
+    },
+    models::{
+        DogRaceInfo,
+        DogRating
+    }
+};
+
+const INITIAL_RATING: f64 = 1500.0;
+const INITIAL_DEVIATION: f64 = 350.0;
+const MIN_DEVIATION: f64 = 30.0;
+/// Deviation gained per day without a race, squared: a settled 50 grows
+/// back to 350 in about a year.
+const DAILY_VARIANCE: f64 = (INITIAL_DEVIATION * INITIAL_DEVIATION - 50.0 * 50.0) / 365.0;
+const MILLIS_PER_DAY: f64 = 86_400_000.0;
+/// Ratings written per insert.
+const WRITE_BATCH: usize = 5_000;
+
+const Q: f64 = LN_10 / 400.0;
+
+#[derive(Debug, Clone, Copy)]
+struct Rated {
+    rating: f64,
+    deviation: f64,
+    races: u32,
+    /// When the rating was last updated.
+    at: i64,
+}
+
+/// Deviation after `millis` without a race.
+fn inflate(deviation: f64, millis: i64) -> f64 {
+    let days = (millis as f64 / MILLIS_PER_DAY).max(0.0);
+    (deviation * deviation + DAILY_VARIANCE * days).sqrt().min(INITIAL_DEVIATION)
+}
+
+fn g(deviation: f64) -> f64 {
+    1.0 / (1.0 + 3.0 * Q * Q * deviation * deviation / (PI * PI)).sqrt()
+}
+
+/// Ratings of every dog seen so far, updated one race at a time in the
+/// order they were run.
+#[derive(Debug, Default)]
+pub struct Ratings {
+    dogs: HashMap<u32, Rated>,
+}
+
+impl Ratings {
+    /// Rating and deviation of a dog going into a race at `at`. `None` for
+    /// a dog without a rated race.
+    pub fn get(&self, dog_id: u32, at: DateTime) -> Option<(f64, f64)> {
+        let rated = self.dogs.get(&dog_id)?;
+        Some((rated.rating, inflate(rated.deviation, at.timestamp_millis() - rated.at)))
+    }
+
+    fn resume(&mut self, record: &DogRating) {
+        self.dogs.insert(record.dog_id, Rated {
+            rating: record.rating,
+            deviation: record.deviation,
+            races: record.races,
+            at: record.race_date_time.timestamp_millis(),
+        });
+    }
+
+    /// Updates the finishers of a race as a match where every dog beat the
+    /// ones behind it, returning their new ratings. Races with fewer than
+    /// two finishers change nothing.
+    pub fn rate(&mut self, race: &[DogRaceInfo]) -> Vec<DogRating> {
+        let finishers: Vec<&DogRaceInfo> = race.iter().filter(|r| r.result_position > 0).collect();
+        if finishers.len() < 2 {
+            return Vec::new();
+        }
+        let at = finishers[0].race_date_time;
+
+        let before: Vec<(f64, f64)> = finishers
+            .iter()
+            .map(|r| self.get(r.dog_id, at).unwrap_or((INITIAL_RATING, INITIAL_DEVIATION)))
+            .collect();
+
+        let updated: Vec<DogRating> = finishers
+            .iter()
+            .zip(&before)
+            .enumerate()
+            .map(|(i, (runner, (rating, deviation)))| {
+                let (mut variance_sum, mut score_sum) = (0.0, 0.0);
+                for (j, opponent) in finishers.iter().enumerate().filter(|(j, _)| *j != i) {
+                    let (opponent_rating, opponent_deviation) = before[j];
+                    let weight = g(opponent_deviation);
+                    let expected = 1.0 / (1.0 + 10f64.powf(-weight * (rating - opponent_rating) / 400.0));
+                    let score = match runner.result_position.cmp(&opponent.result_position) {
+                        std::cmp::Ordering::Less => 1.0,
+                        std::cmp::Ordering::Equal => 0.5,
+                        std::cmp::Ordering::Greater => 0.0,
+                    };
+                    variance_sum += weight * weight * expected * (1.0 - expected);
+                    score_sum += weight * (score - expected);
+                }
+                let precision = 1.0 / (deviation * deviation) + Q * Q * variance_sum;
+                let races = self.dogs.get(&runner.dog_id).map_or(0, |r| r.races) + 1;
+                let rated = Rated {
+                    rating: rating + Q / precision * score_sum,
+                    deviation: (1.0 / precision).sqrt().max(MIN_DEVIATION),
+                    races,
+                    at: at.timestamp_millis(),
+                };
+
+                DogRating {
+                    dog_id: runner.dog_id,
+                    dog_name: runner.dog_name.clone(),
+                    race_id: runner.race_id,
+                    race_date_time: at,
+                    rating: rated.rating,
+                    deviation: rated.deviation,
+                    races: rated.races,
+                }
+            })
+            .collect();
+        for record in &updated {
+            self.resume(record);
+        }
+
+        updated
+    }
+}
+
+fn collection(database: &Database) -> Collection<DogRating> {
+    database.collection(DOG_RATINGS_COLLECTION)
+}
+
+/// Rates the races in `dog_race_info` run after the last rated one, or all
+/// of them from scratch with `replay`. Returns the number of races rated.
+///
+/// Races loaded after an update but run before its last rated race are
+/// left out of an update without `replay`, so a backfill needs one; such
+/// records are counted in a warning.
+pub async fn update(database: &Database, replay: bool) -> Result<usize> {
+    let stored = collection(database);
+    if replay {
+        stored.delete_many(doc! {}).await?;
+    }
+
+    let mut ratings = Ratings::default();
+    let last = stored
+        .find_one(doc! {})
+        .sort(doc! { "raceDateTime": -1 })
+        .await?
+        .map(|r| r.race_date_time);
+    let filter = match last {
+        Some(last) => {
+            let pipeline = vec![
+                doc! { "$sort": { "raceDateTime": -1 } },
+                doc! { "$group": { "_id": "$dogId", "latest": { "$first": "$$ROOT" } } },
+                doc! { "$replaceRoot": { "newRoot": "$latest" } },
+            ];
+            let latest: Vec<Document> = stored.aggregate(pipeline).await?.try_collect().await?;
+            for record in latest.into_iter().filter_map(|d| from_document::<DogRating>(d).ok()) {
+                ratings.resume(&record);
+            }
+            let missed = backfilled(database, last).await?;
+            if missed > 0 {
+                log::warn!("Ratings: {missed} records run by {last} were loaded after the last update and stay unrated, replay to rate them");
+            }
+            doc! { "raceDateTime": { "$gt": last } }
+        }
+        None => doc! {},
+    };
+
+    let mut cursor = database
+        .collection::<Document>(DOG_INFO_COLLECTION)
+        .find(filter)
+        .sort(doc! { "raceDateTime": 1, "raceId": 1 })
+        .await?;
+
+    let mut race: Vec<DogRaceInfo> = Vec::new();
+    let mut pending: Vec<DogRating> = Vec::new();
+    let mut rated = 0;
+    while let Some(document) = cursor.try_next().await? {
+        let Ok(record) = from_document::<DogRaceInfo>(document) else {
+            continue;
+        };
+        if race.first().is_some_and(|r| r.race_id != record.race_id) {
+            rated += close(&mut ratings, &mut race, &mut pending);
+            if pending.len() >= WRITE_BATCH {
+                stored.insert_many(&pending).await?;
+                pending.clear();
+            }
+        }
+        race.push(record);
+    }
+    rated += close(&mut ratings, &mut race, &mut pending);
+    if !pending.is_empty() {
+        stored.insert_many(&pending).await?;
+    }
+    log::info!("Ratings: {rated} races rated after {last:?}");
+
+    Ok(rated)
+}
+
+/// Number of `dog_race_info` records run by `last` but inserted after the
+/// newest stored rating, which an update going on from `last` never sees.
+async fn backfilled(database: &Database, last: DateTime) -> Result<u64> {
+    let newest = database
+        .collection::<Document>(DOG_RATINGS_COLLECTION)
+        .find_one(doc! {})
+        .sort(doc! { "_id": -1 })
+        .projection(doc! { "_id": 1 })
+        .await?
+        .and_then(|d| d.get_object_id("_id").ok());
+    let Some(newest) = newest else {
+        return Ok(0);
+    };
+
+    Ok(database
+        .collection::<Document>(DOG_INFO_COLLECTION)
+        .count_documents(doc! { "raceDateTime": { "$lte": last }, "_id": { "$gt": newest } })
+        .await?)
+}
+
+/// Rates a finished race into `pending`, 1 when it counted.
+fn close(ratings: &mut Ratings, race: &mut Vec<DogRaceInfo>, pending: &mut Vec<DogRating>) -> usize {
+    let updated = ratings.rate(race);
+    race.clear();
+    let counted = usize::from(!updated.is_empty());
+    pending.extend(updated);
+
+    counted
+}
+
+/// Rating of a dog going into a race on `date`, from its last race before
+/// that day.
+pub async fn as_of(database: &Database, dog_name: &str, date: NaiveDate) -> Result<Option<DogRating>> {
+    let start = Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).expect("midnight exists"));
+    let mut rating = collection(database)
+        .find_one(doc! {
+            "dogName": dog_name,
+            "raceDateTime": { "$lt": DateTime::from_millis(start.timestamp_millis()) }
+        })
+        .sort(doc! { "raceDateTime": -1 })
+        .await?;
+    if let Some(rating) = rating.as_mut() {
+        rating.deviation = inflate(rating.deviation, start.timestamp_millis() - rating.race_date_time.timestamp_millis());
+    }
+
+    Ok(rating)
+}
+
+/// Every rating of a dog, oldest first.
+pub async fn history(database: &Database, dog_name: &str) -> Result<Vec<DogRating>> {
+    Ok(collection(database)
+        .find(doc! { "dogName": dog_name })
+        .sort(doc! { "raceDateTime": 1 })
+        .await?
+        .try_collect()
+        .await?)
+}
+
+/// Ratings of a dog as (millis, rating, deviation), oldest first.
+type Series = Vec<(i64, f64, f64)>;
+
+/// Stored rating series of some dogs by lower-case name, for features
+/// that need many ratings as of many dates without a query each.
+#[derive(Debug, Default)]
+pub struct Table {
+    series: HashMap<String, Series>,
+}
+
+impl Table {
+    /// Loads every stored rating of the dogs named.
+    pub async fn load(database: &Database, names: &[&str]) -> Result<Self> {
+        let records: Vec<DogRating> = collection(database)
+            .find(doc! { "dogName": { "$in": names } })
+            .sort(doc! { "raceDateTime": 1 })
+            .await?
+            .try_collect()
+            .await?;
+
+        let mut series: HashMap<String, Series> = HashMap::new();
+        for record in records {
+            series
+                .entry(record.dog_name.trim().to_lowercase())
+                .or_default()
+                .push((record.race_date_time.timestamp_millis(), record.rating, record.deviation));
+        }
+
+        Ok(Self { series })
+    }
+
+    /// Rating and deviation of a runner going into a race on `date`, like
+    /// `as_of`. `None` for a dog not loaded or without a rated race before
+    /// that day.
+    pub fn get(&self, dog_name: &str, date: NaiveDate) -> Option<(f64, f64)> {
+        let start = Utc
+            .from_utc_datetime(&date.and_hms_opt(0, 0, 0).expect("midnight exists"))
+            .timestamp_millis();
+        let (at, rating, deviation) = self
+            .series
+            .get(&dog_name.trim().to_lowercase())?
+            .iter()
+            .rev()
+            .find(|(at, _, _)| *at < start)?;
+
+        Some((*rating, inflate(*deviation, start - at)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: i64 = 86_400_000;
+
+    /// Finishers of race `race_id`, dog `i + 1` at `positions[i]`.
+    fn race(race_id: u64, day: i64, positions: &[u32]) -> Vec<DogRaceInfo> {
+        positions
+            .iter()
+            .enumerate()
+            .map(|(i, position)| DogRaceInfo {
+                dog_id: i as u32 + 1,
+                dog_name: format!("Dog {}", i + 1),
+                race_id,
+                result_position: *position,
+                race_date_time: DateTime::from_millis(day * DAY + DAY / 2),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn winner_gains_and_last_loses() {
+        let mut ratings = Ratings::default();
+
+        let updated = ratings.rate(&race(1, 19_000, &[1, 2, 3, 4, 5, 6]));
+
+        assert_eq!(updated.len(), 6);
+        assert!(updated[0].rating > INITIAL_RATING);
+        assert!(updated[5].rating < INITIAL_RATING);
+        assert!(updated.windows(2).all(|w| w[0].rating > w[1].rating));
+    }
+
+    #[test]
+    fn dead_heat_scores_a_half() {
+        let mut ratings = Ratings::default();
+
+        let updated = ratings.rate(&race(1, 19_000, &[1, 1]));
+
+        // Equal ratings expect 0.5 each, which a dead heat scores.
+        assert!(updated.iter().all(|r| (r.rating - INITIAL_RATING).abs() < 1e-9));
+        assert!(updated.iter().all(|r| r.deviation < INITIAL_DEVIATION));
+    }
+
+    #[test]
+    fn deviation_never_goes_below_the_floor() {
+        let mut ratings = Ratings::default();
+
+        let mut last = Vec::new();
+        for i in 0..200 {
+            // Every dog wins in turn, so the ratings stay close and each
+            // race keeps adding information.
+            let positions: Vec<u32> = (0..6).map(|d| (d + i as u32) % 6 + 1).collect();
+            last = ratings.rate(&race(i, 19_000, &positions));
+            assert!(last.iter().all(|r| r.deviation >= MIN_DEVIATION));
+        }
+        assert!(last.iter().all(|r| r.deviation == MIN_DEVIATION));
+        assert!(last.iter().all(|r| r.races == 200));
+    }
+
+    #[test]
+    fn non_finishers_are_not_rated() {
+        let mut ratings = Ratings::default();
+
+        assert!(ratings.rate(&race(1, 19_000, &[1, 0])).is_empty());
+        assert_eq!(ratings.rate(&race(2, 19_000, &[2, 0, 1])).len(), 2);
+    }
+
+    #[test]
+    fn table_ignores_ratings_from_the_race_day() {
+        let date = NaiveDate::from_ymd_opt(2024, 5, 10).unwrap();
+        let day = Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap()).timestamp_millis() / DAY;
+        let mut ratings = Ratings::default();
+        let rated = ratings.rate(&race(1, day, &[1, 2]));
+        let table = Table {
+            series: HashMap::from([(
+                "dog 1".to_string(),
+                vec![(rated[0].race_date_time.timestamp_millis(), rated[0].rating, rated[0].deviation)],
+            )]),
+        };
+
+        assert_eq!(table.get("Dog 1", date), None);
+
+        let (rating, deviation) = table.get(" DOG 1 ", date.succ_opt().unwrap()).unwrap();
+        assert_eq!(rating, rated[0].rating);
+        assert!(deviation > rated[0].deviation);
+    }
+}
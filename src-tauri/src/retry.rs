@@ -0,0 +1,221 @@
+use std::{
+    fmt,
+    time::{
+        Duration,
+        Instant
+    }
+};
+
+use rand::Rng;
+use reqwest::header::HeaderMap;
+use tokio::sync::Mutex;
+
+const BASE_DELAY: Duration = Duration::from_millis(500);
+const MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// Non-success HTTP status returned by the API.
+#[derive(Debug)]
+pub struct ApiStatusError {
+    pub status: u16,
+    pub retry_after: Option<Duration>,
+    pub message: String,
+}
+
+impl ApiStatusError {
+    /// Rate limits, timeouts and server errors are worth retrying;
+    /// other client errors will fail the same way every time.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self.status, 408 | 409 | 429) || self.status >= 500
+    }
+}
+
+impl fmt::Display for ApiStatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "API error {}: {}", self.status, self.message)
+    }
+}
+
+impl std::error::Error for ApiStatusError {}
+
+/// Reads `retry-after-ms` or `retry-after` (seconds) from the response.
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse::<f64>().ok())
+            .filter(|v| v.is_finite() && *v >= 0.0)
+    };
+
+    header("retry-after-ms")
+        .map(|ms| Duration::from_secs_f64(ms / 1000.0))
+        .or_else(|| header("retry-after").map(Duration::from_secs_f64))
+}
+
+/// Exponential backoff with full jitter: a random delay in
+/// `[0, min(MAX_DELAY, BASE_DELAY * 2^attempt)]`.
+pub fn backoff_delay(attempt: usize) -> Duration {
+    let exp = BASE_DELAY.saturating_mul(1u32 << attempt.min(16));
+    let cap = exp.min(MAX_DELAY);
+
+    cap.mul_f64(rand::thread_rng().gen_range(0.0..=1.0))
+}
+
+/// Token-per-minute budget shared by all in-flight requests,
+/// refilled continuously.
+pub struct TokenBudget {
+    tokens_per_minute: f64,
+    state: Mutex<(f64, Instant)>,
+}
+
+impl TokenBudget {
+    pub fn new(tokens_per_minute: u32) -> Self {
+        let tokens_per_minute = tokens_per_minute as f64;
+
+        Self {
+            tokens_per_minute,
+            state: Mutex::new((tokens_per_minute, Instant::now())),
+        }
+    }
+
+    /// Waits until `tokens` can be spent. Requests larger than the whole
+    /// budget wait for a full bucket instead of blocking forever.
+    pub async fn acquire(&self, tokens: usize) {
+        let needed = (tokens as f64).min(self.tokens_per_minute);
+
+        loop {
+            let wait = {
+                let mut state = self.state.lock().await;
+                self.refill(&mut state);
+
+                if state.0 >= needed {
+                    state.0 -= needed;
+                    return;
+                }
+
+                let missing = needed - state.0;
+                Duration::from_secs_f64(missing / self.tokens_per_minute * 60.0)
+            };
+
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Corrects the budget once the real token count is known.
+    pub async fn settle(&self, estimated: usize, actual: usize) {
+        let mut state = self.state.lock().await;
+        self.refill(&mut state);
+
+        state.0 = (state.0 + estimated as f64 - actual as f64).min(self.tokens_per_minute);
+    }
+
+    fn refill(&self, state: &mut (f64, Instant)) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(state.1).as_secs_f64();
+
+        state.0 = (state.0 + elapsed / 60.0 * self.tokens_per_minute).min(self.tokens_per_minute);
+        state.1 = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::HeaderValue;
+
+    use super::*;
+
+    /// Long enough for an immediate `acquire`, far below any real wait.
+    const NO_WAIT: Duration = Duration::from_millis(200);
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    fn error(status: u16) -> ApiStatusError {
+        ApiStatusError { status, retry_after: None, message: String::new() }
+    }
+
+    async fn acquired(budget: &TokenBudget, tokens: usize) -> bool {
+        tokio::time::timeout(NO_WAIT, budget.acquire(tokens)).await.is_ok()
+    }
+
+    #[test]
+    fn retry_after_prefers_milliseconds() {
+        let headers = headers(&[("retry-after-ms", "1500"), ("retry-after", "30")]);
+
+        assert_eq!(retry_after(&headers), Some(Duration::from_millis(1500)));
+    }
+
+    #[test]
+    fn retry_after_falls_back_to_seconds() {
+        assert_eq!(retry_after(&headers(&[("retry-after", " 2.5 ")])), Some(Duration::from_millis(2500)));
+        assert_eq!(retry_after(&headers(&[("retry-after-ms", "soon"), ("retry-after", "3")])), Some(Duration::from_secs(3)));
+    }
+
+    #[test]
+    fn retry_after_ignores_dates_and_negative_values() {
+        assert_eq!(retry_after(&headers(&[("retry-after", "Wed, 21 Oct 2015 07:28:00 GMT")])), None);
+        assert_eq!(retry_after(&headers(&[("retry-after-ms", "-5")])), None);
+        assert_eq!(retry_after(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn retryable_statuses() {
+        for status in [408, 409, 429, 500, 502, 503, 529] {
+            assert!(error(status).is_retryable(), "{status}");
+        }
+        for status in [400, 401, 403, 404, 422] {
+            assert!(!error(status).is_retryable(), "{status}");
+        }
+    }
+
+    #[test]
+    fn backoff_stays_under_the_cap() {
+        for attempt in [0, 1, 5, 40] {
+            let cap = BASE_DELAY.saturating_mul(1 << attempt.min(16)).min(MAX_DELAY);
+            assert!(backoff_delay(attempt) <= cap);
+        }
+    }
+
+    #[tokio::test]
+    async fn budget_waits_once_spent() {
+        let budget = TokenBudget::new(60);
+
+        assert!(acquired(&budget, 40).await);
+        assert!(acquired(&budget, 20).await);
+        assert!(!acquired(&budget, 30).await);
+    }
+
+    #[tokio::test]
+    async fn oversized_request_takes_a_full_budget() {
+        let budget = TokenBudget::new(60);
+
+        assert!(acquired(&budget, 1_000).await);
+        assert!(!acquired(&budget, 30).await);
+    }
+
+    #[tokio::test]
+    async fn settle_refunds_an_overestimate() {
+        let budget = TokenBudget::new(60);
+        assert!(acquired(&budget, 60).await);
+
+        budget.settle(60, 10).await;
+
+        assert!(acquired(&budget, 50).await);
+    }
+
+    #[tokio::test]
+    async fn settle_charges_an_underestimate() {
+        let budget = TokenBudget::new(60);
+        assert!(acquired(&budget, 10).await);
+
+        budget.settle(10, 40).await;
+
+        assert!(!acquired(&budget, 30).await);
+        assert!(acquired(&budget, 20).await);
+    }
+}
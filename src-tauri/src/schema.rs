@@ -0,0 +1,300 @@
+use async_openai::types::ResponseFormatJsonSchema;
+use schemars::gen::SchemaSettings;
+use serde_json::{
+    json,
+    Value
+};
+
+use crate::models::{
+    Meta,
+    MultiPredictResponse,
+    PredictResponse,
+    Prediction,
+    SchemaLocale
+};
+
+/// Descriptions of the fields added to `PredictResponse` for requests
+/// with several races, keyed by field path. The rest come from the doc
+/// comments of the types.
+const EN: &[(&str, &str)] = &[
+    ("raceId", "Race id (race_id from the input)"),
+    ("races", "Predictions for every race sent, one per race_id"),
+];
+
+/// Structured output format for a request. A request with several races
+/// gets an array of race predictions, each tagged with the race id it
+/// answers.
+pub fn response_format(races_in_request: usize, locale: SchemaLocale) -> ResponseFormatJsonSchema {
+    let multi = races_in_request > 1;
+
+    let mut race = race_schema();
+    if multi {
+        race["properties"]["raceId"] = json!({ "type": "integer" });
+        race["required"]
+            .as_array_mut()
+            .expect("required is an array")
+            .insert(0, json!("raceId"));
+    }
+    describe(&mut race, "", locale);
+
+    let (name, schema) = if multi {
+        let schema = json!({
+            "$schema": "http://json-schema.org/draft-07/schema#",
+            "title": "MultiPredictionResponse",
+            "type": "object",
+            "additionalProperties": false,
+            "properties": {
+                "races": {
+                    "type": "array",
+                    "description": races_description(locale),
+                    "items": race
+                }
+            },
+            "required": ["races"]
+        });
+        ("MultiPredictionResponse".to_string(), schema)
+    } else {
+        race["$schema"] = json!("http://json-schema.org/draft-07/schema#");
+        race["title"] = json!("PredictionResponse");
+        ("PredictionResponse".to_string(), race)
+    };
+
+    ResponseFormatJsonSchema {
+        description: None,
+        name,
+        schema: Some(schema),
+        strict: Some(true),
+    }
+}
+
+/// Schema of `PredictResponse` in the subset strict structured outputs
+/// accept: everything inlined, every property required (optional ones
+/// are nullable) and no additional properties.
+fn race_schema() -> Value {
+    let settings = SchemaSettings::draft07().with(|s| {
+        s.inline_subschemas = true;
+        s.option_nullable = false;
+        s.option_add_null_type = true;
+    });
+    let root = settings
+        .into_generator()
+        .into_root_schema_for::<PredictResponse>();
+
+    let mut schema = serde_json::to_value(root.schema).expect("schema serializes");
+    strict(&mut schema);
+
+    schema
+}
+
+fn strict(schema: &mut Value) {
+    let Some(object) = schema.as_object_mut() else {
+        return;
+    };
+
+    // Formats like `uint32` or `partial-date-time` are not supported.
+    object.remove("format");
+    object.remove("title");
+
+    if let Some(Value::Object(properties)) = object.get_mut("properties") {
+        properties.values_mut().for_each(strict);
+        let required: Vec<Value> = properties.keys().cloned().map(Value::from).collect();
+        object.insert("required".to_string(), Value::Array(required));
+        object.insert("additionalProperties".to_string(), Value::Bool(false));
+    }
+    if let Some(items) = object.get_mut("items") {
+        strict(items);
+    }
+    for key in ["anyOf", "allOf", "oneOf"] {
+        if let Some(Value::Array(variants)) = object.get_mut(key) {
+            variants.iter_mut().for_each(strict);
+        }
+    }
+}
+
+fn lookup(table: &[(&str, &'static str)], key: &str) -> Option<&'static str> {
+    table.iter().find(|(k, _)| *k == key).map(|(_, text)| *text)
+}
+
+/// Russian descriptions of the fields of the type at `path` of a race.
+fn ru_fields(path: &str) -> &'static [(&'static str, &'static str)] {
+    match path {
+        "" => PredictResponse::RU,
+        "meta" => Meta::RU,
+        "predictions" => Prediction::RU,
+        _ => &[],
+    }
+}
+
+/// Field name as declared in Rust, e.g. `rawScore` -> `raw_score`.
+fn ident(name: &str) -> String {
+    let mut ident = String::with_capacity(name.len() + 4);
+    for c in name.chars() {
+        if c.is_ascii_uppercase() {
+            ident.push('_');
+        }
+        ident.push(c.to_ascii_lowercase());
+    }
+
+    ident
+}
+
+/// Description of the race field at `path`, `""` being the race itself.
+fn description(locale: SchemaLocale, path: &str) -> Option<&'static str> {
+    match locale {
+        SchemaLocale::En => lookup(EN, path),
+        SchemaLocale::Ru => {
+            let (parent, field) = path.rsplit_once('.').unwrap_or(("", path));
+            lookup(ru_fields(parent), &ident(field))
+        }
+    }
+}
+
+fn races_description(locale: SchemaLocale) -> Option<&'static str> {
+    match locale {
+        SchemaLocale::En => lookup(EN, "races"),
+        SchemaLocale::Ru => lookup(MultiPredictResponse::RU, "races"),
+    }
+}
+
+/// Sets the description of every field described for the locale. Fields
+/// without one keep the one taken from the doc comments.
+fn describe(schema: &mut Value, path: &str, locale: SchemaLocale) {
+    let Some(object) = schema.as_object_mut() else {
+        return;
+    };
+
+    if let Some(text) = description(locale, path) {
+        object.insert("description".to_string(), Value::from(text));
+    }
+
+    // Array items are described by the array itself, only their fields
+    // get their own paths.
+    let fields = match object.get_mut("items") {
+        Some(items) => items.get_mut("properties"),
+        None => object.get_mut("properties"),
+    };
+    if let Some(Value::Object(properties)) = fields {
+        for (name, property) in properties.iter_mut() {
+            let path = if path.is_empty() { name.clone() } else { format!("{path}.{name}") };
+            describe(property, &path, locale);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema(races: usize, locale: SchemaLocale) -> Value {
+        response_format(races, locale).schema.unwrap()
+    }
+
+    /// The schema of one race in a request with several races.
+    fn race(locale: SchemaLocale) -> Value {
+        schema(2, locale)["properties"]["races"]["items"].clone()
+    }
+
+    /// Property at a dotted path, stepping into array items.
+    fn at<'a>(schema: &'a Value, path: &str) -> &'a Value {
+        path.split('.').filter(|p| !p.is_empty()).fold(schema, |node, name| {
+            let node = node.get("items").unwrap_or(node);
+            &node["properties"][name]
+        })
+    }
+
+    fn keys(value: &Value) -> Vec<String> {
+        let mut keys: Vec<String> = value.as_object().unwrap().keys().cloned().collect();
+        keys.sort();
+        keys
+    }
+
+    fn assert_strict(schema: &Value, path: &str) {
+        let object = schema.as_object().unwrap();
+        assert!(!object.contains_key("format"), "{path} has a format");
+
+        if let Some(properties) = object.get("properties") {
+            let mut required: Vec<String> = object["required"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|v| v.as_str().unwrap().to_string())
+                .collect();
+            required.sort();
+            assert_eq!(required, keys(properties), "{path} does not require every property");
+            assert_eq!(object["additionalProperties"], false, "{path} allows more properties");
+
+            for (name, property) in properties.as_object().unwrap() {
+                assert_strict(property, &format!("{path}.{name}"));
+            }
+        }
+        if let Some(items) = object.get("items") {
+            assert_strict(items, path);
+        }
+    }
+
+    #[test]
+    fn schemas_are_strict() {
+        for races in [1, 3] {
+            let format = response_format(races, SchemaLocale::En);
+            assert_eq!(format.strict, Some(true));
+            assert_strict(format.schema.as_ref().unwrap(), "");
+        }
+    }
+
+    #[test]
+    fn race_id_only_with_several_races() {
+        assert!(schema(1, SchemaLocale::En)["properties"].get("raceId").is_none());
+
+        let race = race(SchemaLocale::En);
+        assert_eq!(race["required"][0], "raceId");
+        assert_eq!(race["properties"]["raceId"]["description"], lookup(EN, "raceId").unwrap());
+    }
+
+    #[test]
+    fn fields_follow_the_types() {
+        // Fields skipped in the schema are also skipped when empty.
+        let race = schema(1, SchemaLocale::En);
+        let prediction = serde_json::to_value(Prediction::default()).unwrap();
+        assert_eq!(keys(&at(&race, "predictions")["items"]["properties"]), keys(&prediction));
+        let meta = serde_json::to_value(Meta::default()).unwrap();
+        assert_eq!(keys(&at(&race, "meta")["properties"]), keys(&meta));
+    }
+
+    #[test]
+    fn rank_has_no_upper_bound() {
+        let race = schema(1, SchemaLocale::En);
+        let rank = at(&race, "predictions.rank");
+        assert_eq!(rank["minimum"], 1.0);
+        assert!(rank.get("maximum").is_none());
+    }
+
+    #[test]
+    fn every_ru_description_is_in_the_schema() {
+        let race = race(SchemaLocale::Ru);
+        for parent in ["", "meta", "predictions"] {
+            let node = at(&race, parent);
+            let properties = node.get("items").unwrap_or(node)["properties"].as_object().unwrap();
+            for (field, text) in ru_fields(parent) {
+                if field.is_empty() {
+                    assert_eq!(race["description"], *text);
+                    continue;
+                }
+                let property = properties
+                    .iter()
+                    .find(|(name, _)| ident(name) == *field)
+                    .unwrap_or_else(|| panic!("{parent}.{field} is not in the schema"))
+                    .1;
+                assert_eq!(property["description"], *text, "{parent}.{field}");
+            }
+        }
+
+        let races = &schema(2, SchemaLocale::Ru)["properties"]["races"];
+        assert_eq!(races["description"], lookup(MultiPredictResponse::RU, "races").unwrap());
+    }
+
+    #[test]
+    fn en_keeps_doc_comments() {
+        let race = schema(1, SchemaLocale::En);
+        assert_eq!(at(&race, "predictions.percentage")["description"], "Chance to win in percent.");
+    }
+}
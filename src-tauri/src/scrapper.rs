@@ -6,10 +6,17 @@ use log::{error, info};
 use mongodb::bson::{self, Bson, Document};
 use serde_json::json;
 
-use crate::constants::BASE_GRAYHOUND_URL;
+use crate::{
+    constants::BASE_GRAYHOUND_URL,
+    progress::{
+        Progress,
+        ProgressEvent
+    }
+};
 
 pub struct Scrapper {
     client: reqwest::Client,
+    progress: Progress,
 }
 
 impl Scrapper {
@@ -19,7 +26,12 @@ impl Scrapper {
             .build()
             .context("Failed to build reqwest::Cient")?;
 
-        Ok(Self { client })
+        Ok(Self { client, progress: Progress::none() })
+    }
+
+    pub fn with_progress(mut self, progress: Progress) -> Self {
+        self.progress = progress;
+        self
     }
 
     pub async fn get_daily_races(
@@ -90,8 +102,17 @@ impl Scrapper {
                     .unwrap_or("")
                     .to_string();
 
+                // e.g. "A3"; the key differs between listings.
+                let grade = ["raceGrade", "raceClass"]
+                    .iter()
+                    .find_map(|key| race.get(*key).and_then(|v| v.as_str()))
+                    .unwrap_or("")
+                    .trim()
+                    .to_string();
+
                 let mut race_map = HashMap::new();
                 race_map.insert("raceId".to_string(), race_id);
+                race_map.insert("grade".to_string(), grade);
                 race_map.insert("raceDate".to_string(), race_date);
                 race_map.insert("raceTime".to_string(), race_time);
                 race_map.insert("distance".to_string(), distance);
@@ -116,12 +137,19 @@ impl Scrapper {
             bail!("No race IDs found for date {}", date);
         }
 
+        let total = all_meetings_races.iter().map(Vec::len).sum();
+        let mut done = 0;
         for meeting_races in all_meetings_races {
             for race in meeting_races {
+                self.progress.check()?;
+                self.progress.emit(ProgressEvent::Scraping { done, total });
+                done += 1;
+
                 let race_id_str = race.get("raceId").cloned().unwrap_or_default(); // String
                 let race_time_str = race.get("raceTime").cloned().unwrap_or_default(); // String, "14:36"
                 let distance_str = race.get("distance").cloned().unwrap_or_default(); // String, "277m"
                 let race_date_str = race.get("raceDate").cloned().unwrap_or_default(); // String, "2025-06-02"
+                let grade = race.get("grade").filter(|g| !g.is_empty()).cloned(); // String, "A3"
 
                 // 1.1) "277m" -> 277i32
                 let distance_i32: i32 = distance_str
@@ -233,6 +261,9 @@ impl Scrapper {
                 doc.insert("race_date_time", dt_utc);
                 doc.insert("race_time", race_time_str);
                 doc.insert("race_id", Bson::Int64(race_id_u64 as i64));
+                if let Some(grade) = grade {
+                    doc.insert("grade", grade);
+                }
                 doc.insert("createdAt", bson::DateTime::now());
 
                 let bson_array_of_dogs = converted_dogs
@@ -248,6 +279,7 @@ impl Scrapper {
                 all_dogs_data.push(doc);
             }
         }
+        self.progress.emit(ProgressEvent::Scraping { done, total });
 
         Ok(all_dogs_data)
     }
@@ -300,9 +332,7 @@ impl Scrapper {
             "otherDTxt",
             "raceGradeId",
             "raceTime",
-            "resultDate",
             "resultsAvailable",
-            "trackId",
             "trackName",
         ];
 
@@ -511,6 +541,19 @@ impl Scrapper {
                             entry_doc.insert("raceClass", gc);
                         }
 
+                        // resultDate, trackId -> raceDate, trackName for run features
+                        if let Some(serde_json::Value::String(date)) = entry_map.get("resultDate") {
+                            let date = ["%Y-%m-%d", "%d/%m/%Y", "%d%b%y"]
+                                .iter()
+                                .find_map(|f| NaiveDate::parse_from_str(date.trim(), f).ok())
+                                .map(|d| d.format("%Y-%m-%d").to_string())
+                                .unwrap_or_else(|| date.clone());
+                            entry_doc.insert("raceDate", date);
+                        }
+                        if let Some(serde_json::Value::String(track_id)) = entry_map.get("trackId") {
+                            entry_doc.insert("trackName", self.convert_track_id(track_id));
+                        }
+
                         // Ading to the array
                         bson_forms.push(Bson::Document(entry_doc));
                     }
@@ -0,0 +1,211 @@
+use mongodb::bson::Document;
+
+use crate::{
+    features::{
+        FeatureContext,
+        RunnerFeatures
+    },
+    models::{
+        Meta,
+        PredictResponse,
+        Prediction,
+        RaceCard
+    }
+};
+
+/// Metres per second lost per second per 100m finished behind the winner.
+const GAP_WEIGHT: f64 = 0.5;
+/// Metres per second gained per second of faster first sectional.
+const SECTIONAL_WEIGHT: f64 = 0.3;
+/// Metres per second gained per unit of log trap win rate over an even
+/// share.
+const TRAP_WEIGHT: f64 = 0.15;
+/// Metres per second per speed figure point above the field's average.
+const FIGURE_WEIGHT: f64 = 0.08;
+/// Softmax temperature in metres per second.
+const TEMPERATURE: f64 = 0.35;
+/// Penalty for a runner without usable form, below the slowest rated one.
+const UNRATED_PENALTY: f64 = 0.5;
+
+/// Ranks the runners of a race by their recent going-adjusted speed.
+/// `None` when the document is not a race card.
+pub fn predict(race: &Document, context: &FeatureContext) -> Option<PredictResponse> {
+    let card = RaceCard::from_document(race)?;
+    let field = context.for_race(race)?;
+    if field.is_empty() {
+        return None;
+    }
+
+    let sectionals: Vec<f64> = field.iter().filter_map(|f| f.sectional_average).collect();
+    let field_sectional = (!sectionals.is_empty())
+        .then(|| sectionals.iter().sum::<f64>() / sectionals.len() as f64);
+
+    let figures: Vec<f64> = field.iter().filter_map(|f| f.speed_figure).collect();
+    let field_figure = (!figures.is_empty())
+        .then(|| figures.iter().sum::<f64>() / figures.len() as f64);
+
+    let scores: Vec<Option<f64>> = field
+        .iter()
+        .map(|f| {
+            let early = match (f.sectional_average, field_sectional) {
+                (Some(own), Some(field)) => (field - own) * SECTIONAL_WEIGHT,
+                _ => 0.0,
+            };
+            let draw = f
+                .trap_win_rate
+                .map_or(0.0, |rate| (rate * field.len() as f64).max(0.05).ln() * TRAP_WEIGHT);
+            // Figures compare runs at other tracks, which raw speed
+            // does not.
+            let form = match (f.speed_figure, field_figure) {
+                (Some(own), Some(field)) => (own - field) * FIGURE_WEIGHT,
+                _ => 0.0,
+            };
+            Some(f.speed? - f.winner_gap.unwrap_or(0.0) * GAP_WEIGHT + early + draw + form)
+        })
+        .collect();
+    let floor = scores
+        .iter()
+        .flatten()
+        .copied()
+        .reduce(f64::min)
+        .map_or(0.0, |min| min - UNRATED_PENALTY);
+    let scores: Vec<f64> = scores.iter().map(|s| s.unwrap_or(floor)).collect();
+
+    let probabilities = softmax(&scores, TEMPERATURE);
+
+    let mut predictions: Vec<Prediction> = field
+        .iter()
+        .zip(scores.iter().zip(&probabilities))
+        .map(|(f, (score, probability))| Prediction {
+            name: f.name.clone(),
+            raw_score: *score as f32,
+            percentage: (probability * 100.0) as f32,
+            rank: 0,
+            comment: Some(comment(f)),
+            confidence: None,
+        })
+        .collect();
+    rank(&mut predictions);
+
+    Some(PredictResponse {
+        race_id: Some(card.race_id),
+        meta: Meta {
+            date: card.date,
+            time: card.time,
+            distance: card.distance,
+            track: card.track,
+            grade: card.grade,
+        },
+        predictions,
+        summary: Some("Speed rating baseline".to_string()),
+        ..Default::default()
+    })
+}
+
+fn comment(features: &RunnerFeatures) -> String {
+    match features.speed {
+        Some(speed) => format!(
+            "{:.2} m/s over {} runs, {:.2}s/100m behind the winner",
+            speed,
+            features.runs,
+            features.winner_gap.unwrap_or(0.0)
+        ),
+        None => "No usable form".to_string(),
+    }
+}
+
+pub(crate) fn softmax(scores: &[f64], temperature: f64) -> Vec<f64> {
+    let max = scores.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let exps: Vec<f64> = scores.iter().map(|s| ((s - max) / temperature).exp()).collect();
+    let sum: f64 = exps.iter().sum();
+
+    exps.iter().map(|e| e / sum).collect()
+}
+
+/// Ranks by descending percentage, 1 is the favourite.
+pub(crate) fn rank(predictions: &mut [Prediction]) {
+    predictions.sort_by(|a, b| b.percentage.total_cmp(&a.percentage));
+    for (i, p) in predictions.iter_mut().enumerate() {
+        p.rank = i as u8 + 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::{
+        doc,
+        Bson
+    };
+
+    use super::*;
+
+    /// A dog with one run over 480m in `time` seconds, or no form.
+    fn dog(name: &str, trap: i32, time: Option<f64>) -> Bson {
+        let forms: Vec<Document> = time
+            .map(|time| doc! {
+                "raceDate": "2024-06-01",
+                "trackName": "Romford",
+                "distance": 480,
+                "resultRunTime": time,
+                "raceWinnersTime": 28.5,
+            })
+            .into_iter()
+            .collect();
+
+        Bson::Document(doc! { "dogName": name, "trapNumber": trap, "trackName": "Romford", "forms": forms })
+    }
+
+    fn race() -> Document {
+        doc! {
+            "race_id": 1,
+            "distance": 480,
+            "race_date": "2024-06-20",
+            "race_time": "19:00",
+            "dogs": [
+                dog("Slow", 1, Some(30.0)),
+                dog("Unraced", 2, None),
+                dog("Fast", 3, Some(28.5)),
+                dog("Middle", 4, Some(29.2)),
+            ],
+        }
+    }
+
+    fn prediction<'a>(response: &'a PredictResponse, name: &str) -> &'a Prediction {
+        response.predictions.iter().find(|p| p.name == name).unwrap()
+    }
+
+    #[test]
+    fn unrated_runner_goes_below_the_slowest() {
+        let response = predict(&race(), &FeatureContext::default()).unwrap();
+
+        let unraced = prediction(&response, "Unraced");
+        let slow = prediction(&response, "Slow");
+        assert!((unraced.raw_score - (slow.raw_score - UNRATED_PENALTY as f32)).abs() < 1e-4);
+        assert!(unraced.percentage < slow.percentage);
+        assert_eq!(unraced.rank, 4);
+    }
+
+    #[test]
+    fn percentages_add_up_to_a_hundred() {
+        let response = predict(&race(), &FeatureContext::default()).unwrap();
+
+        let total: f32 = response.predictions.iter().map(|p| p.percentage).sum();
+        assert!((total - 100.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn ranks_follow_the_percentages() {
+        let response = predict(&race(), &FeatureContext::default()).unwrap();
+
+        let ranks: Vec<u8> = response.predictions.iter().map(|p| p.rank).collect();
+        assert_eq!(ranks, [1, 2, 3, 4]);
+        assert!(response.predictions.windows(2).all(|w| w[0].percentage >= w[1].percentage));
+        assert_eq!(response.predictions[0].name, "Fast");
+        assert_eq!(response.race_id, Some(1));
+    }
+
+    #[test]
+    fn documents_that_are_not_cards_give_nothing() {
+        assert!(predict(&doc! { "dogs": [] }, &FeatureContext::default()).is_none());
+    }
+}
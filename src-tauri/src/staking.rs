@@ -0,0 +1,307 @@
+use crate::{
+    constants::BETFAIR_PERCENTAGE,
+    models::{
+        BetSide,
+        SettledBet,
+        StakingPlan,
+        StakingResults
+    },
+    strategy::Bet
+};
+
+/// Part of the Kelly stake used by the plan the others are compared with.
+const COMPARED_KELLY_FRACTION: f64 = 0.25;
+/// Stakes below this are not placed, like on the exchange.
+const MIN_STAKE: f64 = 0.01;
+
+impl StakingPlan {
+    /// The plans every backtest is also settled with: the fixed ones, a
+    /// percentage risking as much as the first fixed stake, and quarter
+    /// Kelly.
+    pub fn presets(initial_stake: f64, initial_balance: f64) -> [StakingPlan; 4] {
+        [
+            StakingPlan::FixedStake,
+            StakingPlan::FixedLiability,
+            StakingPlan::PercentOfBank { percent: initial_stake / initial_balance * 100.0 },
+            StakingPlan::Kelly { fraction: COMPARED_KELLY_FRACTION },
+        ]
+    }
+
+    /// Backer's stake of a lay at `odds`, the liability being
+    /// `stake * (odds - 1)`. 0 when the plan would not bet.
+    pub fn lay_stake(&self, odds: f64, probability: Option<f64>, balance: f64, initial_stake: f64) -> f64 {
+        let odds_against = odds - 1.0;
+        if odds_against <= 0.0 {
+            return 0.0;
+        }
+        let stake = match *self {
+            StakingPlan::FixedStake => initial_stake,
+            StakingPlan::FixedLiability => initial_stake / odds_against,
+            StakingPlan::PercentOfBank { percent } => balance * percent / 100.0 / odds_against,
+            StakingPlan::Kelly { fraction } => {
+                let Some(p) = probability else {
+                    return 0.0;
+                };
+                // Won per unit of liability when the dog loses.
+                let b = BETFAIR_PERCENTAGE / odds_against;
+                let liability = fraction * ((1.0 - p) * b - p) / b * balance;
+                liability / odds_against
+            }
+        };
+
+        r2(stake.max(0.0))
+    }
+
+    /// Stake of a back at `odds`. 0 when the plan would not bet.
+    pub fn back_stake(&self, odds: f64, probability: Option<f64>, balance: f64, initial_stake: f64) -> f64 {
+        let stake = match *self {
+            StakingPlan::FixedStake | StakingPlan::FixedLiability => initial_stake,
+            StakingPlan::PercentOfBank { percent } => balance * percent / 100.0,
+            StakingPlan::Kelly { fraction } => {
+                let Some(p) = probability else {
+                    return 0.0;
+                };
+                // Won per unit staked when the dog wins.
+                let b = (odds - 1.0) * BETFAIR_PERCENTAGE;
+                if b <= 0.0 {
+                    return 0.0;
+                }
+                fraction * (p * b - (1.0 - p)) / b * balance
+            }
+        };
+
+        r2(stake.max(0.0))
+    }
+}
+
+/// The balance of a backtest under one staking plan.
+#[derive(Debug, Clone)]
+pub struct Bank {
+    pub plan: StakingPlan,
+    pub balance: f64,
+    pub tracked_races: usize,
+    pub stopped: bool,
+    initial_balance: f64,
+    initial_stake: f64,
+}
+
+impl Bank {
+    pub fn new(plan: StakingPlan, initial_balance: f64, initial_stake: f64) -> Self {
+        Self {
+            plan,
+            balance: initial_balance,
+            tracked_races: 0,
+            stopped: false,
+            initial_balance,
+            initial_stake,
+        }
+    }
+
+    /// Stakes and settles the bets of a race, the commission taken from
+    /// the net winnings of the market. `None`, and no more betting, when
+    /// the balance cannot cover what the bets could lose.
+    pub fn settle(&mut self, bets: &[Bet]) -> Option<Vec<SettledBet>> {
+        if self.stopped {
+            return None;
+        }
+        let mut placed: Vec<SettledBet> = bets
+            .iter()
+            .map(|bet| {
+                let (stake, liability) = match bet.side {
+                    BetSide::Back => {
+                        let stake = self.plan.back_stake(bet.odds, bet.probability, self.balance, self.initial_stake);
+                        (stake, stake)
+                    }
+                    BetSide::Lay => {
+                        let stake = self.plan.lay_stake(bet.odds, bet.probability, self.balance, self.initial_stake);
+                        (stake, stake * (bet.odds - 1.0))
+                    }
+                };
+                let won = (bet.position == 1) == (bet.side == BetSide::Back);
+                let profit = match (won, bet.side) {
+                    (true, BetSide::Back) => stake * (bet.odds - 1.0),
+                    (true, BetSide::Lay) => stake,
+                    (false, _) => -liability,
+                };
+
+                SettledBet {
+                    dog_name: bet.dog_name.clone(),
+                    side: bet.side,
+                    odds: bet.odds,
+                    stake,
+                    liability,
+                    profit,
+                }
+            })
+            .filter(|bet| bet.stake >= MIN_STAKE)
+            .collect();
+
+        let obligation: f64 = placed.iter().map(|bet| bet.liability).sum();
+        if self.balance < obligation {
+            log::warn!(
+                "Недостаточно баланса ({}) для обязательства ставки {}. Прерываем.",
+                self.balance,
+                obligation
+            );
+            self.stopped = true;
+            return None;
+        }
+
+        // Betfair charges commission on what a market won overall; it is
+        // shared out between the winning bets.
+        let net: f64 = placed.iter().map(|bet| bet.profit).sum();
+        if net > 0.0 {
+            let commission = net * (1.0 - BETFAIR_PERCENTAGE);
+            let winnings: f64 = placed.iter().map(|bet| bet.profit.max(0.0)).sum();
+            for bet in placed.iter_mut().filter(|bet| bet.profit > 0.0) {
+                bet.profit -= commission * bet.profit / winnings;
+            }
+        }
+        for bet in placed.iter_mut() {
+            bet.liability = r2(bet.liability);
+            bet.profit = r2(bet.profit);
+        }
+
+        if !placed.is_empty() {
+            self.balance = r2(self.balance + placed.iter().map(|bet| bet.profit).sum::<f64>());
+            self.tracked_races += 1;
+        }
+
+        Some(placed)
+    }
+
+    /// Profit in percent of the initial balance.
+    pub fn percentage(&self) -> f64 {
+        r2((self.balance - self.initial_balance) / self.initial_balance * 100.0)
+    }
+
+    pub fn results(&self) -> StakingResults {
+        StakingResults {
+            plan: self.plan,
+            tracked_races: self.tracked_races,
+            final_balance: self.balance,
+            percentage: self.percentage(),
+            stopped: self.stopped,
+        }
+    }
+}
+
+fn r2(v: f64) -> f64 {
+    (v * 100.0).round() / 100.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kelly_lays_a_share_of_the_edge() {
+        let full = StakingPlan::Kelly { fraction: 1.0 };
+        let quarter = StakingPlan::Kelly { fraction: 0.25 };
+
+        // Liability 69.49 of a 100 bank at 3.0 on a 10% chance.
+        assert_eq!(full.lay_stake(3.0, Some(0.1), 100.0, 1.0), 34.74);
+        assert_eq!(quarter.lay_stake(3.0, Some(0.1), 100.0, 1.0), 8.69);
+        // Scales with the balance, not the initial stake.
+        assert_eq!(quarter.lay_stake(3.0, Some(0.1), 200.0, 5.0), 17.37);
+    }
+
+    #[test]
+    fn kelly_lays_nothing_without_an_edge_or_a_probability() {
+        let plan = StakingPlan::Kelly { fraction: 0.25 };
+
+        assert_eq!(plan.lay_stake(3.0, Some(0.4), 100.0, 1.0), 0.0);
+        assert_eq!(plan.lay_stake(3.0, None, 100.0, 1.0), 0.0);
+        assert_eq!(plan.lay_stake(1.0, Some(0.1), 100.0, 1.0), 0.0);
+    }
+
+    #[test]
+    fn kelly_backs_a_share_of_the_edge() {
+        let full = StakingPlan::Kelly { fraction: 1.0 };
+        let half = StakingPlan::Kelly { fraction: 0.5 };
+
+        assert_eq!(full.back_stake(4.0, Some(0.3), 100.0, 1.0), 6.07);
+        assert_eq!(half.back_stake(4.0, Some(0.3), 100.0, 1.0), 3.03);
+        assert_eq!(full.back_stake(4.0, Some(0.2), 100.0, 1.0), 0.0);
+        assert_eq!(full.back_stake(4.0, None, 100.0, 1.0), 0.0);
+        assert_eq!(full.back_stake(1.0, Some(0.9), 100.0, 1.0), 0.0);
+    }
+
+    fn bet(dog_name: &str, side: BetSide, odds: f64, position: u32) -> Bet {
+        Bet {
+            dog_name: dog_name.to_string(),
+            side,
+            odds,
+            position,
+            probability: None,
+        }
+    }
+
+    fn profits(settled: &[SettledBet]) -> Vec<f64> {
+        settled.iter().map(|bet| bet.profit).collect()
+    }
+
+    #[test]
+    fn commission_is_taken_from_a_winning_bet() {
+        let mut bank = Bank::new(StakingPlan::FixedStake, 100.0, 2.0);
+
+        let settled = bank.settle(&[bet("Dog 3", BetSide::Lay, 3.0, 3)]).unwrap();
+        assert_eq!(profits(&settled), [1.95]);
+        assert_eq!(bank.balance, 101.95);
+    }
+
+    #[test]
+    fn losing_bets_pay_no_commission() {
+        let mut bank = Bank::new(StakingPlan::FixedStake, 100.0, 2.0);
+
+        let settled = bank.settle(&[bet("Dog 1", BetSide::Lay, 3.0, 1)]).unwrap();
+        assert_eq!(profits(&settled), [-4.0]);
+        assert_eq!(settled[0].liability, 4.0);
+        assert_eq!(bank.balance, 96.0);
+    }
+
+    #[test]
+    fn commission_on_the_net_win_is_shared_by_the_winning_bets() {
+        let mut bank = Bank::new(StakingPlan::FixedStake, 100.0, 2.0);
+
+        // 6 and 2 won, 0.2 commission split 3 to 1.
+        let settled = bank
+            .settle(&[bet("Dog 1", BetSide::Back, 4.0, 1), bet("Dog 2", BetSide::Lay, 3.0, 2)])
+            .unwrap();
+        assert_eq!(profits(&settled), [5.85, 1.95]);
+        assert_eq!(bank.balance, 107.8);
+        assert_eq!(bank.tracked_races, 1);
+    }
+
+    #[test]
+    fn commission_is_only_charged_on_what_the_market_won_overall() {
+        let mut bank = Bank::new(StakingPlan::FixedStake, 100.0, 2.0);
+
+        // Net 2 of 6 won: the commission falls on the winner only.
+        let settled = bank
+            .settle(&[bet("Dog 1", BetSide::Back, 4.0, 1), bet("Dog 1", BetSide::Lay, 3.0, 1)])
+            .unwrap();
+        assert_eq!(profits(&settled), [5.95, -4.0]);
+        assert_eq!(bank.balance, 101.95);
+
+        // Even overall: nothing to charge.
+        let settled = bank
+            .settle(&[bet("Dog 1", BetSide::Back, 4.0, 2), bet("Dog 3", BetSide::Lay, 3.0, 3)])
+            .unwrap();
+        assert_eq!(profits(&settled), [-2.0, 2.0]);
+        assert_eq!(bank.balance, 101.95);
+    }
+
+    #[test]
+    fn stopped_bank_places_nothing() {
+        let mut bank = Bank::new(StakingPlan::FixedStake, 15.0, 2.0);
+
+        // A 20 liability on a 15 balance.
+        assert!(bank.settle(&[bet("Dog 1", BetSide::Lay, 11.0, 1)]).is_none());
+        assert!(bank.stopped);
+
+        assert!(bank.settle(&[bet("Dog 1", BetSide::Lay, 2.0, 1)]).is_none());
+        assert_eq!(bank.balance, 15.0);
+        assert_eq!(bank.tracked_races, 0);
+    }
+}
@@ -0,0 +1,336 @@
+use std::collections::HashMap;
+
+use anyhow::{
+    bail,
+    Result
+};
+use chrono::{
+    Days,
+    NaiveDate
+};
+use futures::TryStreamExt;
+use mongodb::{
+    bson::{
+        doc,
+        from_document,
+        DateTime,
+        Document
+    },
+    Collection,
+    Database
+};
+
+use crate::{
+    constants::{
+        DOG_INFO_COLLECTION,
+        SPEED_FIGURES_COLLECTION,
+        STANDARD_TIMES_COLLECTION
+    },
+    models::{
+        DateWindow,
+        DogRaceInfo,
+        SpeedFigure,
+        StandardTime,
+        StandardTimesInput
+    },
+    window::{
+        self,
+        day_start,
+        AsOf
+    }
+};
+
+/// Winning times a track and distance needs before it gets a standard.
+const MIN_RACES: usize = 20;
+/// Speed figures written per insert.
+const WRITE_BATCH: usize = 5_000;
+
+/// Track in lower case and distance.
+type Key = (String, u32);
+
+fn key(track: &str, distance: u32) -> Key {
+    (track.trim().to_lowercase(), distance)
+}
+
+/// Time with the going allowance taken out: `goingType` is in hundredths
+/// of a second, negative on a fast track.
+pub fn adjusted(time: f64, going: f64) -> f64 {
+    time - going
+}
+
+fn collection(database: &Database) -> Collection<StandardTime> {
+    database.collection(STANDARD_TIMES_COLLECTION)
+}
+
+fn figures(database: &Database) -> Collection<SpeedFigure> {
+    database.collection(SPEED_FIGURES_COLLECTION)
+}
+
+/// Derives the standards from the winners between `from` and `to` and
+/// stores them in place of earlier ones over the same window. The speed
+/// figures of the runs after the window are then figured again, as they
+/// may now use it; runs loaded later get theirs on the next compute.
+pub async fn compute(database: &Database, input: &StandardTimesInput) -> Result<Vec<StandardTime>> {
+    if input.from > input.to {
+        bail!("Period starts after it ends");
+    }
+    let mut filter = window::period(input.from, input.to);
+    filter.insert("resultPosition", 1);
+
+    let winners: Vec<DogRaceInfo> = database
+        .collection::<Document>(DOG_INFO_COLLECTION)
+        .find(filter)
+        .await?
+        .try_collect::<Vec<Document>>()
+        .await?
+        .into_iter()
+        .filter_map(|d| from_document(d).ok())
+        .collect();
+
+    let mut times: HashMap<Key, (String, Vec<f64>)> = HashMap::new();
+    for winner in &winners {
+        let Some(track) = winner.track_name.as_deref().filter(|t| !t.trim().is_empty()) else {
+            continue;
+        };
+        let Some(time) = winner.result_run_time.map(f64::from).filter(|t| *t > 0.0) else {
+            continue;
+        };
+        let going = winner.race_going.unwrap_or(0) as f64 / 100.0;
+        times
+            .entry(key(track, winner.distance))
+            .or_insert_with(|| (track.trim().to_string(), Vec::new()))
+            .1
+            .push(adjusted(time, going));
+    }
+
+    let created_at = Some(DateTime::now());
+    let mut table: Vec<StandardTime> = times
+        .into_iter()
+        .filter(|(_, (_, times))| times.len() >= MIN_RACES)
+        .map(|((_, distance), (track, mut times))| {
+            times.sort_by(f64::total_cmp);
+            let middle = times.len() / 2;
+            let standard = if times.len() % 2 == 0 {
+                (times[middle - 1] + times[middle]) / 2.0
+            } else {
+                times[middle]
+            };
+            let mean = times.iter().sum::<f64>() / times.len() as f64;
+            let deviation = (times.iter().map(|t| (t - mean).powi(2)).sum::<f64>() / times.len() as f64).sqrt();
+
+            StandardTime {
+                track,
+                distance,
+                from: input.from,
+                to: input.to,
+                races: times.len(),
+                standard,
+                deviation,
+                created_at,
+            }
+        })
+        .collect();
+    table.sort_by(|a, b| (&a.track, a.distance).cmp(&(&b.track, b.distance)));
+    log::info!(
+        "Standard times: {} tracks and distances from {} winners between {} and {}",
+        table.len(),
+        winners.len(),
+        input.from,
+        input.to
+    );
+
+    window::store(&collection(database), input.from, input.to, &table).await?;
+    let figured = store_figures(database, input.to).await?;
+    log::info!("Speed figures: {figured} runs after {} figured", input.to);
+
+    Ok(table)
+}
+
+/// Figures every run in `dog_race_info` after `after` against the stored
+/// standards, in place of the figures stored for those runs before.
+/// Returns the number of runs figured.
+async fn store_figures(database: &Database, after: NaiveDate) -> Result<usize> {
+    let table = Table::load(database).await?;
+    let filter = doc! { "raceDateTime": { "$gte": day_start(after + Days::new(1)) } };
+
+    let stored = figures(database);
+    stored.delete_many(filter.clone()).await?;
+
+    let mut cursor = database
+        .collection::<Document>(DOG_INFO_COLLECTION)
+        .find(filter)
+        .await?;
+
+    let mut pending: Vec<SpeedFigure> = Vec::new();
+    let mut figured = 0;
+    while let Some(document) = cursor.try_next().await? {
+        let Some(figure) = from_document::<DogRaceInfo>(document).ok().and_then(|run| table.run(&run)) else {
+            continue;
+        };
+        pending.push(figure);
+        figured += 1;
+        if pending.len() >= WRITE_BATCH {
+            stored.insert_many(&pending).await?;
+            pending.clear();
+        }
+    }
+    if !pending.is_empty() {
+        stored.insert_many(&pending).await?;
+    }
+
+    Ok(figured)
+}
+
+/// Every stored speed figure of a dog, oldest first.
+pub async fn history(database: &Database, dog_name: &str) -> Result<Vec<SpeedFigure>> {
+    Ok(figures(database)
+        .find(doc! { "dogName": dog_name })
+        .sort(doc! { "raceDateTime": 1 })
+        .await?
+        .try_collect()
+        .await?)
+}
+
+pub async fn stored(database: &Database) -> Result<Vec<StandardTime>> {
+    Ok(collection(database)
+        .find(doc! {})
+        .sort(doc! { "track": 1, "distance": 1, "to": 1, "createdAt": 1 })
+        .await?
+        .try_collect()
+        .await?)
+}
+
+/// Stored standards of every window, for figures as of a race date.
+#[derive(Debug, Default)]
+pub struct Table(AsOf<Key, StandardTime>);
+
+impl Table {
+    pub async fn load(database: &Database) -> Result<Self> {
+        let table = stored(database).await?;
+
+        Ok(Self(AsOf::new(table.into_iter().map(|standard| {
+            (key(&standard.track, standard.distance), standard.to, standard)
+        }))))
+    }
+
+    /// Speed figure of a going-adjusted time against the standard going
+    /// into a race on `date`: 100 at the standard, one point per 1% faster.
+    /// Comparable across tracks and distances. `None` for a track and
+    /// distance without a standard from a window that ended before that
+    /// day.
+    pub fn figure(&self, track: &str, distance: u32, adjusted_time: f64, date: NaiveDate) -> Option<f64> {
+        if adjusted_time <= 0.0 {
+            return None;
+        }
+        let standard = self.0.get(&key(track, distance), date)?;
+
+        Some(100.0 * standard.standard / adjusted_time)
+    }
+
+    /// Speed figure of a run as of its own day. `None` for a run without a
+    /// time or track, or without a standard before that day.
+    fn run(&self, run: &DogRaceInfo) -> Option<SpeedFigure> {
+        let track = run.track_name.as_deref().map(str::trim).filter(|t| !t.is_empty())?;
+        let time = run.result_run_time.map(f64::from).filter(|t| *t > 0.0)?;
+        let date = chrono::DateTime::from_timestamp_millis(run.race_date_time.timestamp_millis())?.date_naive();
+        let going = run.race_going.unwrap_or(0) as f64 / 100.0;
+        let standard = self.0.get(&key(track, run.distance), date)?;
+
+        Some(SpeedFigure {
+            dog_id: run.dog_id,
+            dog_name: run.dog_name.clone(),
+            race_id: run.race_id,
+            race_date_time: run.race_date_time,
+            track: track.to_string(),
+            distance: run.distance,
+            figure: self.figure(track, run.distance, adjusted(time, going), date)?,
+            standard: DateWindow { from: standard.from, to: standard.to },
+        })
+    }
+
+    /// Windows of the standards races before `date` can use, oldest first.
+    pub fn windows(&self, date: NaiveDate) -> Vec<DateWindow> {
+        let mut windows: Vec<DateWindow> = self
+            .0
+            .values()
+            .filter(|standard| standard.to < date)
+            .map(|standard| DateWindow { from: standard.from, to: standard.to })
+            .collect();
+        windows.sort_unstable();
+        windows.dedup();
+
+        windows
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, month, day).unwrap()
+    }
+
+    /// Romford 480m standard of `standard` seconds, derived up to `to`.
+    fn standard(to: NaiveDate, standard: f64) -> StandardTime {
+        StandardTime {
+            track: "Romford".to_string(),
+            distance: 480,
+            from: date(1, 1),
+            to,
+            races: MIN_RACES,
+            standard,
+            deviation: 0.3,
+            created_at: None,
+        }
+    }
+
+    fn table(standards: Vec<StandardTime>) -> Table {
+        Table(AsOf::new(standards.into_iter().map(|standard| {
+            (key(&standard.track, standard.distance), standard.to, standard)
+        })))
+    }
+
+    fn run(day: NaiveDate, time: f32, going: i32) -> DogRaceInfo {
+        DogRaceInfo {
+            dog_id: 7,
+            dog_name: "Dog".to_string(),
+            race_id: 1,
+            track_name: Some(" romford ".to_string()),
+            distance: 480,
+            result_run_time: Some(time),
+            race_going: Some(going),
+            race_date_time: day_start(day),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn run_is_figured_against_the_latest_standard_before_its_day() {
+        let table = table(vec![standard(date(3, 31), 30.0), standard(date(5, 31), 29.0)]);
+
+        let figure = table.run(&run(date(6, 1), 29.2, 20)).unwrap();
+
+        // 29.2 run on going +20 is 29.0 adjusted, the standard itself.
+        assert!((figure.figure - 100.0).abs() < 1e-4);
+        assert_eq!(figure.standard, DateWindow { from: date(1, 1), to: date(5, 31) });
+        assert_eq!(figure.track, "romford");
+    }
+
+    #[test]
+    fn run_on_the_last_day_of_a_window_uses_the_one_before() {
+        let table = table(vec![standard(date(3, 31), 30.0), standard(date(5, 31), 29.0)]);
+
+        let figure = table.run(&run(date(5, 31), 30.0, 0)).unwrap();
+        assert!((figure.figure - 100.0).abs() < 1e-4);
+
+        assert!(table.run(&run(date(3, 31), 30.0, 0)).is_none());
+    }
+
+    #[test]
+    fn run_without_a_time_is_not_figured() {
+        let table = table(vec![standard(date(3, 31), 30.0)]);
+
+        assert!(table.run(&run(date(6, 1), 0.0, 0)).is_none());
+    }
+}
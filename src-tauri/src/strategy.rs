@@ -0,0 +1,301 @@
+use crate::models::{
+    BetSide,
+    OddsRange,
+    Prediction,
+    StrategyKind,
+    TestParams
+};
+
+/// A runner of a settled race: its price a minute before the off and
+/// where it finished.
+#[derive(Debug, Clone)]
+pub struct Runner {
+    pub name: String,
+    pub odds: f64,
+    pub position: u32,
+}
+
+/// What a strategy sees of a race.
+pub struct Race<'a> {
+    /// Ranked predictions, favourite first.
+    pub predictions: &'a [Prediction],
+    pub runners: &'a [Runner],
+    pub odds_range: OddsRange,
+}
+
+impl Race<'_> {
+    pub fn runner(&self, name: &str) -> Option<&Runner> {
+        self.runners.iter().find(|r| r.name == name)
+    }
+
+    /// Shortest price in the race.
+    pub fn favourite_odds(&self) -> f64 {
+        self.runners.iter().map(|r| r.odds).fold(f64::INFINITY, f64::min)
+    }
+
+    /// The prediction of a rank and its runner.
+    pub fn ranked(&self, rank: u8) -> Option<(&Prediction, &Runner)> {
+        let prediction = self.predictions.iter().find(|p| p.rank == rank)?;
+        Some((prediction, self.runner(&prediction.name)?))
+    }
+
+    pub fn in_range(&self, runner: &Runner) -> bool {
+        (self.odds_range.low..=self.odds_range.high).contains(&runner.odds)
+    }
+}
+
+/// A bet on one runner at its price.
+#[derive(Debug, Clone)]
+pub struct Bet {
+    pub dog_name: String,
+    pub side: BetSide,
+    pub odds: f64,
+    pub position: u32,
+    /// Predicted win chance, 0 to 1.
+    pub probability: Option<f64>,
+}
+
+impl Bet {
+    fn on(side: BetSide, runner: &Runner, prediction: Option<&Prediction>) -> Self {
+        Self {
+            dog_name: runner.name.clone(),
+            side,
+            odds: runner.odds,
+            position: runner.position,
+            probability: prediction.map(|p| p.percentage as f64 / 100.0),
+        }
+    }
+}
+
+/// Why a strategy left dogs out of a race, counted in the test results.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Skip {
+    /// Dogs qualified but none was priced within the odds range.
+    OddsRange,
+    /// The market favourite qualified and was passed over.
+    Favourite,
+}
+
+/// What a strategy decided for a race.
+#[derive(Debug, Clone, Default)]
+pub struct Selection {
+    /// Possibly none, when nothing qualified.
+    pub bets: Vec<Bet>,
+    pub skip: Option<Skip>,
+}
+
+impl Selection {
+    fn bet(bet: Bet) -> Self {
+        Self { bets: vec![bet], skip: None }
+    }
+
+    fn skipped(skip: Skip) -> Self {
+        Self { bets: Vec::new(), skip: Some(skip) }
+    }
+}
+
+/// Picks the bets of a race from its predictions and prices. Settlement
+/// and staking are left to the backtester.
+pub trait Strategy: Send + Sync {
+    fn select(&self, race: &Race) -> Selection;
+}
+
+/// The strategy a backtest was asked for.
+pub fn from_params(params: &TestParams) -> Box<dyn Strategy> {
+    match params.strategy {
+        StrategyKind::LayBottomTwo => Box::new(LayBottomTwo {
+            favourite_protected: params.is_favorite_protected,
+        }),
+        StrategyKind::LayRank { rank } => Box::new(LayRank { rank }),
+        StrategyKind::LayBelow { percentage } => Box::new(LayBelow { percentage }),
+        StrategyKind::BackRank { rank } => Box::new(BackRank { rank }),
+    }
+}
+
+/// Lays the shorter-priced of the two lowest-ranked dogs within the odds
+/// range. With favourite protection the market favourite is never laid,
+/// the other one is taken instead.
+pub struct LayBottomTwo {
+    pub favourite_protected: bool,
+}
+
+impl Strategy for LayBottomTwo {
+    fn select(&self, race: &Race) -> Selection {
+        let bottom = &race.predictions[race.predictions.len().saturating_sub(2)..];
+        let mut candidates: Vec<Bet> = bottom
+            .iter()
+            .filter_map(|p| {
+                let runner = race.runner(&p.name)?;
+                race.in_range(runner).then(|| Bet::on(BetSide::Lay, runner, Some(p)))
+            })
+            .collect();
+        if candidates.is_empty() {
+            return Selection::skipped(Skip::OddsRange);
+        }
+        candidates.sort_by(|a, b| a.odds.total_cmp(&b.odds));
+
+        let favourite = race.favourite_odds();
+        if !self.favourite_protected || candidates[0].odds != favourite {
+            return Selection::bet(candidates.swap_remove(0));
+        }
+        // The favourite is counted as skipped even when the other dog is
+        // laid instead.
+        match candidates.get(1) {
+            Some(next) if next.odds != favourite => Selection {
+                bets: vec![next.clone()],
+                skip: Some(Skip::Favourite),
+            },
+            _ => Selection::skipped(Skip::Favourite),
+        }
+    }
+}
+
+/// Lays the dog of one predicted rank, e.g. the one ranked last.
+pub struct LayRank {
+    pub rank: u8,
+}
+
+impl Strategy for LayRank {
+    fn select(&self, race: &Race) -> Selection {
+        single(race, self.rank, BetSide::Lay)
+    }
+}
+
+/// Lays every dog predicted a win chance below `percentage` within the
+/// odds range.
+pub struct LayBelow {
+    pub percentage: f32,
+}
+
+impl Strategy for LayBelow {
+    fn select(&self, race: &Race) -> Selection {
+        let qualified: Vec<(&Prediction, &Runner)> = race
+            .predictions
+            .iter()
+            .filter(|p| p.percentage < self.percentage)
+            .filter_map(|p| Some((p, race.runner(&p.name)?)))
+            .collect();
+        let bets: Vec<Bet> = qualified
+            .iter()
+            .filter(|(_, runner)| race.in_range(runner))
+            .map(|(prediction, runner)| Bet::on(BetSide::Lay, runner, Some(prediction)))
+            .collect();
+
+        if bets.is_empty() && !qualified.is_empty() {
+            Selection::skipped(Skip::OddsRange)
+        } else {
+            Selection { bets, skip: None }
+        }
+    }
+}
+
+/// Backs the dog of one predicted rank, e.g. the top-rated one.
+pub struct BackRank {
+    pub rank: u8,
+}
+
+impl Strategy for BackRank {
+    fn select(&self, race: &Race) -> Selection {
+        single(race, self.rank, BetSide::Back)
+    }
+}
+
+/// One bet on the dog of `rank` when it is priced within the odds range.
+fn single(race: &Race, rank: u8, side: BetSide) -> Selection {
+    let Some((prediction, runner)) = race.ranked(rank) else {
+        return Selection::default();
+    };
+
+    if race.in_range(runner) {
+        Selection::bet(Bet::on(side, runner, Some(prediction)))
+    } else {
+        Selection::skipped(Skip::OddsRange)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Six runners ranked in card order, the last two priced as given.
+    fn race_with_bottom(fifth: f64, sixth: f64) -> (Vec<Prediction>, Vec<Runner>) {
+        let odds = [3.0, 4.0, 6.0, 8.0, fifth, sixth];
+        let predictions = (1..=6)
+            .map(|rank| Prediction {
+                name: format!("Dog {rank}"),
+                rank,
+                percentage: 10.0,
+                ..Default::default()
+            })
+            .collect();
+        let runners = odds
+            .iter()
+            .enumerate()
+            .map(|(i, odds)| Runner {
+                name: format!("Dog {}", i + 1),
+                odds: *odds,
+                position: i as u32 + 1,
+            })
+            .collect();
+
+        (predictions, runners)
+    }
+
+    fn select(favourite_protected: bool, predictions: &[Prediction], runners: &[Runner], high: f64) -> Selection {
+        LayBottomTwo { favourite_protected }.select(&Race {
+            predictions,
+            runners,
+            odds_range: OddsRange { low: 1.01, high },
+        })
+    }
+
+    fn laid(selection: &Selection) -> Vec<&str> {
+        selection.bets.iter().map(|bet| bet.dog_name.as_str()).collect()
+    }
+
+    #[test]
+    fn lays_the_shorter_priced_of_the_bottom_two() {
+        let (predictions, runners) = race_with_bottom(5.0, 7.0);
+
+        let selection = select(true, &predictions, &runners, 10.0);
+        assert_eq!(laid(&selection), ["Dog 5"]);
+        assert_eq!(selection.skip, None);
+    }
+
+    #[test]
+    fn protected_favourite_is_passed_over_for_the_other_dog() {
+        let (predictions, runners) = race_with_bottom(5.0, 2.0);
+
+        let selection = select(true, &predictions, &runners, 10.0);
+        assert_eq!(laid(&selection), ["Dog 5"]);
+        assert_eq!(selection.skip, Some(Skip::Favourite));
+        assert_eq!(selection.bets[0].side, BetSide::Lay);
+    }
+
+    #[test]
+    fn unprotected_favourite_is_laid() {
+        let (predictions, runners) = race_with_bottom(5.0, 2.0);
+
+        let selection = select(false, &predictions, &runners, 10.0);
+        assert_eq!(laid(&selection), ["Dog 6"]);
+        assert_eq!(selection.skip, None);
+    }
+
+    #[test]
+    fn protected_favourite_alone_in_range_skips_the_race() {
+        let (predictions, runners) = race_with_bottom(12.0, 2.0);
+
+        let selection = select(true, &predictions, &runners, 10.0);
+        assert!(selection.bets.is_empty());
+        assert_eq!(selection.skip, Some(Skip::Favourite));
+    }
+
+    #[test]
+    fn joint_favourites_are_both_protected() {
+        let (predictions, runners) = race_with_bottom(2.0, 2.0);
+
+        let selection = select(true, &predictions, &runners, 10.0);
+        assert!(selection.bets.is_empty());
+        assert_eq!(selection.skip, Some(Skip::Favourite));
+    }
+}
@@ -1,3 +1,5 @@
+use std::path::PathBuf;
+
 use anyhow::{
     anyhow, 
     Result
@@ -11,36 +13,57 @@ use mongodb::{bson::{
     doc, 
     Bson, 
     Document
-}, Cursor};
+}, Cursor, Database};
 use futures::stream::TryStreamExt;
 
 use crate::{
+    baseline, 
+    cache::ResponseCache, 
     client::OpenAIClient, 
     constants::{
         DOG_INFO_COLLECTION, 
         MAX_REQUEST_DEFENCE
     }, 
     models::{
-        OddsRange, 
+        BatchJob, 
+        EnsembleOptions, 
+        ModelInfo, 
+        PredictorBackend, 
+        PromptEstimate, 
+        RaceCard, 
         RequestsInfo, 
         Settings, 
         TestDateTime, 
+        TestParams, 
         TestResults
     }, 
-    utils::build_requests
+    batch::BatchClient, 
+    ensemble::Ensemble, 
+    progress::Progress, 
+    utils::{
+        backtest, 
+        build_requests, 
+        estimate_prompts, 
+        save_test_run
+    }
 };
 
 #[allow(unused)]
 pub struct Tester {
     db_client: mongodb::Client,
     config: Settings,
+    model: ModelInfo,
     date_time: TestDateTime,
-    distances: Vec<i32>
+    distances: Vec<i32>,
+    ensemble: Option<EnsembleOptions>,
+    backend: PredictorBackend,
+    progress: Progress
 }
 
 impl Tester {
     pub fn new(
         config: Settings, 
+        model: ModelInfo,
         db_client: mongodb::Client,
         date_time: TestDateTime,
         distances: Vec<i32>
@@ -48,15 +71,62 @@ impl Tester {
         Self { 
             db_client, 
             config,
+            model,
             date_time,
-            distances
+            distances,
+            ensemble: None,
+            backend: PredictorBackend::Llm,
+            progress: Progress::none()
         }
     }
 
+    pub fn with_ensemble(mut self, ensemble: Option<EnsembleOptions>) -> Self {
+        self.ensemble = ensemble;
+        self
+    }
+
+    pub fn with_backend(mut self, backend: PredictorBackend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    pub fn with_progress(mut self, progress: Progress) -> Self {
+        self.progress = progress;
+        self
+    }
+
     async fn generate_races(&self) -> Result<RequestsInfo> {
         let database = self.db_client
             .default_database()
             .ok_or_else(|| anyhow!("No default database"))?;
+
+        let races = self.load_races().await?;
+        let total_races = races.len();
+        let requests = build_requests(
+            races,
+            database,
+            self.config.clone()
+        ).await?;
+
+        Ok(RequestsInfo { requests, total_races })
+    }
+
+    /// Token estimate of the requests `run` would send, in every prompt
+    /// format.
+    pub async fn estimate_prompts(&self) -> Result<Vec<PromptEstimate>> {
+        let database = self.db_client
+            .default_database()
+            .ok_or_else(|| anyhow!("No default database"))?;
+
+        let races = self.load_races().await?;
+        estimate_prompts(races, database, self.config.clone(), &self.model).await
+    }
+
+    /// Backtest races in the range, each dog with its runs before the race.
+    async fn load_races(&self) -> Result<Vec<Document>> {
+        let database = self.db_client
+            .default_database()
+            .ok_or_else(|| anyhow!("No default database"))?;
         
         let collection = database.collection::<Document>(DOG_INFO_COLLECTION);
 
@@ -123,6 +193,7 @@ impl Tester {
 
         let mut races = Vec::new();
         for (idx, race_id) in race_ids.into_iter().enumerate() {
+            self.progress.check()?;
             log::info!("> [{}/{}] raceId={}", idx + 1, total, race_id);
             
             let filter = doc! { "raceId": Bson::Int64(race_id) };
@@ -179,8 +250,13 @@ impl Tester {
                     }
                 };
 
+                // Only runs before this race, its own result must not leak
+                // into the form.
                 let mut form_docs: Vec<_> = collection
-                    .find(doc! { "dogId": dog_id })
+                    .find(doc! {
+                        "dogId": dog_id,
+                        "raceDateTime": { "$lt": race_date_time }
+                    })
                     .await?
                     .try_collect()
                     .await?;
@@ -241,19 +317,24 @@ impl Tester {
                     let comm = form.get_str("resultComment")?;
                     let calc = form.get_f64("resultRunTime")?;
                     let outcome = form.get_i32("resultPosition")?;
-
-                    let going_type = match dog_result
+                    let form_date = form
+                        .get_datetime("raceDateTime")?
+                        .try_to_rfc3339_string()?
+                        .chars()
+                        .take(10)
+                        .collect::<String>();
+
+                    // The going of that run, not of the race being tested.
+                    // Older records lack it; like `Run::from_info`, they
+                    // count as a normal going.
+                    let going_type = form
                         .get_i32("raceGoing")
-                    {
-                        Ok(val) => val,
-                        Err(_) => match dog_result.get_i64("raceGoing") {
-                            Ok(val) => val as i32,
-                            Err(err) => {
-                                log::error!("{err}");
-                                panic!()
-                            }
-                        }
-                    };
+                        .ok()
+                        .or_else(|| form.get_i64("raceGoing").ok().map(|val| val as i32))
+                        .unwrap_or_else(|| {
+                            log::warn!("Form run of race {form_race_id} without raceGoing, taken as 0");
+                            0
+                        });
 
                     let form_doc = doc! {
                         "btnDistance": by,
@@ -267,6 +348,8 @@ impl Tester {
                         "sectionalTime": sectional.map(Bson::from).unwrap_or(Bson::Null),
                         "resultPosition": outcome,
                         "distance": distance,
+                        "raceDate": form_date,
+                        "trackName": form.get_str("trackName").ok(),
                     };
                     forms_array.push(Bson::Document(form_doc));
                 }
@@ -291,6 +374,7 @@ impl Tester {
                 "race_time": race_time,
                 "race_id": race_id,
                 "distance": dist,
+                "grade": meta.get_str("raceClass").ok(),
                 "dogs": Bson::Array(dogs),
             };
 
@@ -310,27 +394,91 @@ impl Tester {
             log::info!("Defenced to {} requests", races.len());
         }
 
+        Ok(races)
+    }
+
+    pub async fn run(&self, params: TestParams) -> Result<TestResults> {
+        let database = self.db_client
+            .default_database()
+            .ok_or_else(|| anyhow!("Failed to get default database"))?;
+
+        let races = self.load_races().await?;
+        let (results, label) = match self.backend {
+            PredictorBackend::Llm => self.run_llm(races.clone(), &database, &params).await?,
+            backend => (self.run_baseline(&races, &database, &params).await?, backend.as_str().to_string()),
+        };
+
+        let results = match self.backend {
+            PredictorBackend::Market => results,
+            _ => {
+                let cards = races.iter().filter_map(RaceCard::from_document).collect();
+                baseline::with_market(results, cards, &database, &params).await
+            }
+        };
+
+        save_test_run(&database, &label, &results).await?;
+
+        Ok(results)
+    }
+
+    /// Backtest through the model, or the ensemble when one is set.
+    /// Returns the results and the label the run is saved under.
+    async fn run_llm(
+        &self,
+        races: Vec<Document>,
+        database: &Database,
+        params: &TestParams
+    ) -> Result<(TestResults, String)> {
         let total_races = races.len();
-        let requests = build_requests(
-            races,
-            database.clone(),
-            self.config.clone()
-        ).await?;
+        let requests = build_requests(races, database.clone(), self.config.clone()).await?;
+        let requests_info = RequestsInfo { requests, total_races };
 
-        Ok(RequestsInfo { requests, total_races })
+        log::info!(
+            "{} requests, {} races in total", 
+            requests_info.requests.len(), 
+            requests_info.total_races
+        );
+
+        let cache = ResponseCache::new(database, self.config.cache_mode);
+        let client = OpenAIClient::new(self.config.clone(), self.model.clone())
+            .with_cache(cache)
+            .with_progress(self.progress.clone());
+
+        match &self.ensemble {
+            Some(options) => {
+                let ensemble = Ensemble::load(database, client, options).await?;
+                Ok((ensemble.test(requests_info, database, params).await?, options.label(&self.model.id)))
+            }
+            None => {
+                let results = client
+                    .test(requests_info, database.clone(), params)
+                    .await?;
+                Ok((results, self.model.id.clone()))
+            }
+        }
     }
 
-    pub async fn run(
+    /// Backtest of a local backend: nothing is sent, so there is no
+    /// usage and no requests to report.
+    async fn run_baseline(
         &self,
-        initial_balance: f64,
-        initial_stake: f64,
-        odds_range: OddsRange,
-        is_favorite_protected: bool
+        races: &[Document],
+        database: &Database,
+        params: &TestParams
     ) -> Result<TestResults> {
+        let requests_info = RequestsInfo { requests: Vec::new(), total_races: races.len() };
+        let execution = baseline::execute(self.backend, races, database).await?;
+
+        backtest(execution, requests_info, database, params, &self.progress).await
+    }
+
+    /// Submits the backtest as a batch; the input file is written to
+    /// `files_dir`.
+    pub async fn submit_batch(&self, params: TestParams, files_dir: PathBuf) -> Result<BatchJob> {
         let requests_info = self.generate_races().await?;
 
         log::info!(
-            "{} requests, {} races in total", 
+            "Submitting batch: {} requests, {} races in total", 
             requests_info.requests.len(), 
             requests_info.total_races
         );
@@ -339,17 +487,11 @@ impl Tester {
             .default_database()
             .ok_or_else(|| anyhow!("Failed to get default database"))?;
 
-        let client = OpenAIClient::new(self.config.clone());
-    
-        client
-            .test(
-                requests_info, 
-                database,
-                initial_balance,
-                initial_stake,
-                odds_range,
-                is_favorite_protected
-            )
+        let client = OpenAIClient::new(self.config.clone(), self.model.clone());
+
+        BatchClient::new(client, &database)
+            .with_files_dir(files_dir)
+            .submit(requests_info, params)
             .await
     }
 }
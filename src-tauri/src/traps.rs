@@ -0,0 +1,315 @@
+use std::collections::HashMap;
+
+use anyhow::{
+    bail,
+    Result
+};
+use chrono::NaiveDate;
+use futures::TryStreamExt;
+use mongodb::{
+    bson::{
+        doc,
+        from_document,
+        DateTime,
+        Document
+    },
+    Collection,
+    Database
+};
+
+use crate::{
+    constants::{
+        DOG_INFO_COLLECTION,
+        TRAP_BIAS_COLLECTION
+    },
+    models::{
+        DogRaceInfo,
+        TrapBias,
+        TrapBiasInput,
+        TrapStats
+    },
+    window::{
+        self,
+        AsOf
+    }
+};
+
+/// Races a grade needs before its own figures are used over the ones of
+/// all grades together.
+const MIN_GRADE_RACES: usize = 30;
+/// Normal quantile of the 95% intervals.
+const Z: f64 = 1.96;
+
+/// Track, distance and grade, the track in lower case.
+type Key = (String, u32, Option<String>);
+
+fn key(track: &str, distance: u32, grade: Option<&str>) -> Key {
+    (track.trim().to_lowercase(), distance, grade.map(|g| g.trim().to_uppercase()))
+}
+
+#[derive(Default)]
+struct Tally {
+    runs: usize,
+    wins: usize,
+    places: usize,
+    positions: f64,
+    squares: f64,
+}
+
+impl Tally {
+    fn stats(&self, trap: u32) -> TrapStats {
+        let n = self.runs as f64;
+        let average = self.positions / n;
+        let spread = if self.runs > 1 {
+            ((self.squares - n * average * average) / (n - 1.0)).max(0.0).sqrt() / n.sqrt()
+        } else {
+            0.0
+        };
+
+        TrapStats {
+            trap,
+            runs: self.runs,
+            wins: self.wins,
+            places: self.places,
+            win_rate: self.wins as f64 / n,
+            win_interval: wilson(self.wins, self.runs),
+            place_rate: self.places as f64 / n,
+            place_interval: wilson(self.places, self.runs),
+            average_position: average,
+            position_interval: (average - Z * spread, average + Z * spread),
+        }
+    }
+}
+
+/// Wilson score interval of a share, sound for small samples and rates
+/// near 0 or 1.
+fn wilson(hits: usize, n: usize) -> (f64, f64) {
+    if n == 0 {
+        return (0.0, 1.0);
+    }
+    let n = n as f64;
+    let p = hits as f64 / n;
+    let z2 = Z * Z;
+    let centre = (p + z2 / (2.0 * n)) / (1.0 + z2 / n);
+    let margin = Z * (p * (1.0 - p) / n + z2 / (4.0 * n * n)).sqrt() / (1.0 + z2 / n);
+
+    ((centre - margin).max(0.0), (centre + margin).min(1.0))
+}
+
+fn collection(database: &Database) -> Collection<TrapBias> {
+    database.collection(TRAP_BIAS_COLLECTION)
+}
+
+/// Aggregates the settled races between `from` and `to` and stores the
+/// figures in place of earlier ones over the same window.
+pub async fn compute(database: &Database, input: &TrapBiasInput) -> Result<Vec<TrapBias>> {
+    if input.from > input.to {
+        bail!("Period starts after it ends");
+    }
+
+    let records: Vec<DogRaceInfo> = database
+        .collection::<Document>(DOG_INFO_COLLECTION)
+        .find(window::period(input.from, input.to))
+        .await?
+        .try_collect::<Vec<Document>>()
+        .await?
+        .into_iter()
+        .filter_map(|d| from_document(d).ok())
+        .collect();
+
+    let mut races: HashMap<u64, Vec<DogRaceInfo>> = HashMap::new();
+    for record in records {
+        races.entry(record.race_id).or_default().push(record);
+    }
+
+    let mut tallies: HashMap<Key, (String, usize, HashMap<u32, Tally>)> = HashMap::new();
+    for race in races.values() {
+        // Dead heats and half-loaded results would skew the rates.
+        if race.iter().filter(|r| r.result_position == 1).count() != 1 {
+            continue;
+        }
+        let Some(track) = race[0].track_name.as_deref().filter(|t| !t.trim().is_empty()) else {
+            continue;
+        };
+        let distance = race[0].distance;
+        let grade = race[0].race_class.as_deref().filter(|g| !g.trim().is_empty());
+
+        let mut keys = vec![key(track, distance, None)];
+        if input.by_grade {
+            keys.extend(grade.map(|g| key(track, distance, Some(g))));
+        }
+        for key in keys {
+            let (_, races, traps) = tallies
+                .entry(key)
+                .or_insert_with(|| (track.trim().to_string(), 0, HashMap::new()));
+            *races += 1;
+            for runner in race.iter().filter(|r| r.result_position > 0) {
+                let Some(trap) = runner.trap_number else {
+                    continue;
+                };
+                let position = runner.result_position as f64;
+                let tally = traps.entry(trap).or_default();
+                tally.runs += 1;
+                tally.wins += usize::from(runner.result_position == 1);
+                tally.places += usize::from(runner.result_position <= 2);
+                tally.positions += position;
+                tally.squares += position * position;
+            }
+        }
+    }
+
+    let created_at = Some(DateTime::now());
+    let mut table: Vec<TrapBias> = tallies
+        .into_iter()
+        .map(|((_, distance, grade), (track, races, traps))| {
+            let mut traps: Vec<TrapStats> = traps.iter().map(|(trap, tally)| tally.stats(*trap)).collect();
+            traps.sort_by_key(|t| t.trap);
+            TrapBias {
+                track,
+                distance,
+                grade,
+                from: input.from,
+                to: input.to,
+                races,
+                traps,
+                created_at,
+            }
+        })
+        .collect();
+    table.sort_by(|a, b| (&a.track, a.distance, &a.grade).cmp(&(&b.track, b.distance, &b.grade)));
+    log::info!(
+        "Trap bias: {} groups from {} races between {} and {}",
+        table.len(),
+        races.len(),
+        input.from,
+        input.to
+    );
+
+    window::store(&collection(database), input.from, input.to, &table).await?;
+
+    Ok(table)
+}
+
+/// Stored figures, optionally for one track and distance.
+pub async fn stored(database: &Database, track: Option<&str>, distance: Option<u32>) -> Result<Vec<TrapBias>> {
+    let filter = match distance {
+        Some(distance) => doc! { "distance": distance },
+        None => doc! {},
+    };
+    let table: Vec<TrapBias> = collection(database)
+        .find(filter)
+        .sort(doc! { "track": 1, "distance": 1, "grade": 1, "to": 1, "createdAt": 1 })
+        .await?
+        .try_collect()
+        .await?;
+
+    Ok(table
+        .into_iter()
+        .filter(|bias| track.is_none_or(|t| bias.track.eq_ignore_ascii_case(t.trim())))
+        .collect())
+}
+
+/// Stored figures of every window, for lookups as of a race date.
+#[derive(Debug, Default)]
+pub struct Table(AsOf<Key, TrapBias>);
+
+impl Table {
+    pub async fn load(database: &Database) -> Result<Self> {
+        let table = stored(database, None, None).await?;
+
+        Ok(Self(AsOf::new(table.into_iter().map(|bias| {
+            (key(&bias.track, bias.distance, bias.grade.as_deref()), bias.to, bias)
+        }))))
+    }
+
+    /// Figures of a trap at a track and distance going into a race on
+    /// `date`, from its grade when that has enough races. `None` without
+    /// figures from a window that ended before that day.
+    pub fn lookup(&self, track: &str, distance: u32, grade: Option<&str>, trap: u32, date: NaiveDate) -> Option<&TrapStats> {
+        let graded = grade
+            .and_then(|g| self.0.get(&key(track, distance, Some(g)), date))
+            .filter(|bias| bias.races >= MIN_GRADE_RACES);
+        let bias = graded.or_else(|| self.0.get(&key(track, distance, None), date))?;
+
+        bias.traps.iter().find(|t| t.trap == trap)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 6, day).unwrap()
+    }
+
+    /// Figures of trap 1 at Romford 400m over the first half of June, the
+    /// trap winning `wins` of 20 runs.
+    fn bias(grade: Option<&str>, races: usize, wins: usize) -> TrapBias {
+        let tally = Tally { runs: 20, wins, places: wins, positions: 60.0, squares: 200.0 };
+
+        TrapBias {
+            track: "Romford".to_string(),
+            distance: 400,
+            grade: grade.map(str::to_string),
+            from: date(1),
+            to: date(15),
+            races,
+            traps: vec![tally.stats(1)],
+            created_at: None,
+        }
+    }
+
+    fn table(figures: Vec<TrapBias>) -> Table {
+        Table(AsOf::new(figures.into_iter().map(|bias| {
+            (key(&bias.track, bias.distance, bias.grade.as_deref()), bias.to, bias)
+        })))
+    }
+
+    #[test]
+    fn wilson_of_no_hits_starts_at_zero() {
+        let (low, high) = wilson(0, 20);
+
+        assert_eq!(low, 0.0);
+        assert!(high > 0.0 && high < 0.2);
+    }
+
+    #[test]
+    fn wilson_of_all_hits_ends_at_one() {
+        let (low, high) = wilson(20, 20);
+
+        assert!((high - 1.0).abs() < 1e-12);
+        assert!(low > 0.8 && low < 1.0);
+        assert!((low - (1.0 - wilson(0, 20).1)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn wilson_without_runs_is_unknown() {
+        assert_eq!(wilson(0, 0), (0.0, 1.0));
+    }
+
+    #[test]
+    fn lookup_uses_the_grade_from_its_minimum_races() {
+        let table = table(vec![bias(None, 500, 2), bias(Some("A5"), MIN_GRADE_RACES, 6)]);
+
+        let stats = table.lookup(" ROMFORD ", 400, Some("a5"), 1, date(20)).unwrap();
+        assert_eq!(stats.wins, 6);
+    }
+
+    #[test]
+    fn lookup_falls_back_below_the_minimum_races() {
+        let table = table(vec![bias(None, 500, 2), bias(Some("A5"), MIN_GRADE_RACES - 1, 6)]);
+
+        let stats = table.lookup("Romford", 400, Some("A5"), 1, date(20)).unwrap();
+        assert_eq!(stats.wins, 2);
+    }
+
+    #[test]
+    fn lookup_needs_a_window_ended_before_the_race() {
+        let table = table(vec![bias(None, 500, 2)]);
+
+        assert!(table.lookup("Romford", 400, None, 1, date(15)).is_none());
+        assert!(table.lookup("Romford", 400, None, 2, date(20)).is_none());
+        assert!(table.lookup("Romford", 400, None, 1, date(16)).is_some());
+    }
+}
@@ -1,16 +1,30 @@
 use std::collections::HashMap;
 use anyhow::{
     bail, 
-    Context, 
     Result
 };
-use async_openai::types::ResponseFormatJsonSchema;
+use futures::{
+    stream, 
+    Stream, 
+    StreamExt
+};
 use mongodb::{
     bson::{
         doc, 
+        to_bson, 
+        to_document, 
+        DateTime, 
         Document
     }, 
-    Database
+    error::{
+        Error as MongoError, 
+        ErrorKind, 
+        WriteFailure
+    }, 
+    options::IndexOptions, 
+    Collection, 
+    Database, 
+    IndexModel
 };
 use serde_json::{
     json, 
@@ -19,159 +33,293 @@ use serde_json::{
 
 use crate::{
     constants::{
-        BETFAIR_PERCENTAGE, 
-        INSTRUCTION_COLLECTION
+        DOG_INFO_COLLECTION, 
+        MODELS_COLLECTION, 
+        TEST_RUNS_COLLECTION, 
+        USAGE_COLLECTION
     }, 
     models::{
         Balance, 
         InstructionDoc, 
+        ModelInfo, 
         OddsRange, 
-        PosOdds, 
         PositionInfo, 
+        PromptEstimate, 
+        PromptFormat, 
         PredictResponse, 
+        RaceCard, 
         RaceCount, 
+        RequestsInfo, 
+        StakingPlan, 
         Settings, 
         SkipInfo, 
         TestErrors, 
+        TestParams, 
+        TestResults, 
         TestResultsDog, 
         TestResultsMeta, 
         TestResultsRace, 
         TestResultsRaceMeta, 
-        TestResultsRealResults
+        TestResultsRealResults, 
+        TokenUsage
+    }, 
+    client::Execution, 
+    features::FeatureContext, 
+    instructions, 
+    progress::{
+        Progress, 
+        ProgressEvent
     }, 
-    DogInfoRepo
+    prompt, 
+    staking::Bank, 
+    strategy::{
+        self, 
+        Race, 
+        Runner, 
+        Skip, 
+        Strategy
+    }, 
+    DogInfoRepo, 
+    MongoDogInfoRepo
 };
 
+pub async fn load_model_info(database: &Database, id: &str) -> Result<ModelInfo> {
+    let found = database
+        .collection::<ModelInfo>(MODELS_COLLECTION)
+        .find_one(doc! { "id": id })
+        .await?;
+
+    let model = match found {
+        Some(model) => model,
+        None => match ModelInfo::defaults().into_iter().find(|m| m.id == id) {
+            Some(model) => model,
+            None => {
+                log::warn!("Model '{id}' is not in the catalogue, sending only common parameters");
+                ModelInfo::unknown(id)
+            }
+        }
+    };
+
+    Ok(model)
+}
+
+/// Makes `keys` unique in the collection. Creating an index that exists
+/// already does nothing.
+pub async fn unique_index<T: Send + Sync>(collection: &Collection<T>, keys: Document) -> Result<()> {
+    let index = IndexModel::builder()
+        .keys(keys)
+        .options(IndexOptions::builder().unique(true).build())
+        .build();
+    collection.create_index(index).await?;
+
+    Ok(())
+}
+
+/// Whether an insert failed on a unique index.
+pub fn is_duplicate_key(err: &MongoError) -> bool {
+    const DUPLICATE_KEY: i32 = 11000;
+    matches!(
+        err.kind.as_ref(),
+        ErrorKind::Write(WriteFailure::WriteError(e)) if e.code == DUPLICATE_KEY
+    )
+}
+
+/// Appends a usage record, `kind` is either "predict" or "test".
+pub async fn save_usage(
+    database: &Database,
+    kind: &str,
+    model: &str,
+    usage: TokenUsage
+) -> Result<()> {
+    let date = chrono::Utc::now().date_naive().to_string();
+
+    database
+        .collection::<Document>(USAGE_COLLECTION)
+        .insert_one(doc! {
+            "date": date,
+            "kind": kind,
+            "model": model,
+            "usage": to_bson(&usage)?,
+            "createdAt": DateTime::now()
+        })
+        .await?;
+
+    Ok(())
+}
+
+/// Stores the run with the model and, through the results, the
+/// instruction version that produced it.
+pub async fn save_test_run(
+    database: &Database,
+    model: &str,
+    results: &TestResults
+) -> Result<()> {
+    let mut doc = to_document(results)?;
+    doc.insert("model", model);
+    doc.insert("createdAt", DateTime::now());
+
+    database
+        .collection::<Document>(TEST_RUNS_COLLECTION)
+        .insert_one(doc)
+        .await?;
+
+    save_usage(database, "test", model, results.usage()).await
+}
+
+/// Rough token count for budgeting and prompt size estimates
+/// (~4 characters per token for English/JSON text).
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
 pub async fn build_requests(
     races: Vec<Document>,
     database: Database,
     config: Settings
 ) -> Result<Vec<HashMap<String, Value>>> {
-    let instruction = database
-        .collection::<InstructionDoc>(INSTRUCTION_COLLECTION)
-        .find_one(doc! { "name": config.instruction_name.as_str() })
-        .await?
-        .with_context(|| format!("Not instruction with such name: {}", config.instruction_name.as_str()))?;
+    let instruction = instructions::latest(&database, &config.instruction_name).await?;
+    let races = with_features(races, &database, &config).await;
+
+    Ok(render_requests(&races, &instruction, &config))
+}
+
+/// The races with their runner features when the settings include them.
+async fn with_features(mut races: Vec<Document>, database: &Database, config: &Settings) -> Vec<Document> {
+    if config.include_features {
+        let context = FeatureContext::load(database, &races).await;
+        races.iter_mut().for_each(|race| context.annotate(race));
+    }
+
+    races
+}
 
+fn render_requests(
+    races: &[Document],
+    instruction: &InstructionDoc,
+    config: &Settings
+) -> Vec<HashMap<String, Value>> {
     let mut requests = Vec::new();
     for chunk in races.chunks(config.races_per_request) {
-        let meta = json!({ "model": config.model });
+        let race_ids: Vec<Value> = chunk
+            .iter()
+            .filter_map(|r| r.get("race_id"))
+            .filter_map(|id| id.as_i64().or_else(|| id.as_i32().map(i64::from)))
+            .map(Value::from)
+            .collect();
+        let cards: Vec<RaceCard> = chunk
+            .iter()
+            .filter_map(RaceCard::from_document)
+            .collect();
+        let system_content = instructions::render(&instruction.content, &cards);
+        let meta = json!({
+            "model": config.model,
+            "raceIds": race_ids,
+            "races": cards,
+            "instruction": instruction.reference()
+        });
         let mut map = HashMap::new();
 
         map.insert("meta".to_string(), meta);
         
-        let system = json!({ "role": "system", "content": instruction.content.as_str() });
-        let user = json!({ "role": "user", "content": json!({ "races": chunk }).to_string()});
+        let system = json!({ "role": "system", "content": system_content });
+        let user = json!({ "role": "user", "content": prompt::render(chunk, config.prompt_format) });
         
         map.insert("messages".to_string(), json!([ system, user ]));
         
         requests.push(map);
     }
 
-    Ok(requests)
+    requests
 }
 
-pub fn get_response_format_json_schema() -> ResponseFormatJsonSchema {
-    let description = None;
-    let name = "PredictionResponse".to_string();
-    let strict = Some(true);
-    let schema = json!({
-        "$schema": "http://json-schema.org/draft-07/schema#",
-        "title": "PredictionResponse",
-        "type": "object",
-        "additionalProperties": false,
-        "properties": {
-            "meta": {
-                "type": "object",
-                "description": "Метаинформация о гонке",
-                "additionalProperties": false,
-                "properties": {
-                    "date": {
-                        "type": "string",
-                        "description": "Дата гонки (YYYY-MM-DD). Я передаю ее в поле raceDateTime, будь внимателен!! Это очень важно!"
-                    },
-                    "time": {
-                        "type": "string",
-                        "description": "Время гонки (HH:MM:SS)"
-                    },
-                    "distance": {
-                        "type": "integer",
-                        "description": "Дистанция гонки (например, 480)"
-                    },
-                    "track": {
-                        "type": "string",
-                        "description": "Название трека. Очень важно! Выдавай всегда"
-                    },
-                    "grade": {
-                        "type": "string",
-                        "description": "Класс гонки"
-                    }
-                },
-                "required": ["date", "time", "distance", "track", "grade"]
-            },
-            "predictions": {
-                "type": "array",
-                "description": "Рейтинг собак по шансам на победу",
-                "items": {
-                    "type": "object",
-                    "additionalProperties": false,
-                    "properties": {
-                        "name": { 
-                            "type": "string", 
-                            "description": "Имя собаки" 
-                        },
-                        "rawScore": { 
-                            "type": "number", 
-                            "description": "Суммарный Raw Score" 
-                        },
-                        "percentage": { 
-                            "type": "number", 
-                            "min": 0, 
-                            "max": 100, 
-                            "description": "Шанс победы в процентах" 
-                        },
-                        "rank": { 
-                            "type": "integer", 
-                            "min": 1, 
-                            "max": 6, 
-                            "description": "Позиция в прогнозе (1 — фаворит)" 
-                        },
-                        "comment": { 
-                            "type": "string", 
-                            "description": "Краткий комментарий по результату. Выдавай его всегда" 
-                        }
-                    },
-                    "required": ["name", "rawScore", "percentage", "rank", "comment"]
-                }
-            },
-            "summary": {
-                "type": ["string", "null"],
-                "description": "Краткое заключение по прогнозу. Выдавай его всегда!"
-            }
-        },
-        "required": ["meta", "predictions", "summary"]
-    });
-
-    ResponseFormatJsonSchema {
-        description,
-        name,
-        schema: Some(schema),
-        strict,
+/// Size of the requests the races would produce in every prompt format,
+/// so the cheapest one can be picked before sending.
+pub async fn estimate_prompts(
+    races: Vec<Document>,
+    database: Database,
+    config: Settings,
+    model: &ModelInfo
+) -> Result<Vec<PromptEstimate>> {
+    let instruction = instructions::latest(&database, &config.instruction_name).await?;
+    let races = with_features(races, &database, &config).await;
+
+    let mut estimates = Vec::with_capacity(PromptFormat::ALL.len());
+    for format in PromptFormat::ALL {
+        let requests = render_requests(
+            &races,
+            &instruction,
+            &Settings { prompt_format: format, ..config.clone() }
+        );
+
+        let tokens: usize = requests
+            .iter()
+            .filter_map(|r| r.get("messages"))
+            .map(|m| estimate_tokens(&m.to_string()))
+            .sum();
+
+        estimates.push(PromptEstimate {
+            format,
+            requests: requests.len(),
+            tokens,
+            cost: tokens as f64 * model.input_price / 1_000_000.0
+        });
     }
+
+    Ok(estimates)
+}
+
+/// Settles the bets for an executed test and assembles its results.
+pub async fn backtest(
+    execution: Execution,
+    requests_info: RequestsInfo,
+    database: &Database,
+    params: &TestParams,
+    progress: &Progress
+) -> Result<TestResults> {
+    let predictions = stream::iter(execution.predictions);
+    let (meta, races) = settle(predictions, requests_info.total_races, database, params, progress).await?;
+
+    Ok(TestResults::new(meta, races, requests_info.requests, execution.usage, execution.failed))
 }
 
+/// Settles the bets of the predictions as they come, against the real
+/// results stored in the database.
+pub async fn settle(
+    predictions: impl Stream<Item = PredictResponse>,
+    total_races: usize,
+    database: &Database,
+    params: &TestParams,
+    progress: &Progress
+) -> Result<(TestResultsMeta, Vec<TestResultsRace>)> {
+    let repo = MongoDogInfoRepo::new(database.collection(DOG_INFO_COLLECTION));
+
+    process_test_results(
+        predictions,
+        &repo,
+        total_races,
+        params.initial_balance,
+        params.initial_stake,
+        params.odds_range,
+        params.min_confidence,
+        strategy::from_params(params).as_ref(),
+        params.staking,
+        progress
+    ).await
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn process_test_results<R: DogInfoRepo>(
-    predictions: Vec<PredictResponse>,
+    predictions: impl Stream<Item = PredictResponse>,
     repo: &R,
     total_races: usize,
     initial_balance: f64,
     initial_stake: f64,
     odds_range: OddsRange,
-    is_favorite_protected: bool,
+    min_confidence: Option<f32>,
+    strategy: &dyn Strategy,
+    staking: StakingPlan,
+    progress: &Progress,
 ) -> Result<(TestResultsMeta, Vec<TestResultsRace>)> {
-    if predictions.is_empty() {
-        bail!("Пустой ответ от LLM модели, выход из функции. Выход изз функции тестирования.");
-    }
     if initial_balance <= 2.0 || !initial_balance.is_normal() {
         bail!("Неверный стартовый баланс: {initial_balance}. Выход из функции тестирования.");
     }
@@ -191,18 +339,25 @@ pub async fn process_test_results<R: DogInfoRepo>(
         );
     }
 
-    let mut tracked_races: usize = 0;
     // let mut total_empty_content = 0;
     // let mut total_race_parse_error = 0;
     let mut total_mongo_db_error = 0;
     let mut bad_hit_4_pos = 0;
     let mut bad_hit_5_pos = 0;
     let mut bad_hit_6_pos = 0;
-    let mut current_balance = initial_balance;
+    let mut bank = Bank::new(staking, initial_balance, initial_stake);
+    let mut compared: Vec<Bank> = StakingPlan::presets(initial_stake, initial_balance)
+        .into_iter()
+        .filter(|plan| *plan != staking)
+        .map(|plan| Bank::new(plan, initial_balance, initial_stake))
+        .collect();
+    let mut stopped_at = None;
     let mut skipped_races_lt5 = 0;
     let mut skipped_races_gt6 = 0;
     let mut skipped_odds_range = 0;
     let mut skipped_favorite = 0;
+    let mut skipped_low_confidence = 0;
+    let mut skipped_no_confidence = 0;
     let mut races = Vec::with_capacity(total_races);
 
     log::info!(
@@ -214,186 +369,167 @@ pub async fn process_test_results<R: DogInfoRepo>(
         odds_range.high
     );
 
-    'preds: for mut predict in predictions {
-            predict.sort_predictions();
-
-            let meta_pred = &predict.meta;
-
-            let dogs = match repo.race_participants(meta_pred.date, meta_pred.time).await {
-                Ok(v) => v,
-                Err(error) => {
-                    total_mongo_db_error += 1;
-                    log::error!("{error}");
-                    continue;
-                }
-            };
-
-            let n_participants = dogs.len();
-            if !(5..=6).contains(&n_participants) {
-                if n_participants < 5 {
-                    skipped_races_lt5 += 1;
-                } else {
-                    skipped_races_gt6 += 1;
-                }
-                log::warn!(
-                    "Пропущенна гонка. Кол-во участников: {}; skipped_races_lt5 == {}; skipped_races_gt6 == {}",
-                    n_participants,
-                    skipped_races_lt5,
-                    skipped_races_gt6
-                );
+    let mut predictions = std::pin::pin!(predictions);
+    let mut received = 0;
+    // After each race, skipped or settled.
+    let emit_settled = |done| progress.emit(ProgressEvent::RacesSettled { done, total: total_races });
+    while let Some(mut predict) = predictions.next().await {
+        received += 1;
+        predict.sort_predictions();
+
+        let meta_pred = &predict.meta;
+
+        let dogs = match repo.race_participants(meta_pred.date, meta_pred.time).await {
+            Ok(v) => v,
+            Err(error) => {
+                total_mongo_db_error += 1;
+                log::error!("{error}");
+                emit_settled(received);
                 continue;
             }
+        };
 
-            let favorite_odds = dogs
-                .iter()
-                .map(|d| d.bf_odds_1_minute)
-                .fold(f64::INFINITY, f64::min);
-
-            let mut odds_info = Vec::new();
-            for p in &predict.predictions[predict.predictions.len().saturating_sub(2)..] {
-                let rec = match repo
-                    .dog_record(meta_pred.date, meta_pred.time, meta_pred.distance, &p.name)
-                    .await
-                {
-                    Ok(Some(r)) => r,
-                    Ok(None) => continue,
-                    Err(e) => {
-                        total_mongo_db_error += 1;
-                        log::error!("{e}");
-                        continue;
-                    }
-                };
-
-                match (p.rank as i32, rec.result_position) {
-                    (4, 1) => bad_hit_4_pos += 1,
-                    (5, 1) => bad_hit_5_pos += 1,
-                    (6, 1) => bad_hit_6_pos += 1,
-                    _ => {}
-                }
-
-                if !(odds_range.low..=odds_range.high).contains(&rec.bf_odds_1_minute) {
-                    // skipped_odds_range += 1;
-                    log::info!(
-                        "Коэффициент не входит в указанный диапозон: {}; skipped_odds_range: {}",
-                        rec.bf_odds_1_minute,
-                        skipped_odds_range
-                    );
-                    continue;
-                }
-
-                odds_info.push(PosOdds {
-                    real_position: rec.result_position,
-                    odds: rec.bf_odds_1_minute,
-                });
+        let n_participants = dogs.len();
+        if !(5..=6).contains(&n_participants) {
+            if n_participants < 5 {
+                skipped_races_lt5 += 1;
+            } else {
+                skipped_races_gt6 += 1;
             }
+            log::warn!(
+                "Пропущенна гонка. Кол-во участников: {}; skipped_races_lt5 == {}; skipped_races_gt6 == {}",
+                n_participants,
+                skipped_races_lt5,
+                skipped_races_gt6
+            );
+            emit_settled(received);
+            continue;
+        }
 
-            let mut test_dogs = Vec::with_capacity(n_participants);
-            for dog in dogs.iter() {
-                let record_opt = repo
-                    .dog_record(meta_pred.date, meta_pred.time, meta_pred.distance, &dog.dog_name)
-                    .await
-                    .ok()
-                    .flatten();
-
-                let (rank, odds_res) = if let Some(record) = &record_opt {
-                    (record.result_position as u8, record.bf_odds_1_minute as f32)
-                } else {
-                    (0, 0.0)
-                };
-
-                let model_pred = predict
-                    .predictions
-                    .iter()
-                    .find(|p| p.name.eq(&dog.dog_name))
-                    .cloned()
-                    .unwrap_or_default();
-
-                let real_results = TestResultsRealResults::new(rank, odds_res);
-
-                test_dogs.push(TestResultsDog::new(
-                    dog.dog_name.clone(),
-                    model_pred,
-                    real_results,
-                ));
+        let runners: Vec<Runner> = dogs
+            .iter()
+            .map(|d| Runner {
+                name: d.dog_name.clone(),
+                odds: d.bf_odds_1_minute,
+                position: d.result_position,
+            })
+            .collect();
+        let race = Race {
+            predictions: &predict.predictions,
+            runners: &runners,
+            odds_range,
+        };
+
+        for p in &predict.predictions[predict.predictions.len().saturating_sub(2)..] {
+            match (p.rank, race.runner(&p.name).map(|r| r.position)) {
+                (4, Some(1)) => bad_hit_4_pos += 1,
+                (5, Some(1)) => bad_hit_5_pos += 1,
+                (6, Some(1)) => bad_hit_6_pos += 1,
+                _ => {}
             }
+        }
 
-            let bet_target_opt: Option<PosOdds> = if odds_info.is_empty() {
-                skipped_odds_range += 1;
-                None
+        let mut test_dogs = Vec::with_capacity(n_participants);
+        for dog in dogs.iter() {
+            let record_opt = repo
+                .dog_record(meta_pred.date, meta_pred.time, meta_pred.distance, &dog.dog_name)
+                .await
+                .ok()
+                .flatten();
+
+            let (rank, odds_res) = if let Some(record) = &record_opt {
+                (record.result_position as u8, record.bf_odds_1_minute as f32)
             } else {
-                odds_info.sort_by(|a, b| a.odds.partial_cmp(&b.odds).unwrap());
-                if is_favorite_protected {
-                    match odds_info.len() {
-                        1 => {
-                            if odds_info[0].odds == favorite_odds {
-                                skipped_favorite += 1;
-                                None
-                            } else {
-                                Some(odds_info[0])
-                            }
-                        }
-                        _ => {
-                            if odds_info[0].odds == favorite_odds {
-                                skipped_favorite += 1;
-                                if odds_info[1].odds == favorite_odds {
-                                    // skipped_favorite += 1;
-                                    None
-                                } else {
-                                    Some(odds_info[1])
-                                }
-                            } else {
-                                Some(odds_info[0])
-                            }
-                        }
-                    }
-                } else {
-                    Some(odds_info[0])
-                }
+                (0, 0.0)
             };
 
-            if let Some(bet_target) = bet_target_opt {
-                let obligation = initial_stake * (bet_target.odds - 1.0);
-                if current_balance < obligation {
-                    log::warn!(
-                        "Недостаточно баланса ({}) для обязательства ставки {}. Прерываем.",
-                        current_balance,
-                        obligation
-                    );
-                    break 'preds;
-                }
-
-                if bet_target.real_position == 1 {
-                    current_balance -= obligation;
-                    current_balance = r2(current_balance);
-                } else {
-                    current_balance += initial_stake * BETFAIR_PERCENTAGE;
-                    current_balance = r2(current_balance);
-                }
-                tracked_races += 1;
-            }
+            let model_pred = predict
+                .predictions
+                .iter()
+                .find(|p| p.name.eq(&dog.dog_name))
+                .cloned()
+                .unwrap_or_default();
+
+            let real_results = TestResultsRealResults::new(rank, odds_res);
 
-            let mut profit = ((current_balance - initial_balance) / initial_balance) * 100.0;
-            profit = r2(profit);
-            let race_meta = TestResultsRaceMeta::new(
-                meta_pred.date,
-                meta_pred.distance,
-                meta_pred.grade.clone(),
-                meta_pred.time,
-                meta_pred.track.clone(),
-                r2(current_balance),
-                profit,
+            test_dogs.push(TestResultsDog::new(
+                dog.dog_name.clone(),
+                model_pred,
+                real_results,
+            ));
+        }
+
+        // With a threshold set, a race without a confidence cannot
+        // pass it: logprobs were off or the values were not found.
+        let bets = if min_confidence.is_some() && predict.confidence.is_none() {
+            skipped_no_confidence += 1;
+            log::info!("Нет уверенности модели; skipped_no_confidence: {}", skipped_no_confidence);
+            Vec::new()
+        } else if min_confidence.zip(predict.confidence).is_some_and(|(min, confidence)| confidence < min) {
+            skipped_low_confidence += 1;
+            log::info!(
+                "Низкая уверенность модели: {:?}; skipped_low_confidence: {}",
+                predict.confidence,
+                skipped_low_confidence
             );
+            Vec::new()
+        } else {
+            let selection = strategy.select(&race);
+            match selection.skip {
+                Some(Skip::OddsRange) => skipped_odds_range += 1,
+                Some(Skip::Favourite) => skipped_favorite += 1,
+                None => {}
+            }
+            selection.bets
+        };
+
+        let race_id = dogs.first().unwrap().race_id;
+        // A bank that ran out stops betting; the others go on with the
+        // same bets until every one of them is out.
+        let mut settled = Vec::new();
+        if !bets.is_empty() {
+            let was_stopped = bank.stopped;
+            settled = bank.settle(&bets).unwrap_or_default();
+            if bank.stopped && !was_stopped {
+                stopped_at = Some(race_id);
+            }
+            for other in compared.iter_mut() {
+                other.settle(&bets);
+            }
+        }
+        let current_balance = bank.balance;
+
+        let profit = bank.percentage();
+        let race_meta = TestResultsRaceMeta::new(
+            meta_pred.date,
+            meta_pred.distance,
+            meta_pred.grade.clone(),
+            meta_pred.time,
+            meta_pred.track.clone(),
+            r2(current_balance),
+            profit,
+        );
 
-            let race_summary = predict.summary.clone().unwrap_or_default();
-            let race_id = dogs.first().unwrap().race_id;
-            let race_struct = TestResultsRace::new(race_id, race_meta, test_dogs, race_summary);
-            races.push(race_struct);
+        let race_summary = predict.summary.clone().unwrap_or_default();
+        let race_struct = TestResultsRace::new(race_id, race_meta, test_dogs, race_summary).with_bets(settled);
+        progress.emit(ProgressEvent::RaceSettled {
+            race: Box::new(race_struct.clone()),
+            balance: r2(current_balance)
+        });
+        races.push(race_struct);
+        emit_settled(received);
+
+        if bank.stopped && compared.iter().all(|other| other.stopped) {
+            break;
         }
+    }
+
+    if received == 0 {
+        bail!("Пустой ответ от LLM модели, выход из функции. Выход изз функции тестирования.");
+    }
 
-    let mut percentage = ((current_balance - initial_balance) / initial_balance) * 100.0;
-    percentage = r2(percentage);
     let meta = TestResultsMeta::new(
-        RaceCount::new(total_races, tracked_races),
+        RaceCount::new(total_races, bank.tracked_races),
         odds_range,
         PositionInfo::new(bad_hit_4_pos, bad_hit_5_pos, bad_hit_6_pos),
         SkipInfo::new(
@@ -401,12 +537,16 @@ pub async fn process_test_results<R: DogInfoRepo>(
             skipped_races_gt6,
             skipped_odds_range,
             skipped_favorite,
+            skipped_low_confidence,
+            skipped_no_confidence,
         ),
-        Balance::new(initial_balance, r2(current_balance)),
+        Balance::new(initial_balance, bank.balance),
         TestErrors::new(0, 0, total_mongo_db_error),
         initial_stake,
-        percentage,
-    );
+        bank.percentage(),
+    )
+    .with_staking(staking, compared.iter().map(Bank::results).collect())
+    .with_stopped_at(stopped_at);
 
     Ok((meta, races))
 }
@@ -0,0 +1,487 @@
+use std::{
+    collections::HashMap,
+    fmt
+};
+
+use serde_json::{
+    json,
+    Value
+};
+
+use crate::models::{
+    PredictResponse,
+    RaceCard
+};
+
+/// Allowed deviation of the percentage sum from 100 before it is rescaled.
+const PERCENTAGE_TOLERANCE: f32 = 1.0;
+
+/// A way a parsed response disagrees with the race it was asked about.
+/// The `Display` text is sent back to the model when repair fails.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationError {
+    UnknownRunner(String),
+    MissingRunner(String),
+    DuplicateRunner(String),
+    InvalidRanks(Vec<u8>),
+    InvalidPercentage { name: String, value: f32 },
+    PercentageSum(f32),
+    MetaMismatch { field: &'static str, expected: String, actual: String },
+    UnknownRace(u64),
+    MissingRace(u64),
+    DuplicateRace(u64),
+    InRace { race_id: u64, error: Box<ValidationError> },
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownRunner(name) => write!(f, "\"{name}\" is not a runner in this race"),
+            Self::MissingRunner(name) => write!(f, "runner \"{name}\" has no prediction"),
+            Self::DuplicateRunner(name) => write!(f, "runner \"{name}\" is predicted more than once"),
+            Self::InvalidRanks(ranks) => write!(f, "ranks {ranks:?} must be 1..{} with no repeats", ranks.len()),
+            Self::InvalidPercentage { name, value } => write!(f, "percentage {value} for \"{name}\" must be between 0 and 100"),
+            Self::PercentageSum(sum) => write!(f, "percentages sum to {sum}, expected 100"),
+            Self::MetaMismatch { field, expected, actual } => write!(f, "meta.{field} is \"{actual}\", expected \"{expected}\""),
+            Self::UnknownRace(id) => write!(f, "race {id} was not sent"),
+            Self::MissingRace(id) => write!(f, "race {id} has no prediction"),
+            Self::DuplicateRace(id) => write!(f, "race {id} is predicted more than once"),
+            Self::InRace { race_id, error } => write!(f, "race {race_id}: {error}"),
+        }
+    }
+}
+
+/// Why a completion was not accepted.
+#[derive(Debug)]
+pub enum Rejection {
+    /// No usable prediction could be parsed; the request is simply re-sent.
+    Malformed,
+    /// The prediction contradicts the race card and could not be repaired;
+    /// the model is asked again with the errors listed.
+    Invalid { content: String, errors: Vec<ValidationError> },
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Malformed => write!(f, "Response failed validation"),
+            Self::Invalid { errors, .. } => write!(f, "Response contradicts the race card: {}", join(errors)),
+        }
+    }
+}
+
+/// Race cards the request was built from. Requests created before the
+/// cards were stored in the meta have none and skip semantic checks.
+pub fn request_cards(request: &HashMap<String, Value>) -> Vec<RaceCard> {
+    request
+        .get("meta")
+        .and_then(|m| m.get("races"))
+        .and_then(|r| serde_json::from_value(r.clone()).ok())
+        .unwrap_or_default()
+}
+
+/// Number of races packed into the request.
+pub fn request_race_count(request: &HashMap<String, Value>) -> usize {
+    request
+        .get("meta")
+        .and_then(|m| m.get("raceIds"))
+        .and_then(|ids| ids.as_array())
+        .map_or(1, Vec::len)
+}
+
+/// Matches every predicted race to the card it was sent with, repairs it
+/// and tags it with the race id. Every card must be answered exactly once.
+pub fn check_races(resps: &mut [PredictResponse], cards: &[RaceCard]) -> Vec<ValidationError> {
+    if cards.is_empty() {
+        return Vec::new();
+    }
+
+    let mut errors = Vec::new();
+    let mut answered = Vec::with_capacity(cards.len());
+    for resp in resps.iter_mut() {
+        let card = match resp.race_id {
+            Some(id) => match cards.iter().find(|c| c.race_id == id) {
+                Some(card) => card,
+                None => {
+                    errors.push(ValidationError::UnknownRace(id));
+                    continue;
+                }
+            },
+            None => match card_for(resp, cards) {
+                Some(card) => card,
+                None => continue,
+            },
+        };
+
+        if answered.contains(&card.race_id) {
+            errors.push(ValidationError::DuplicateRace(card.race_id));
+            continue;
+        }
+        answered.push(card.race_id);
+        resp.race_id = Some(card.race_id);
+
+        if card.runners.is_empty() {
+            continue;
+        }
+        let race_errors = repair(resp, card);
+        if cards.len() > 1 {
+            errors.extend(race_errors.into_iter().map(|error| ValidationError::InRace {
+                race_id: card.race_id,
+                error: Box::new(error)
+            }));
+        } else {
+            errors.extend(race_errors);
+        }
+    }
+
+    for card in cards {
+        if !answered.contains(&card.race_id) {
+            errors.push(ValidationError::MissingRace(card.race_id));
+        }
+    }
+
+    errors
+}
+
+/// Picks the card whose runners best match the predicted names.
+pub fn card_for<'a>(resp: &PredictResponse, cards: &'a [RaceCard]) -> Option<&'a RaceCard> {
+    cards.iter().max_by_key(|card| {
+        let overlap = resp
+            .predictions
+            .iter()
+            .filter(|p| card.runners.iter().any(|r| normalize(&r.name) == normalize(&p.name)))
+            .count();
+        (overlap, card.time == resp.meta.time)
+    })
+}
+
+/// Checks the response against the card without changing it.
+pub fn validate(resp: &PredictResponse, card: &RaceCard) -> Vec<ValidationError> {
+    let mut errors = Vec::new();
+
+    let mut seen = Vec::with_capacity(resp.predictions.len());
+    for p in &resp.predictions {
+        if !card.runners.iter().any(|r| r.name == p.name) {
+            errors.push(ValidationError::UnknownRunner(p.name.clone()));
+        } else if seen.contains(&p.name.as_str()) {
+            errors.push(ValidationError::DuplicateRunner(p.name.clone()));
+        }
+        seen.push(p.name.as_str());
+    }
+    for r in &card.runners {
+        if !seen.contains(&r.name.as_str()) {
+            errors.push(ValidationError::MissingRunner(r.name.clone()));
+        }
+    }
+
+    let mut ranks: Vec<u8> = resp.predictions.iter().map(|p| p.rank).collect();
+    ranks.sort_unstable();
+    if ranks.iter().enumerate().any(|(i, r)| *r as usize != i + 1) {
+        errors.push(ValidationError::InvalidRanks(resp.predictions.iter().map(|p| p.rank).collect()));
+    }
+
+    for p in &resp.predictions {
+        if !p.percentage.is_finite() || !(0.0..=100.0).contains(&p.percentage) {
+            errors.push(ValidationError::InvalidPercentage { name: p.name.clone(), value: p.percentage });
+        }
+    }
+    let sum: f32 = resp.predictions.iter().map(|p| p.percentage).sum();
+    if (sum - 100.0).abs() > PERCENTAGE_TOLERANCE {
+        errors.push(ValidationError::PercentageSum(sum));
+    }
+
+    let meta = &resp.meta;
+    let mut mismatch = |field, expected: String, actual: String| {
+        if expected != actual {
+            errors.push(ValidationError::MetaMismatch { field, expected, actual });
+        }
+    };
+    mismatch("date", card.date.to_string(), meta.date.to_string());
+    mismatch("time", card.time.to_string(), meta.time.to_string());
+    mismatch("distance", card.distance.to_string(), meta.distance.to_string());
+    if !card.track.is_empty() {
+        mismatch("track", card.track.clone(), meta.track.clone());
+    }
+
+    errors
+}
+
+/// Applies the deterministic repairs and returns what is still wrong.
+///
+/// Names are snapped to the closest runner when the match is unambiguous.
+/// Once every runner is accounted for, meta is taken from the card,
+/// percentages are rescaled to 100 and ranks re-derived from percentages.
+/// Missing, unknown or duplicate runners cannot be repaired.
+pub fn repair(resp: &mut PredictResponse, card: &RaceCard) -> Vec<ValidationError> {
+    let mut taken = vec![false; card.runners.len()];
+    let mut unmatched = Vec::new();
+    for (i, p) in resp.predictions.iter().enumerate() {
+        match card.runners.iter().position(|r| r.name == p.name) {
+            Some(idx) if !taken[idx] => taken[idx] = true,
+            _ => unmatched.push(i),
+        }
+    }
+
+    for i in unmatched {
+        let name = normalize(&resp.predictions[i].name);
+        let mut candidates: Vec<(usize, usize)> = card.runners
+            .iter()
+            .enumerate()
+            .filter(|(idx, _)| !taken[*idx])
+            .map(|(idx, r)| (levenshtein(&name, &normalize(&r.name)), idx))
+            .filter(|(dist, _)| *dist <= max_name_distance(&name))
+            .collect();
+        candidates.sort_unstable();
+
+        let unambiguous = match candidates.as_slice() {
+            [best] => Some(best.1),
+            [best, next, ..] if best.0 < next.0 => Some(best.1),
+            _ => None,
+        };
+        if let Some(idx) = unambiguous {
+            log::info!("Repaired runner name \"{}\" -> \"{}\"", resp.predictions[i].name, card.runners[idx].name);
+            resp.predictions[i].name = card.runners[idx].name.clone();
+            taken[idx] = true;
+        }
+    }
+
+    let errors = validate(resp, card);
+    let structural = errors.iter().any(|e| matches!(
+        e,
+        ValidationError::UnknownRunner(_)
+            | ValidationError::MissingRunner(_)
+            | ValidationError::DuplicateRunner(_)
+            | ValidationError::InvalidPercentage { .. }
+    ));
+    if structural {
+        return errors;
+    }
+
+    let meta = &mut resp.meta;
+    meta.date = card.date;
+    meta.time = card.time;
+    meta.distance = card.distance;
+    if !card.track.is_empty() {
+        meta.track = card.track.clone();
+    }
+
+    let sum: f32 = resp.predictions.iter().map(|p| p.percentage).sum();
+    if sum > 0.0 && (sum - 100.0).abs() > PERCENTAGE_TOLERANCE {
+        for p in resp.predictions.iter_mut() {
+            p.percentage = p.percentage * 100.0 / sum;
+        }
+    }
+
+    if errors.iter().any(|e| matches!(e, ValidationError::InvalidRanks(_))) {
+        let mut order: Vec<usize> = (0..resp.predictions.len()).collect();
+        order.sort_by(|a, b| {
+            let (a, b) = (&resp.predictions[*a], &resp.predictions[*b]);
+            b.percentage
+                .total_cmp(&a.percentage)
+                .then(b.raw_score.total_cmp(&a.raw_score))
+        });
+        for (rank, i) in order.into_iter().enumerate() {
+            resp.predictions[i].rank = rank as u8 + 1;
+        }
+    }
+
+    for e in &errors {
+        log::info!("Repaired: {e}");
+    }
+
+    validate(resp, card)
+}
+
+/// Builds the follow-up request: the original conversation, the rejected
+/// answer and a message listing what has to be fixed.
+pub fn feedback_request(
+    request: &HashMap<String, Value>,
+    content: &str,
+    errors: &[ValidationError]
+) -> HashMap<String, Value> {
+    let mut request = request.clone();
+
+    if let Some(Value::Array(messages)) = request.get_mut("messages") {
+        messages.push(json!({ "role": "assistant", "content": content }));
+        messages.push(json!({
+            "role": "user",
+            "content": format!(
+                "Your answer does not match the race card: {}. Answer again using exactly the runners, date, time, distance and track given.",
+                join(errors)
+            )
+        }));
+    }
+
+    request
+}
+
+fn join(errors: &[ValidationError]) -> String {
+    errors
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Edits tolerated when snapping a name: one per five characters, at least one.
+fn max_name_distance(name: &str) -> usize {
+    (name.chars().count() / 5).max(1)
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = usize::from(ca != *cb);
+            cur[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        prev = cur;
+    }
+
+    prev[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{
+        NaiveDate,
+        NaiveTime
+    };
+
+    use super::*;
+    use crate::models::{
+        Meta,
+        Prediction,
+        Runner
+    };
+
+    const NAMES: [&str; 4] = ["Swift Blaze", "Droopys Ace", "Ballymac Tom", "Kilara Lady"];
+
+    fn card(race_id: u64) -> RaceCard {
+        RaceCard {
+            race_id,
+            date: NaiveDate::from_ymd_opt(2025, 6, 2).unwrap(),
+            time: NaiveTime::from_hms_opt(14, 36, 0).unwrap(),
+            distance: 480,
+            track: "Romford".to_string(),
+            grade: Some("A3".to_string()),
+            runners: NAMES
+                .iter()
+                .enumerate()
+                .map(|(i, name)| Runner { name: name.to_string(), trap: Some(i as u8 + 1) })
+                .collect(),
+        }
+    }
+
+    fn response(race_id: Option<u64>, dogs: &[(&str, f32, u8)]) -> PredictResponse {
+        let card = card(race_id.unwrap_or(1));
+        PredictResponse {
+            race_id,
+            meta: Meta {
+                date: card.date,
+                time: card.time,
+                distance: card.distance,
+                track: card.track,
+                grade: card.grade,
+            },
+            predictions: dogs
+                .iter()
+                .map(|(name, percentage, rank)| Prediction {
+                    name: name.to_string(),
+                    raw_score: *percentage,
+                    percentage: *percentage,
+                    rank: *rank,
+                    comment: None,
+                    confidence: None,
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn levenshtein_counts_edits() {
+        assert_eq!(levenshtein("", "abc"), 3);
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+        assert_eq!(levenshtein("same", "same"), 0);
+    }
+
+    #[test]
+    fn valid_response_has_no_errors() {
+        let resp = response(Some(1), &[("Swift Blaze", 40.0, 1), ("Droopys Ace", 30.0, 2), ("Ballymac Tom", 20.0, 3), ("Kilara Lady", 10.0, 4)]);
+        assert!(validate(&resp, &card(1)).is_empty());
+    }
+
+    #[test]
+    fn repair_snaps_close_names() {
+        let mut resp = response(Some(1), &[("swift blaze", 40.0, 1), ("Droopy Ace", 30.0, 2), ("Ballymac Tom", 20.0, 3), ("Kilara Lady", 10.0, 4)]);
+
+        assert!(repair(&mut resp, &card(1)).is_empty());
+        let names: Vec<&str> = resp.predictions.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, NAMES);
+    }
+
+    #[test]
+    fn repair_leaves_distant_names() {
+        let mut resp = response(Some(1), &[("Swift Blaze", 40.0, 1), ("Some Other Dog", 30.0, 2), ("Ballymac Tom", 20.0, 3), ("Kilara Lady", 10.0, 4)]);
+
+        let errors = repair(&mut resp, &card(1));
+        assert!(errors.contains(&ValidationError::UnknownRunner("Some Other Dog".to_string())));
+        assert!(errors.contains(&ValidationError::MissingRunner("Droopys Ace".to_string())));
+    }
+
+    #[test]
+    fn repair_skips_ambiguous_names() {
+        let mut card = card(1);
+        card.runners[1].name = "Droopys Acf".to_string();
+        card.runners[2].name = "Droopys Acd".to_string();
+        let mut resp = response(Some(1), &[("Swift Blaze", 40.0, 1), ("Droopys Ace", 30.0, 2), ("Droopys Acx", 20.0, 3), ("Kilara Lady", 10.0, 4)]);
+
+        let errors = repair(&mut resp, &card);
+        assert!(errors.contains(&ValidationError::UnknownRunner("Droopys Ace".to_string())));
+    }
+
+    #[test]
+    fn repair_rescales_percentages_and_reranks() {
+        let mut resp = response(Some(1), &[("Swift Blaze", 20.0, 1), ("Droopys Ace", 40.0, 1), ("Ballymac Tom", 10.0, 3), ("Kilara Lady", 10.0, 4)]);
+        resp.meta.track = "romford".to_string();
+
+        assert!(repair(&mut resp, &card(1)).is_empty());
+        let sum: f32 = resp.predictions.iter().map(|p| p.percentage).sum();
+        assert!((sum - 100.0).abs() < 1e-3);
+        let ace = resp.predictions.iter().find(|p| p.name == "Droopys Ace").unwrap();
+        assert_eq!(ace.rank, 1);
+        assert_eq!(resp.meta.track, "Romford");
+    }
+
+    #[test]
+    fn check_races_matches_every_card_once() {
+        let cards = [card(1), card(2)];
+        let dogs = [("Swift Blaze", 40.0, 1), ("Droopys Ace", 30.0, 2), ("Ballymac Tom", 20.0, 3), ("Kilara Lady", 10.0, 4)];
+
+        let mut answered = vec![response(Some(1), &dogs), response(Some(1), &dogs), response(Some(7), &dogs)];
+        let errors = check_races(&mut answered, &cards);
+        assert_eq!(
+            errors,
+            vec![
+                ValidationError::DuplicateRace(1),
+                ValidationError::UnknownRace(7),
+                ValidationError::MissingRace(2),
+            ]
+        );
+
+        let mut untagged = vec![response(None, &dogs)];
+        check_races(&mut untagged, &[card(1)]);
+        assert_eq!(untagged[0].race_id, Some(1));
+    }
+}
@@ -0,0 +1,97 @@
+use std::{
+    collections::HashMap,
+    hash::Hash
+};
+
+use anyhow::Result;
+use chrono::{
+    Days,
+    NaiveDate,
+    TimeZone,
+    Utc
+};
+use mongodb::{
+    bson::{
+        doc,
+        DateTime,
+        Document
+    },
+    Collection
+};
+use serde::Serialize;
+
+/// Start of a day in UTC.
+pub fn day_start(date: NaiveDate) -> DateTime {
+    let start = date.and_hms_opt(0, 0, 0).expect("midnight exists");
+    DateTime::from_millis(Utc.from_utc_datetime(&start).timestamp_millis())
+}
+
+/// Filter on the `dog_race_info` records run from `from` to `to`, both
+/// days included.
+pub fn period(from: NaiveDate, to: NaiveDate) -> Document {
+    doc! { "raceDateTime": { "$gte": day_start(from), "$lt": day_start(to + Days::new(1)) } }
+}
+
+/// Stores figures computed over a window in place of the ones computed
+/// over the same window before. Other windows are kept: races before them
+/// still look their figures up there.
+pub async fn store<T>(collection: &Collection<T>, from: NaiveDate, to: NaiveDate, figures: &[T]) -> Result<()>
+where
+    T: Serialize + Send + Sync
+{
+    collection
+        .delete_many(doc! { "from": from.to_string(), "to": to.to_string() })
+        .await?;
+    if !figures.is_empty() {
+        collection.insert_many(figures).await?;
+    }
+
+    Ok(())
+}
+
+/// Figures by key, each computed over one or more windows, looked up as
+/// of a race date.
+#[derive(Debug)]
+pub struct AsOf<K, V> {
+    windows: HashMap<K, Vec<(NaiveDate, V)>>,
+}
+
+impl<K, V> Default for AsOf<K, V> {
+    fn default() -> Self {
+        Self { windows: HashMap::new() }
+    }
+}
+
+impl<K: Eq + Hash, V> AsOf<K, V> {
+    /// Table of (key, last day of the window, figure), given in the order
+    /// they were computed.
+    pub fn new(figures: impl IntoIterator<Item = (K, NaiveDate, V)>) -> Self {
+        let mut windows: HashMap<K, Vec<(NaiveDate, V)>> = HashMap::new();
+        for (key, to, figure) in figures {
+            windows.entry(key).or_default().push((to, figure));
+        }
+        // Stable, so of two windows ending the same day the later computed
+        // one wins.
+        for figures in windows.values_mut() {
+            figures.sort_by_key(|(to, _)| *to);
+        }
+
+        Self { windows }
+    }
+
+    /// Figure of the latest window that ended before `date`, so a race never
+    /// sees figures its own result went into.
+    pub fn get(&self, key: &K, date: NaiveDate) -> Option<&V> {
+        self.windows
+            .get(key)?
+            .iter()
+            .rev()
+            .find(|(to, _)| *to < date)
+            .map(|(_, figure)| figure)
+    }
+
+    /// Every figure, whatever its window.
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.windows.values().flatten().map(|(_, figure)| figure)
+    }
+}
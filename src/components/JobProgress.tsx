@@ -0,0 +1,107 @@
+import { useEffect, useState } from 'react';
+import { Box, Button, CircularProgress, LinearProgress, Typography } from '@mui/material';
+import { invoke } from '@tauri-apps/api/core';
+import { listen } from '@tauri-apps/api/event';
+import { JobProgressPayload } from '@/types';
+
+type Counts = {
+  scraping?: { done: number; total: number };
+  requests: number;
+  succeeded: number;
+  failed: number;
+  settled?: { done: number; total: number };
+  balance?: number;
+};
+
+const initialCounts: Counts = { requests: 0, succeeded: 0, failed: 0 };
+
+// Прогресс задачи с кнопкой отмены. Без overlay — полоса над уже
+// полученными результатами, чтобы с ними можно было работать.
+export const JobProgress: React.FC<{ jobId: string; overlay?: boolean }> = ({ jobId, overlay = true }) => {
+  const [counts, setCounts] = useState<Counts>(initialCounts);
+  const [cancelling, setCancelling] = useState(false);
+
+  useEffect(() => {
+    setCounts(initialCounts);
+    setCancelling(false);
+
+    const unlisten = listen<JobProgressPayload>('job-progress', ({ payload }) => {
+      if (payload.jobId !== jobId) return;
+      const e = payload.event;
+      setCounts(prev => {
+        switch (e.kind) {
+          case 'scraping': return { ...prev, scraping: { done: e.done, total: e.total } };
+          case 'requests': return { ...prev, requests: prev.requests + e.total };
+          case 'requestSucceeded': return { ...prev, succeeded: prev.succeeded + 1 };
+          case 'requestFailed': return { ...prev, failed: prev.failed + 1 };
+          case 'racesSettled': return { ...prev, settled: { done: e.done, total: e.total } };
+          case 'raceSettled': return { ...prev, balance: e.balance };
+          default: return prev;
+        }
+      });
+    });
+
+    return () => { unlisten.then(f => f()); };
+  }, [jobId]);
+
+  const handleCancel = async () => {
+    setCancelling(true);
+    try {
+      await invoke<boolean>('cancel_job', { jobId });
+    } catch (err) {
+      console.error('cancel_job error', err);
+    }
+  };
+
+  const finished = counts.succeeded + counts.failed;
+
+  return (
+    <Box
+      sx={overlay ? {
+        position: 'absolute',
+        inset: 0,
+        bgcolor: 'rgba(255,255,255,0.7)',
+        display: 'flex',
+        flexDirection: 'column',
+        alignItems: 'center',
+        justifyContent: 'center',
+        gap: 1,
+      } : {
+        display: 'flex',
+        alignItems: 'center',
+        gap: 2,
+        mb: 2,
+      }}
+    >
+      {overlay && <CircularProgress />}
+      <Box sx={{ width: 360 }}>
+        {counts.scraping && (
+          <Typography variant="body2">
+            Загрузка гонок: {counts.scraping.done} / {counts.scraping.total}
+          </Typography>
+        )}
+        {counts.requests > 0 && (
+          <>
+            <Typography variant="body2">
+              Запросы: {finished} / {counts.requests} (ошибок: {counts.failed})
+            </Typography>
+            <LinearProgress variant="determinate" value={(finished / counts.requests) * 100} />
+          </>
+        )}
+        {counts.settled && (
+          <Typography variant="body2">
+            Рассчитано гонок: {counts.settled.done} / {counts.settled.total}
+          </Typography>
+        )}
+        {counts.balance !== undefined && (
+          <Typography variant="body2">
+            Текущий баланс: {counts.balance}
+          </Typography>
+        )}
+      </Box>
+      <Button variant="outlined" color="error" onClick={handleCancel} disabled={cancelling}>
+        {cancelling ? 'Отмена…' : 'Отменить'}
+      </Button>
+    </Box>
+  );
+};
@@ -20,6 +20,7 @@ export const testResultsMock: TestResults = {
       skippedRacesGt6: 0,
       skippedOddsRange: 0,
       skippedFavorite: 0,
+      skippedLowConfidence: 0,
     },
     balance: {
       initialBalance: 1000,
@@ -17,11 +17,11 @@ import {
   Alert,
 } from '@mui/material';
 import { invoke } from '@tauri-apps/api/core';
-
-const modelOptions = ['o3-mini', 'o4-mini', 'o3'];
+import { CacheMode, ModelInfo, PromptFormat, SchemaLocale } from '@/types';
 
 const SettingsPage: React.FC = () => {
   const [model, setModel] = useState<string>('');
+  const [modelOptions, setModelOptions] = useState<ModelInfo[]>([]);
   const [frequencyPenalty, setFrequencyPenalty] = useState<number | null>(null);
   const [logprobs, setLogprobs] = useState<boolean | null>(null);
   const [maxCompletionTokens, setMaxCompletionTokens] = useState<number | null>(null);
@@ -32,6 +32,13 @@ const SettingsPage: React.FC = () => {
   const [temperature, setTemperature] = useState<number | null>(null);
   const [maxRaces, setMaxRaces] = useState<number>(0);
   const [racesPerRequest, setRacesPerRequest] = useState<number>(0);
+  const [cacheMode, setCacheMode] = useState<CacheMode>('read-through');
+  const [promptFormat, setPromptFormat] = useState<PromptFormat>('json');
+  const [schemaLocale, setSchemaLocale] = useState<SchemaLocale>('ru');
+  const [includeFeatures, setIncludeFeatures] = useState(false);
+  const [maxInFlight, setMaxInFlight] = useState<number | null>(null);
+  const [maxRetries, setMaxRetries] = useState<number | null>(null);
+  const [tokensPerMinute, setTokensPerMinute] = useState<number | null>(null);
 
   const [instruction, setInstruction] = useState<string>('');
   const [instructionOptions, setInstructionOptions] = useState<string[]>([]);
@@ -58,6 +65,13 @@ const SettingsPage: React.FC = () => {
           temperature: number | null;
           max_races: number;
           races_per_request: number;
+          max_in_flight: number | null;
+          max_retries: number | null;
+          tokens_per_minute: number | null;
+          cache_mode: CacheMode;
+          prompt_format: PromptFormat;
+          schema_locale: SchemaLocale;
+          include_features: boolean;
         }>('load_settings', {
           input: { model }
         });
@@ -73,6 +87,13 @@ const SettingsPage: React.FC = () => {
         setTemperature(settings.temperature);
         setMaxRaces(settings.max_races);
         setRacesPerRequest(settings.races_per_request);
+        setMaxInFlight(settings.max_in_flight);
+        setMaxRetries(settings.max_retries);
+        setTokensPerMinute(settings.tokens_per_minute);
+        setCacheMode(settings.cache_mode);
+        setPromptFormat(settings.prompt_format);
+        setSchemaLocale(settings.schema_locale);
+        setIncludeFeatures(settings.include_features);
       } catch (err) {
         console.error('load_settings error', err);
       }
@@ -81,6 +102,21 @@ const SettingsPage: React.FC = () => {
     if (model) loadSettings();
   }, [model]);
 
+  useEffect(() => {
+    const loadModels = async () => {
+      try {
+        const list = await invoke<ModelInfo[]>('load_models');
+        setModelOptions(list);
+      } catch (err) {
+        console.error('load_models error', err);
+        setModelOptions([]);
+      }
+    };
+    loadModels();
+  }, []);
+
+  const selectedModel = modelOptions.find(m => m.id === model);
+
   useEffect(() => {
     const loadInstructionNames = async () => {
       try {
@@ -130,6 +166,13 @@ const SettingsPage: React.FC = () => {
           temperature: temperature,
           max_races: maxRaces,
           races_per_request: racesPerRequest,
+          max_in_flight: maxInFlight,
+          max_retries: maxRetries,
+          tokens_per_minute: tokensPerMinute,
+          cache_mode: cacheMode,
+          prompt_format: promptFormat,
+          schema_locale: schemaLocale,
+          include_features: includeFeatures,
           instruction_name: instruction,
           selected: true
         }
@@ -173,7 +216,7 @@ const SettingsPage: React.FC = () => {
             error={Boolean(errors.model)}
           >
             {modelOptions.map(m => (
-              <MenuItem key={m} value={m}>{m}</MenuItem>
+              <MenuItem key={m.id} value={m.id}>{m.id}</MenuItem>
             ))}
           </Select>
         </FormControl>
@@ -190,12 +233,14 @@ const SettingsPage: React.FC = () => {
             htmlInput: { min: -2, max: 2, step: 0.1 }
           }}
           fullWidth
+          disabled={!selectedModel?.supports_penalties}
           error={Boolean(errors.frequencyPenalty)}
           helperText={errors.frequencyPenalty}
         />
         <FormControlLabel
           control={
             <Checkbox
+              disabled={!selectedModel?.supports_logprobs}
               checked={Boolean(logprobs)}
               onChange={e => setLogprobs(e.target.checked)}
             />
@@ -226,10 +271,11 @@ const SettingsPage: React.FC = () => {
             htmlInput: { min: -2, max: 2, step: 0.1 }
           }}
           fullWidth
+          disabled={!selectedModel?.supports_penalties}
           error={Boolean(errors.presencePenalty)}
           helperText={errors.presencePenalty}
         />
-        <FormControl fullWidth>
+        <FormControl fullWidth disabled={!selectedModel?.supports_reasoning_effort}>
           <InputLabel>reasoning_effort</InputLabel>
           <Select
             value={reasoningEffort ?? ''}
@@ -257,6 +303,7 @@ const SettingsPage: React.FC = () => {
             )
           }
           fullWidth
+          disabled={!selectedModel?.supports_seed}
         />
         <FormControlLabel
           control={
@@ -280,6 +327,7 @@ const SettingsPage: React.FC = () => {
             htmlInput: { min: 0, max: 2, step: 0.1 }
           }}
           fullWidth
+          disabled={!selectedModel?.supports_temperature}
           error={Boolean(errors.temperature)}
           helperText={errors.temperature}
         />
@@ -311,6 +359,90 @@ const SettingsPage: React.FC = () => {
             }
           }}
         />
+        <TextField
+          label="Одновременных запросов"
+          type="number"
+          value={maxInFlight ?? ''}
+          onChange={e =>
+            setMaxInFlight(
+              e.target.value === '' ? null : parseInt(e.target.value, 10)
+            )
+          }
+          fullWidth
+        />
+        <TextField
+          label="Повторов при ошибке"
+          type="number"
+          value={maxRetries ?? ''}
+          onChange={e =>
+            setMaxRetries(
+              e.target.value === '' ? null : parseInt(e.target.value, 10)
+            )
+          }
+          fullWidth
+        />
+        <TextField
+          label="Лимит токенов в минуту"
+          type="number"
+          value={tokensPerMinute ?? ''}
+          onChange={e =>
+            setTokensPerMinute(
+              e.target.value === '' ? null : parseInt(e.target.value, 10)
+            )
+          }
+          fullWidth
+        />
+        <FormControl fullWidth>
+          <InputLabel>Кэш ответов</InputLabel>
+          <Select
+            value={cacheMode}
+            label="Кэш ответов"
+            onChange={e => setCacheMode(e.target.value as CacheMode)}
+          >
+            {(['read-through', 'refresh', 'cache-only', 'disabled'] as CacheMode[]).map(mode => (
+              <MenuItem key={mode} value={mode}>
+                {mode}
+              </MenuItem>
+            ))}
+          </Select>
+        </FormControl>
+        <FormControl fullWidth>
+          <InputLabel>Формат запроса</InputLabel>
+          <Select
+            value={promptFormat}
+            label="Формат запроса"
+            onChange={e => setPromptFormat(e.target.value as PromptFormat)}
+          >
+            {(['json', 'table', 'csv'] as PromptFormat[]).map(format => (
+              <MenuItem key={format} value={format}>
+                {format}
+              </MenuItem>
+            ))}
+          </Select>
+        </FormControl>
+        <FormControl fullWidth>
+          <InputLabel>Язык схемы ответа</InputLabel>
+          <Select
+            value={schemaLocale}
+            label="Язык схемы ответа"
+            onChange={e => setSchemaLocale(e.target.value as SchemaLocale)}
+          >
+            {(['ru', 'en'] as SchemaLocale[]).map(locale => (
+              <MenuItem key={locale} value={locale}>
+                {locale}
+              </MenuItem>
+            ))}
+          </Select>
+        </FormControl>
+        <FormControlLabel
+          control={
+            <Checkbox
+              checked={includeFeatures}
+              onChange={e => setIncludeFeatures(e.target.checked)}
+            />
+          }
+          label="Добавлять признаки бегунов в запрос"
+        />
         <FormControl fullWidth>
           <InputLabel>Инструкция</InputLabel>
           <Select 
@@ -369,6 +501,7 @@ const SettingsPage: React.FC = () => {
             minRows={4}
             value={instructionText}
             onChange={e => setInstructionText(e.target.value)}
+            helperText="Переменные: {{track}}, {{distance}}, {{grade}}, {{runners}}, {{date}}, {{time}}, {{races}}. Название должно быть уникальным; изменения сохраняются новыми версиями"
           />
         </DialogContent>
         <DialogActions>
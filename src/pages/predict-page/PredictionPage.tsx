@@ -5,8 +5,10 @@ import { Box, CircularProgress, IconButton, Snackbar, Alert } from '@mui/materia
 import { InitialView } from './components/InitialView';
 import { ResultsView } from './components/ResultsView';
 import { CacheTabs } from '@/components/CacheTabs';
+import { JobProgress } from '@/components/JobProgress';
 import { invoke } from '@tauri-apps/api/core';
-import { Prediction, TimeRange } from '@/types';
+import { listen } from '@tauri-apps/api/event';
+import { JobProgressPayload, Prediction, PredictorBackend, PredictResults, PromptEstimate, TimeRange } from '@/types';
 import { DISATNCES, DOGS_TIMEZONE, MAX_DISTANCE, MIN_DISTANCE } from '@/utils/constants';
 
 type PredictInput = {
@@ -15,6 +17,7 @@ type PredictInput = {
         | { fixedTime: string }
         | { rangeTime: { startTime: string; endTime: string } };
     distances: number[];
+    backend?: PredictorBackend;
   };
 };
 
@@ -34,6 +37,7 @@ const copyText = async (payload: any) => {
 
 const PredictionPage = () => {
   const [isLoading, setIsLoading] = useState(false);
+  const [jobId, setJobId] = useState<string | null>(null);
   const [step, setStep] = useState(0);
   const [predictions, setPredictions] = useState<Prediction[]>([]);
   const [predictionsCache, setPredictionsCache] = useState<Record<string, Prediction[]>>({});
@@ -45,6 +49,7 @@ const PredictionPage = () => {
   const [minDistance, setMinDistance] = useState<number>(MIN_DISTANCE);
   const [maxDistance, setMaxDistance] = useState<number>(MAX_DISTANCE);
   const [distances, setDistances] = useState<number[]>(DISATNCES);
+  const [backend, setBackend] = useState<PredictorBackend>('llm');
 
   const [errors, setErrors] = useState<{
     fixedTime?: boolean;
@@ -62,6 +67,23 @@ const PredictionPage = () => {
   // Snackbar под копирование (виден на обеих шагах)
   const [copyStatus, setCopyStatus] = useState<'success'|'error'|null>(null);
   const [copyMessage, setCopyMessage] = useState<string>('');
+  const [estimates, setEstimates] = useState<PromptEstimate[]>([]);
+
+  // Гонки показываются по мере готовности, не дожидаясь всего запуска
+  useEffect(() => {
+    if (!jobId) return;
+
+    const unlisten = listen<JobProgressPayload>('job-progress', ({ payload }) => {
+      if (payload.jobId !== jobId || payload.event.kind !== 'prediction') return;
+      const prediction = payload.event.prediction;
+      setPredictions(prev =>
+        [...prev, prediction].sort((a, b) => a.meta.time.localeCompare(b.meta.time))
+      );
+      setStep(1);
+    });
+
+    return () => { unlisten.then(f => f()); };
+  }, [jobId]);
 
   useEffect(() => {
     if (distanceMode === 'all') {
@@ -165,14 +187,18 @@ const PredictionPage = () => {
             },
           };
 
-      const payload: PredictInput = { input: { time, distances } };
+      const payload: PredictInput = { input: { time, distances, backend } };
 
       // Сохраняем фильтры для кнопок копирования (для второй страницы)
       setCopyInput(payload);
 
-      const preds = await invoke<Prediction[]>('run_predict', payload);
+      const id = crypto.randomUUID();
+      setPredictions([]);
+      setJobId(id);
+      const results = await invoke<PredictResults>('run_predict', { ...payload, jobId: id });
+      console.info(`run_predict cost: $${results.usage.cost.toFixed(4)}`);
 
-      setPredictions(preds);
+      setPredictions(results.predictions);
       setAlertStatus('success');
       setStep(1);
     } catch (error) {
@@ -180,6 +206,7 @@ const PredictionPage = () => {
       console.error('run_predict error', error);
     } finally {
       setIsLoading(false);
+      setJobId(null);
     }
   };
 
@@ -200,6 +227,17 @@ const PredictionPage = () => {
     }
   };
 
+  const handleEstimatePrompt = async () => {
+    try {
+      if (!derivedCopyInput) throw new Error('Нет активных фильтров');
+      const list = await invoke<PromptEstimate[]>('estimate_predict_prompt', derivedCopyInput);
+      setEstimates(list);
+    } catch (err) {
+      console.error('estimate_predict_prompt error', err);
+      setEstimates([]);
+    }
+  };
+
   const handleTabSelect = async (range: TimeRange) => {
     const key = `${range.startTime}-${range.endTime ?? ''}`;
 
@@ -283,11 +321,15 @@ const PredictionPage = () => {
                 setMinDistance={setMinDistance}
                 setMaxDistance={setMaxDistance}
                 handleSelectChange={handleSelectChange}
+                backend={backend}
+                handleBackend={setBackend}
                 onSubmit={handleSubmit}
                 errors={errors}
                 // ВАЖНО: теперь кнопка активируется, когда derivedCopyInput валиден
                 copyInput={derivedCopyInput}
                 onCopyPredictRequest={handleCopyPredictRequest}
+                estimates={estimates}
+                onEstimatePrompt={handleEstimatePrompt}
             />
         )}
 
@@ -299,7 +341,9 @@ const PredictionPage = () => {
             />
         )}
 
-        {isLoading && (
+        {jobId && <JobProgress jobId={jobId} overlay={predictions.length === 0} />}
+
+        {isLoading && !jobId && (
             <Box
                 sx={{
                   position: 'absolute',
@@ -6,6 +6,9 @@ import {
   FormControlLabel,
   FormControl,
   FormLabel,
+  MenuItem,
+  TextField,
+  Typography,
 } from '@mui/material'
 import { SelectChangeEvent } from '@mui/material/Select'
 import { Dayjs } from 'dayjs'
@@ -14,6 +17,8 @@ import { AdapterDayjs } from '@mui/x-date-pickers/AdapterDayjs'
 import { TimeRangePicker } from '@/components/TimeRangePicker'
 import { DistanceControl } from '@/components/DistanceControl'
 import React from "react";
+import { PredictorBackend, PromptEstimate } from '@/types'
+import { PREDICTOR_BACKENDS } from '@/utils/constants'
 
 interface Props {
   timeMode: 'fixed' | 'range'
@@ -30,6 +35,8 @@ interface Props {
   setMinDistance: (n: number) => void
   setMaxDistance: (n: number) => void
   handleSelectChange: (e: SelectChangeEvent<string[]>) => void
+  backend: PredictorBackend
+  handleBackend: (v: PredictorBackend) => void
   onSubmit: (e: React.FormEvent) => void
   errors: {
     fixedTime?: boolean
@@ -41,6 +48,8 @@ interface Props {
   }
   copyInput: PredictInput | null,
   onCopyPredictRequest: () => Promise<void>
+  estimates: PromptEstimate[]
+  onEstimatePrompt: () => Promise<void>
 }
 
 type PredictInput = {
@@ -49,6 +58,7 @@ type PredictInput = {
         | { fixedTime: string }
         | { rangeTime: { startTime: string; endTime: string } };
     distances: number[];
+    backend?: PredictorBackend;
   };
 };
 
@@ -67,10 +77,14 @@ export const InitialView: React.FC<Props> = ({
   setMinDistance,
   setMaxDistance,
   handleSelectChange,
+  backend,
+  handleBackend,
   onSubmit,
   errors,
   copyInput,
-  onCopyPredictRequest
+  onCopyPredictRequest,
+  estimates,
+  onEstimatePrompt
 }) => (
   <form
     onSubmit={onSubmit}
@@ -130,6 +144,19 @@ export const InitialView: React.FC<Props> = ({
     </Box>
 
     <Box sx={{ display: 'flex', gap: 1 }}>
+      <TextField
+          select
+          size="small"
+          label="Предиктор"
+          value={backend}
+          onChange={e => handleBackend(e.target.value as PredictorBackend)}
+          sx={{ minWidth: 180 }}
+      >
+        {PREDICTOR_BACKENDS.filter(b => !b.backtestOnly).map(b => (
+          <MenuItem key={b.value} value={b.value}>{b.label}</MenuItem>
+        ))}
+      </TextField>
+
       <Button
           variant="outlined"
           size="small"
@@ -139,9 +166,28 @@ export const InitialView: React.FC<Props> = ({
         Копировать запрос
       </Button>
 
+      <Button
+          variant="outlined"
+          size="small"
+          onClick={onEstimatePrompt}
+          disabled={!copyInput}
+      >
+        Оценить токены
+      </Button>
+
       <Button variant="contained" type="submit" sx={{ alignSelf: 'center', mb: 2 }}>
         Предсказать
       </Button>
     </Box>
+
+    {estimates.length > 0 && (
+      <Box sx={{ display: 'flex', gap: 2, mb: 2 }}>
+        {estimates.map(e => (
+          <Typography key={e.format} variant="body2">
+            {e.format}: ~{e.tokens} токенов, ${e.cost.toFixed(4)} ({e.requests} запр.)
+          </Typography>
+        ))}
+      </Box>
+    )}
   </form>
 )
@@ -10,11 +10,13 @@ import { InitialView } from './components/InitialView'
 import { DISATNCES, DOGS_TIMEZONE, MAX_DISTANCE, MIN_DISTANCE } from '@/utils/constants'
 import ResultsView from './components/ResultsView'
 import { invoke } from '@tauri-apps/api/core'
-import { TestResults } from '@/types'
+import { PredictorBackend, PromptEstimate, StakingPlan, StrategyKind, TestResults } from '@/types'
+import { JobProgress } from '@/components/JobProgress'
 
 const TestingPage = () => {
   const [step, setStep] = useState(0)
   const [isLoading, setIsLoading] = useState(false)
+  const [jobId, setJobId] = useState<string | null>(null)
 
   const [timeMode, setTimeMode] = useState<'fixed' | 'range'>('fixed')
   const [fixedTime, setFixedTime] = useState<Dayjs | null>(dayjs().tz(DOGS_TIMEZONE))
@@ -30,11 +32,18 @@ const TestingPage = () => {
   const [initialBalance, setInitialBalance] = useState<number | ''>('')
   const [oddsMin, setOddsMin] = useState<number | ''>('')
   const [oddsMax, setOddsMax] = useState<number | ''>('')
+  const [minConfidence, setMinConfidence] = useState<number | ''>('')
+  const [backend, setBackend] = useState<PredictorBackend>('llm')
+  const [strategyKind, setStrategyKind] = useState<StrategyKind['kind']>('lay-bottom-two')
+  const [strategyParam, setStrategyParam] = useState<number | ''>('')
+  const [stakingKind, setStakingKind] = useState<StakingPlan['kind']>('fixed-stake')
+  const [stakingParam, setStakingParam] = useState<number | ''>('')
 
   const [errors, setErrors] = useState<Record<string,string>>({})
   const [runStatus, setRunStatus] = useState<'success'|'error'|null>(null)
 
   const [testResults, setTestResults] = useState<TestResults>()
+  const [estimates, setEstimates] = useState<PromptEstimate[]>([])
 
   useEffect(() => {
     if (distanceMode === 'all') {
@@ -59,10 +68,16 @@ const TestingPage = () => {
     setIsLoading(true)
 
     try {
-      const dateTime = timeMode === "fixed" 
-        ? { fixedDateTime: fixedTime!.format('YYYY-MM-DDTHH:mm:ss') }
-        : { rangeDateTime: { startDateTime: rangeTime[0]!.format('YYYY-MM-DDTHH:mm:ss'), endDateTime: rangeTime[1]!.format('YYYY-MM-DDTHH:mm:ss') } };
+      const dateTime = testDateTime();
       const oddsRange = { low: oddsMin, high: oddsMax };
+      const strategy: StrategyKind =
+        strategyKind === 'lay-rank' || strategyKind === 'back-rank' ? { kind: strategyKind, rank: Number(strategyParam) }
+        : strategyKind === 'lay-below' ? { kind: strategyKind, percentage: Number(strategyParam) }
+        : { kind: strategyKind };
+      const staking: StakingPlan =
+        stakingKind === 'percent-of-bank' ? { kind: stakingKind, percent: Number(stakingParam) }
+        : stakingKind === 'kelly' ? { kind: stakingKind, fraction: Number(stakingParam) }
+        : { kind: stakingKind };
 
       const payload = {
         dateTime,
@@ -70,9 +85,15 @@ const TestingPage = () => {
         initialStake,
         initialBalance,
         isFavoriteProtected,
-        oddsRange
+        oddsRange,
+        minConfidence: minConfidence === '' ? null : minConfidence,
+        backend,
+        strategy,
+        staking
       };
-      const results = await invoke<TestResults>('run_test', payload);
+      const id = crypto.randomUUID();
+      setJobId(id);
+      const results = await invoke<TestResults>('run_test', { ...payload, jobId: id });
       // const results = testResultsMock;
 
       setTestResults(results);
@@ -83,6 +104,21 @@ const TestingPage = () => {
       console.error("run_test error", error);
     } finally {
       setIsLoading(false);
+      setJobId(null);
+    }
+  }
+
+  const testDateTime = () => timeMode === "fixed"
+    ? { fixedDateTime: fixedTime!.format('YYYY-MM-DDTHH:mm:ss') }
+    : { rangeDateTime: { startDateTime: rangeTime[0]!.format('YYYY-MM-DDTHH:mm:ss'), endDateTime: rangeTime[1]!.format('YYYY-MM-DDTHH:mm:ss') } };
+
+  const handleEstimatePrompt = async () => {
+    try {
+      const list = await invoke<PromptEstimate[]>('estimate_test_prompt', { dateTime: testDateTime(), distances });
+      setEstimates(list);
+    } catch (err) {
+      console.error('estimate_test_prompt error', err);
+      setEstimates([]);
     }
   }
 
@@ -97,6 +133,14 @@ const TestingPage = () => {
     
     if (!oddsMax || oddsMax <= 0) e.oddsMax = 'Должно быть > 0'
 
+    if (minConfidence !== '' && (minConfidence < 0 || minConfidence > 1)) e.minConfidence = 'От 0 до 1'
+
+    if ((strategyKind === 'lay-rank' || strategyKind === 'back-rank') && (strategyParam === '' || strategyParam < 1 || strategyParam > 6)) e.strategyParam = 'От 1 до 6'
+    if (strategyKind === 'lay-below' && (strategyParam === '' || strategyParam <= 0 || strategyParam > 100)) e.strategyParam = 'От 0 до 100'
+
+    if (stakingKind === 'percent-of-bank' && (stakingParam === '' || stakingParam <= 0 || stakingParam > 100)) e.stakingParam = 'От 0 до 100'
+    if (stakingKind === 'kelly' && (stakingParam === '' || stakingParam <= 0 || stakingParam > 1)) e.stakingParam = 'От 0 до 1'
+
     if (oddsMin && oddsMax && oddsMin > oddsMax) {
       e.oddsMin = 'Не больше max'
       e.oddsMax = 'Не меньше min'
@@ -129,7 +173,14 @@ const TestingPage = () => {
           favoriteProtection={isFavoriteProtected}
           oddsMin={oddsMin}
           oddsMax={oddsMax}
+          minConfidence={minConfidence}
+          backend={backend}
+          strategyKind={strategyKind}
+          strategyParam={strategyParam}
+          stakingKind={stakingKind}
+          stakingParam={stakingParam}
           runStatus={runStatus}
+          estimates={estimates}
           handleTimeMode={setTimeMode}
           setFixedTime={setFixedTime}
           setRangeTime={setRangeTime}
@@ -142,12 +193,21 @@ const TestingPage = () => {
           handleFavoriteProtection={setIsFavoriteProtected}
           handleOddsMin={setOddsMin}
           handleOddsMax={setOddsMax}
+          handleMinConfidence={setMinConfidence}
+          handleBackend={setBackend}
+          handleStrategyKind={setStrategyKind}
+          handleStrategyParam={setStrategyParam}
+          handleStakingKind={setStakingKind}
+          handleStakingParam={setStakingParam}
           handleRunStatus={setRunStatus}
           onSubmit={handleSubmit}
+          onEstimatePrompt={handleEstimatePrompt}
         />
       }
 
-      {isLoading && (
+      {jobId && <JobProgress jobId={jobId} />}
+
+      {isLoading && !jobId && (
         <Box
           sx={{
             position: 'absolute',
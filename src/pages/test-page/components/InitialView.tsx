@@ -9,12 +9,16 @@ import {
 	Switch,
 	Snackbar,
 	Alert,
+	MenuItem,
+	Typography,
 } from '@mui/material';
 import { Dayjs } from 'dayjs';
 import { DateTimePicker, LocalizationProvider } from '@mui/x-date-pickers';
 import { AdapterDayjs } from '@mui/x-date-pickers/AdapterDayjs';
 import { DateTimeRangePicker } from '@/components/DateTimeRangePicker';
 import { DistanceControl } from '@/components/DistanceControl';
+import { PredictorBackend, PromptEstimate, StakingPlan, StrategyKind } from '@/types';
+import { PREDICTOR_BACKENDS, STAKING_PLANS, STRATEGIES } from '@/utils/constants';
 
 const ITEM_HEIGHT = 48;
 const ITEM_PADDING_TOP = 8;
@@ -41,7 +45,14 @@ interface Props {
 	favoriteProtection: boolean;
 	oddsMin: number | string;
 	oddsMax: number | string;
+	minConfidence: number | "";
+	backend: PredictorBackend;
+	strategyKind: StrategyKind['kind'];
+	strategyParam: number | "";
+	stakingKind: StakingPlan['kind'];
+	stakingParam: number | "";
 	runStatus: "success" | "error" | null;
+	estimates: PromptEstimate[];
   handleTimeMode: (v: 'fixed' | 'range') => void;
   setFixedTime: (v: Dayjs | null) => void;
   setRangeTime: (v: [Dayjs | null, Dayjs | null]) => void;
@@ -55,8 +66,15 @@ interface Props {
 	handleFavoriteProtection: (v: boolean) => void;
 	handleOddsMin: (v: number | "") => void;
 	handleOddsMax: (v: number | "") => void;
+	handleMinConfidence: (v: number | "") => void;
+	handleBackend: (v: PredictorBackend) => void;
+	handleStrategyKind: (v: StrategyKind['kind']) => void;
+	handleStrategyParam: (v: number | "") => void;
+	handleStakingKind: (v: StakingPlan['kind']) => void;
+	handleStakingParam: (v: number | "") => void;
 	handleRunStatus: (v: "success" | "error" | null) => void;
 	onSubmit: (e: React.FormEvent) => void;
+	onEstimatePrompt: () => Promise<void>;
 }
 
 export const InitialView: React.FC<Props> = ({
@@ -73,7 +91,14 @@ export const InitialView: React.FC<Props> = ({
 	favoriteProtection,
 	oddsMin,
 	oddsMax,
+	minConfidence,
+	backend,
+	strategyKind,
+	strategyParam,
+	stakingKind,
+	stakingParam,
 	runStatus,
+	estimates,
 	handleTimeMode,
   setFixedTime,
   setRangeTime,
@@ -86,9 +111,16 @@ export const InitialView: React.FC<Props> = ({
 	handleFavoriteProtection,
 	handleOddsMin,
 	handleOddsMax,
+	handleMinConfidence,
+	handleBackend,
+	handleStrategyKind,
+	handleStrategyParam,
+	handleStakingKind,
+	handleStakingParam,
 	handleRunStatus,
 	// handleErrors,
   onSubmit,
+  onEstimatePrompt,
 }) => (
     <form
 			onSubmit={onSubmit}
@@ -183,8 +215,91 @@ export const InitialView: React.FC<Props> = ({
 					error={Boolean(errors.oddsMax)}
 					helperText={errors.oddsMax}
 			/>
+
+			<TextField
+					label="Минимальная уверенность (0-1)"
+					type="number"
+					value={minConfidence}
+					onChange={e => handleMinConfidence(e.target.value === '' ? '' : +e.target.value)}
+					error={Boolean(errors.minConfidence)}
+					helperText={errors.minConfidence ?? 'Требует logprobs в настройках'}
+			/>
+
+			<TextField
+					select
+					label="Предиктор"
+					value={backend}
+					onChange={e => handleBackend(e.target.value as PredictorBackend)}
+			>
+				{PREDICTOR_BACKENDS.map(b => (
+					<MenuItem key={b.value} value={b.value}>{b.label}</MenuItem>
+				))}
+			</TextField>
+
+			<TextField
+					select
+					label="Стратегия"
+					value={strategyKind}
+					onChange={e => handleStrategyKind(e.target.value as StrategyKind['kind'])}
+			>
+				{STRATEGIES.map(s => (
+					<MenuItem key={s.value} value={s.value}>{s.label}</MenuItem>
+				))}
+			</TextField>
+
+			{STRATEGIES.find(s => s.value === strategyKind)?.param && (
+				<TextField
+						label={STRATEGIES.find(s => s.value === strategyKind)?.param}
+						type="number"
+						value={strategyParam}
+						onChange={e => handleStrategyParam(e.target.value === '' ? '' : +e.target.value)}
+						error={Boolean(errors.strategyParam)}
+						helperText={errors.strategyParam}
+				/>
+			)}
+
+			<TextField
+					select
+					label="Размер ставки"
+					value={stakingKind}
+					onChange={e => handleStakingKind(e.target.value as StakingPlan['kind'])}
+			>
+				{STAKING_PLANS.map(s => (
+					<MenuItem key={s.value} value={s.value}>{s.label}</MenuItem>
+				))}
+			</TextField>
+
+			{STAKING_PLANS.find(s => s.value === stakingKind)?.param && (
+				<TextField
+						label={STAKING_PLANS.find(s => s.value === stakingKind)?.param}
+						type="number"
+						value={stakingParam}
+						onChange={e => handleStakingParam(e.target.value === '' ? '' : +e.target.value)}
+						error={Boolean(errors.stakingParam)}
+						helperText={errors.stakingParam}
+				/>
+			)}
 			</Box>
 
+			<Button
+			variant="outlined"
+			size="small"
+			onClick={onEstimatePrompt}
+			sx={{ alignSelf: 'center' }}
+			>
+				Оценить токены
+			</Button>
+
+			{estimates.length > 0 && (
+				<Box sx={{ display: 'flex', gap: 2, alignSelf: 'center' }}>
+					{estimates.map(e => (
+						<Typography key={e.format} variant="body2">
+							{e.format}: ~{e.tokens} токенов, ${e.cost.toFixed(4)} ({e.requests} запр.)
+						</Typography>
+					))}
+				</Box>
+			)}
+
 			<Button
 			type="submit"
 			variant="contained"
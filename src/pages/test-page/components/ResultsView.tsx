@@ -96,11 +96,31 @@ const ResultsView: React.FC<Props> = ({ data }) => {
     { title: 'Race Count', items: { 'Total Races': raceCount.totalRaces, 'Tracked Races': raceCount.racesTracked } },
     { title: 'Odds Range', items: { Low: oddsRange.low, High: oddsRange.high } },
     { title: 'Position Info', items: { 'Bad Hit 4 Pos': positionInfo.badHit4Pos, 'Bad Hit 5 Pos': positionInfo.badHit5Pos, 'Bad Hit 6 Pos': positionInfo.badHit6Pos } },
-    { title: 'Skip Info', items: { 'Skipped Races <5': skipInfo.skippedRacesLt5, 'Skipped Races >6': skipInfo.skippedRacesGt6, 'Skipped Odds Range': skipInfo.skippedOddsRange, 'Skipped Favorite': skipInfo.skippedFavorite } },
-    { title: 'Balance', items: { 'Initial Balance': balance.initialBalance, 'Final Balance': balance.finalBalance } },
+    { title: 'Skip Info', items: { 'Skipped Races <5': skipInfo.skippedRacesLt5, 'Skipped Races >6': skipInfo.skippedRacesGt6, 'Skipped Odds Range': skipInfo.skippedOddsRange, 'Skipped Favorite': skipInfo.skippedFavorite, 'Skipped Low Confidence': skipInfo.skippedLowConfidence, 'Skipped No Confidence': skipInfo.skippedNoConfidence } },
+    { title: 'Balance', items: { 'Initial Balance': balance.initialBalance, 'Final Balance': balance.finalBalance, 'Stopped At Race': data.meta.stoppedAt ?? '-' } },
     { title: 'Errors', items: { 'Empty Content Errors': errors.totalEmptyContent, 'MongoDB Errors': errors.totalMongoDbError, 'Race Parse Errors': errors.totalRaceParseError } },
     { title: 'Initial Stake', items: { 'Stake Amount': initialStake } },
     { title: 'Profit Percentage', items: { 'Profit %': `${percentage}%` } },
+    { title: 'Usage', items: { 'Prompt Tokens': data.usage.promptTokens, 'Completion Tokens': data.usage.completionTokens, 'Reasoning Tokens': data.usage.reasoningTokens, 'Cost $': data.usage.cost.toFixed(4), 'Failed Requests': data.failedRequests.length } },
+    ...(data.meta.stakingPlans ?? []).map(({ plan, trackedRaces, finalBalance, percentage: planPercentage, stopped }) => ({
+      title: `Staking: ${plan.kind}`,
+      items: {
+        'Tracked Races': trackedRaces,
+        'Final Balance': finalBalance,
+        'Profit %': `${planPercentage}%`,
+        'vs chosen plan': `${(planPercentage - percentage).toFixed(2)}%`,
+        Stopped: stopped ? 'yes' : 'no',
+      },
+    })),
+    ...(data.baselines ?? []).map(({ backend, meta }) => ({
+      title: `Baseline: ${backend}`,
+      items: {
+        'Tracked Races': meta.raceCount.racesTracked,
+        'Final Balance': meta.balance.finalBalance,
+        'Profit %': `${meta.percentage}%`,
+        'vs model': `${(percentage - meta.percentage).toFixed(2)}%`,
+      },
+    })),
   ];
 
   const firstColumn = ['Race Count', 'Balance', 'Initial Stake', 'Profit Percentage'];
@@ -269,6 +289,16 @@ const ResultsView: React.FC<Props> = ({ data }) => {
                       <TableCell style={{ paddingBottom: 0, paddingTop: 0 }} colSpan={9}>
                         <Collapse in={expandedRow === idx} timeout="auto" unmountOnExit>
                           <Box sx={{ display: 'flex', flexDirection: 'column', gap: 2, p: 2 }}>
+                            {race.bets && race.bets.length > 0 && (
+                              <Paper sx={{ p: 2 }}>
+                                <Typography variant="subtitle1" gutterBottom>Ставки</Typography>
+                                {race.bets.map(bet => (
+                                  <Typography key={`${bet.side}-${bet.dogName}`} variant="body2">
+                                    {bet.side === 'back' ? 'Бэк' : 'Лэй'} {bet.dogName} @ {bet.odds}: ставка {bet.stake}, обязательство {bet.liability}, P&L {bet.profit}
+                                  </Typography>
+                                ))}
+                              </Paper>
+                            )}
                             {race.dogs.sort((a, b) => a.modelPrediction.rank - b.modelPrediction.rank).map(dog => (
                               <Paper key={dog.dogName} sx={{ p: 2 }}>
                                 <Typography variant="subtitle1" gutterBottom>
@@ -25,6 +25,8 @@ export interface SkipInfo {
   skippedRacesGt6: number;
   skippedOddsRange: number;
   skippedFavorite: number;
+  skippedLowConfidence: number;
+  skippedNoConfidence: number;
 }
 
 export interface Balance {
@@ -41,6 +43,23 @@ export interface TestResultsMeta {
   errors: TestErrors;
   initialStake: number;
   percentage: number;
+  staking: StakingPlan;
+  stoppedAt?: number;
+  stakingPlans?: StakingResults[];
+}
+
+export type StakingPlan =
+  | { kind: 'fixed-stake' }
+  | { kind: 'fixed-liability' }
+  | { kind: 'percent-of-bank'; percent: number }
+  | { kind: 'kelly'; fraction: number };
+
+export interface StakingResults {
+  plan: StakingPlan;
+  trackedRaces: number;
+  finalBalance: number;
+  percentage: number;
+  stopped: boolean;
 }
 
 export interface TestResultsRaceMeta {
@@ -59,6 +78,7 @@ export interface TestResultsRealResults {
 }
 
 export interface Prediction {
+  raceId?: number;
   meta: {
     time: string;
     distance: number;
@@ -73,6 +93,51 @@ export interface Prediction {
     comment: string;
   }[];
   summary: string;
+  usage?: TokenUsage;
+  ensemble?: EnsembleInfo;
+  confidence?: number;
+  instruction?: InstructionRef;
+}
+
+export interface InstructionRef {
+  name: string;
+  version: number;
+}
+
+export interface InstructionDoc {
+  name: string;
+  content: string;
+  version: number;
+  author: string | null;
+  created_at: { $date: { $numberLong: string } } | null;
+}
+
+export type DiffKind = 'same' | 'removed' | 'added' | 'changed';
+
+export interface DiffRow {
+  kind: DiffKind;
+  left: string | null;
+  right: string | null;
+}
+
+export interface ImportSummary {
+  created: string[];
+  updated: string[];
+  unchanged: string[];
+}
+
+export type EnsembleMethod = 'mean-probability' | 'borda' | 'median-rank';
+
+export interface EnsembleOptions {
+  samples: number;
+  models: string[];
+  method: EnsembleMethod;
+}
+
+export interface EnsembleInfo {
+  method: EnsembleMethod;
+  members: number;
+  agreement: number;
 }
 
 export interface PredictionResults {
@@ -81,6 +146,7 @@ export interface PredictionResults {
   percentage: number;
   rank: number;
   comment: string;
+  confidence?: number;
 }
 
 export interface TestResultsDog {
@@ -94,15 +160,195 @@ export interface TestResultsRace {
   meta: TestResultsRaceMeta;
   dogs: TestResultsDog[];
   summary: string;
+  bets?: SettledBet[];
+}
+
+export type BetSide = 'back' | 'lay';
+
+export interface SettledBet {
+  dogName: string;
+  side: BetSide;
+  odds: number;
+  stake: number;
+  liability: number;
+  profit: number;
+}
+
+export interface TokenUsage {
+  promptTokens: number;
+  completionTokens: number;
+  reasoningTokens: number;
+  cost: number;
 }
 
 export interface TestResults {
   meta: TestResultsMeta;
   races: TestResultsRace[];
   requests: Record<string, any>[];
+  usage: TokenUsage;
+  failedRequests: FailedRequest[];
+  instruction: InstructionRef | null;
+  baselines?: BaselineResults[];
+}
+
+export interface BaselineResults {
+  backend: PredictorBackend;
+  meta: TestResultsMeta;
+}
+
+export interface FailedRequest {
+  index: number;
+  raceIds: number[];
+  attempts: number;
+  error: string;
+}
+
+export interface PredictResults {
+  predictions: Prediction[];
+  usage: TokenUsage;
+  failed: FailedRequest[];
 }
 
 export interface TimeRange {
   startTime: string;
   endTime: string | null;
-}
\ No newline at end of file
+}
+
+export interface ModelInfo {
+  id: string;
+  supports_temperature: boolean;
+  supports_penalties: boolean;
+  supports_logprobs: boolean;
+  supports_reasoning_effort: boolean;
+  supports_seed: boolean;
+  context_window: number;
+  input_price: number;
+  output_price: number;
+}
+
+export type CacheMode = 'read-through' | 'refresh' | 'cache-only' | 'disabled';
+
+export type PromptFormat = 'json' | 'table' | 'csv';
+
+export type SchemaLocale = 'en' | 'ru';
+
+export type PredictorBackend = 'llm' | 'speed-rating' | 'market' | 'logit';
+
+export type StrategyKind =
+  | { kind: 'lay-bottom-two' }
+  | { kind: 'lay-rank'; rank: number }
+  | { kind: 'lay-below'; percentage: number }
+  | { kind: 'back-rank'; rank: number };
+
+export type ProgressEvent =
+  | { kind: 'scraping'; done: number; total: number }
+  | { kind: 'requests'; total: number }
+  | { kind: 'requestSent'; index: number; attempt: number }
+  | { kind: 'requestSucceeded'; index: number; races: number }
+  | { kind: 'requestFailed'; index: number; error: string }
+  | { kind: 'prediction'; prediction: Prediction }
+  | { kind: 'racesSettled'; done: number; total: number }
+  | { kind: 'raceSettled'; race: TestResultsRace; balance: number }
+  | { kind: 'cancelled' };
+
+export interface JobProgressPayload {
+  jobId: string;
+  event: ProgressEvent;
+}
+
+export interface PromptEstimate {
+  format: PromptFormat;
+  requests: number;
+  tokens: number;
+  cost: number;
+}
+
+export interface CalibrationBin {
+  from: number;
+  to: number;
+  runners: number;
+  predicted: number;
+  observed: number;
+}
+
+export interface LogitMetrics {
+  races: number;
+  logLoss: number;
+  uniformLogLoss: number;
+  brier: number;
+  topPickAccuracy: number;
+  calibration: CalibrationBin[];
+}
+
+export interface DateWindow {
+  from: string;
+  to: string;
+}
+
+export interface LogitModel {
+  version: number;
+  features: string[];
+  means: number[];
+  scales: number[];
+  weights: number[];
+  temperature: number;
+  trainedFrom: string;
+  trainedTo: string;
+  standardWindows: DateWindow[];
+  train: LogitMetrics;
+  holdout: LogitMetrics | null;
+}
+
+export interface TrapStats {
+  trap: number;
+  runs: number;
+  wins: number;
+  places: number;
+  winRate: number;
+  winInterval: [number, number];
+  placeRate: number;
+  placeInterval: [number, number];
+  averagePosition: number;
+  positionInterval: [number, number];
+}
+
+export interface TrapBias {
+  track: string;
+  distance: number;
+  grade: string | null;
+  from: string;
+  to: string;
+  races: number;
+  traps: TrapStats[];
+}
+
+export interface StandardTime {
+  track: string;
+  distance: number;
+  from: string;
+  to: string;
+  races: number;
+  standard: number;
+  deviation: number;
+}
+
+export interface SpeedFigure {
+  dogId: number;
+  dogName: string;
+  raceId: number;
+  raceDateTime: { $date: { $numberLong: string } };
+  track: string;
+  distance: number;
+  figure: number;
+  standard: { from: string; to: string };
+}
+
+export interface DogRating {
+  dogId: number;
+  dogName: string;
+  raceId: number;
+  raceDateTime: { $date: { $numberLong: string } };
+  rating: number;
+  deviation: number;
+  races: number;
+}
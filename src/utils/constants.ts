@@ -1,3 +1,5 @@
+import { PredictorBackend, StakingPlan, StrategyKind } from '@/types';
+
 export const DOGS_TIMEZONE = 'Europe/London';
 export const MIN_DISTANCE = 209;
 export const MAX_DISTANCE = 750;
@@ -43,4 +45,27 @@ export const DISATNCES = [
   730, 731,
   740,
   750,
-]
\ No newline at end of file
+]
+
+export const PREDICTOR_BACKENDS: { value: PredictorBackend; label: string; backtestOnly?: boolean }[] = [
+  { value: 'llm', label: 'LLM' },
+  { value: 'speed-rating', label: 'Рейтинг скорости' },
+  { value: 'market', label: 'Рынок (Betfair)', backtestOnly: true },
+  { value: 'logit', label: 'Логит-модель' },
+];
+
+/** `param` names the number a strategy needs, if any. */
+export const STRATEGIES: { value: StrategyKind['kind']; label: string; param?: string }[] = [
+  { value: 'lay-bottom-two', label: 'Лэй дешевейшей из двух последних' },
+  { value: 'lay-rank', label: 'Лэй собаки с рангом', param: 'Ранг' },
+  { value: 'lay-below', label: 'Лэй всех ниже процента', param: 'Процент' },
+  { value: 'back-rank', label: 'Бэк собаки с рангом', param: 'Ранг' },
+];
+
+/** `param` names the number a staking plan needs, if any. */
+export const STAKING_PLANS: { value: StakingPlan['kind']; label: string; param?: string }[] = [
+  { value: 'fixed-stake', label: 'Фиксированная ставка' },
+  { value: 'fixed-liability', label: 'Фиксированная ответственность' },
+  { value: 'percent-of-bank', label: 'Процент от банка', param: 'Процент банка' },
+  { value: 'kelly', label: 'Доля Келли', param: 'Доля (0-1)' },
+];